@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+
+use crate::{
+    config::{config, AlertOp, AlertRule},
+    history::now_ms,
+    models::{AlertEvent, AlertState, Data},
+    rates::Rates,
+    tree::ProcessTree
+};
+
+/// What alert rules can look at. Rates are per second.
+pub const METRICS: [&str; 27] = [
+    "cpu.usage",            // Percent per cpu
+    "cpu.total",            // Percent, all cpus together
+    "memory.used_ratio",    // 0 to 1
+    "memory.available_bytes",
+    "swap.used_ratio",
+    "disk.available_bytes", // Per mount
+    "disk.used_ratio",
+    "disk.inodes_free",
+    "disk.inodes_used_ratio",
+    "disk.read_rate",
+    "disk.write_rate",
+    "net.rx_rate",          // Bytes per interface
+    "net.tx_rate",
+    "net.rx_errors_rate",
+    "net.tx_errors_rate",
+    "net.up",               // 1 while an interface is up, 0 otherwise
+    "process.tree_cpu",         // Percent, a process and its descendants per process name
+    "process.tree_memory_bytes",
+    "component.temperature",    // Degrees Celsius per sensor
+    "pressure.some_avg10",      // Percent of the last 10s stalled, per resource
+    "pressure.full_avg10",
+    "cgroup.cpu",               // Percent of one cpu, per cgroup path
+    "cgroup.memory_used_ratio", // Of memory.max, cgroups with a limit only
+    "unit.failed",              // 1 while a systemd unit is failed, 0 otherwise
+    "unit.restarts",            // Automatic restarts so far, services only
+    "block.busy",               // Percent of the time a block device had requests in flight
+    "block.await_ms"            // Average per request, reads and writes together
+];
+
+type Series = (&'static str, String);
+type Values = (&'static [&'static str], Vec<(Series, f64)>); // The metrics carried, then each series' value
+
+struct Active{
+    state: AlertState, // Pending or Firing
+    since_ms: u64
+}
+
+impl Active{
+    /// The state it goes to when its series or rule goes away, only a firing alert resolves.
+    fn ended(&self) -> AlertState{
+        match self.state{
+            AlertState::Firing => AlertState::Resolved,
+            _ => AlertState::Cancelled
+        }
+    }
+}
+
+/// Checks the alert rules against the data going out and says which alerts changed state.
+#[derive(Default)]
+pub struct Engine{
+    active: HashMap<(String, Series), Active>, // By rule name and series
+    rates: Rates<Series>
+}
+
+impl Engine{
+    /// The alerts that changed state because of `data`.
+    pub fn observe(&mut self, data: &Data) -> Vec<AlertEvent>{
+        self.check(&config().alerts, data, now_ms())
+    }
+
+    fn check(&mut self, rules: &[AlertRule], data: &Data, t_ms: u64) -> Vec<AlertEvent>{
+        let mut events = Vec::new();
+
+        // Rules that were removed by a reload, or now look at another metric, end whatever they had going.
+        let gone: Vec<_> = self.active.keys()
+            .filter(|(rule, (metric, _))| !rules.iter().any(|r| &r.name == rule && r.metric == *metric))
+            .cloned().collect();
+        for (rule, (metric, label)) in gone{
+            if let Some(active) = self.active.remove(&(rule.clone(), (metric, label.clone()))){
+                events.push(AlertEvent{
+                    rule, metric: metric.to_string(), label, state: active.ended(),
+                    value: f64::NAN, threshold: f64::NAN, since_ms: active.since_ms, t_ms
+                });
+            }
+        }
+
+        let Some((metrics, values)) = self.values(data, t_ms) else { return events; };
+        let labels = labels(data);
+        // Counters of series this data no longer has, e.g. of a cgroup that went away, are forgotten.
+        self.rates.retain(|(metric, label)| !metrics.contains(metric) || labels.contains(label));
+        for rule in rules{
+            if !metrics.contains(&rule.metric.as_str()){
+                continue;
+            }
+            let series = values.iter().filter(|((metric, label), _)|
+                *metric == rule.metric && rule.label.as_ref().is_none_or(|l| l == label)
+            );
+            for (key, value) in series{
+                if let Some(event) = self.step(rule, key, *value, t_ms){
+                    events.push(event);
+                }
+            }
+            // Series this data no longer has, e.g. an unmounted disk, resolve.
+            let missing: Vec<_> = self.active.keys()
+                .filter(|(name, (metric, label))| *name == rule.name && *metric == rule.metric && !labels.contains(label))
+                .cloned().collect();
+            for key in missing{
+                if let Some(active) = self.active.remove(&key){
+                    events.push(event(rule, &key.1, active.ended(), f64::NAN, active.since_ms, t_ms));
+                }
+            }
+        }
+        events
+    }
+
+    /// Move the alert of `rule` on `series` along with a new value.
+    fn step(&mut self, rule: &AlertRule, series: &Series, value: f64, t_ms: u64) -> Option<AlertEvent>{
+        let key = (rule.name.clone(), series.clone());
+        let over = |limit: f64| match rule.op{
+            AlertOp::Above => value > limit,
+            AlertOp::Below => value < limit
+        };
+        match self.active.get_mut(&key){
+            None if over(rule.threshold) => {
+                let state = if rule.for_s == 0 { AlertState::Firing } else { AlertState::Pending };
+                self.active.insert(key, Active { state, since_ms: t_ms });
+                Some(event(rule, series, state, value, t_ms, t_ms))
+            }
+            None => None,
+            Some(active) if active.state == AlertState::Pending => {
+                if !over(rule.threshold){
+                    let since_ms = active.since_ms;
+                    self.active.remove(&key);
+                    Some(event(rule, series, AlertState::Cancelled, value, since_ms, t_ms))
+                }
+                else if t_ms >= active.since_ms + rule.for_s * 1000{
+                    active.state = AlertState::Firing;
+                    Some(event(rule, series, AlertState::Firing, value, active.since_ms, t_ms))
+                }
+                else{
+                    None
+                }
+            }
+            // Firing stays firing until the value is past the clear level, not just the threshold.
+            Some(active) => {
+                if over(rule.clear.unwrap_or(rule.threshold)){
+                    return None;
+                }
+                let since_ms = active.since_ms;
+                self.active.remove(&key);
+                Some(event(rule, series, AlertState::Resolved, value, since_ms, t_ms))
+            }
+        }
+    }
+
+    /// The metrics `data` carries and their values per series, `None` if it has none.
+    /// A rate is missing the first time its counter is seen.
+    fn values(&mut self, data: &Data, t_ms: u64) -> Option<Values>{
+        let mut out = Vec::new();
+        let metrics: &'static [&'static str] = match data{
+            Data::Cpus(cpus) => {
+                for cpu in cpus{
+                    out.push((("cpu.usage", cpu.cpu_name.clone()), cpu.cpu_per as f64));
+                }
+                if !cpus.is_empty(){
+                    let total = cpus.iter().map(|c| c.cpu_per as f64).sum::<f64>() / cpus.len() as f64;
+                    out.push((("cpu.total", String::new()), total));
+                }
+                &["cpu.usage", "cpu.total"]
+            }
+            Data::Memory(mem) => {
+                if mem.t_ram > 0{
+                    out.push((("memory.used_ratio", String::new()), mem.u_ram as f64 / mem.t_ram as f64));
+                }
+                out.push((("memory.available_bytes", String::new()), mem.a_ram as f64));
+                if mem.t_swap > 0{
+                    out.push((("swap.used_ratio", String::new()), mem.u_swap as f64 / mem.t_swap as f64));
+                }
+                &["memory.used_ratio", "memory.available_bytes", "swap.used_ratio"]
+            }
+            Data::Disk(disks) => {
+                for disk in disks{
+                    out.push((("disk.available_bytes", disk.loc.clone()), disk.a_space as f64));
+                    if disk.t_space > 0{
+                        out.push((("disk.used_ratio", disk.loc.clone()), 1.0 - disk.a_space as f64 / disk.t_space as f64));
+                    }
+                    if let (Some(total), Some(free)) = (disk.t_inodes.filter(|t| *t > 0), disk.a_inodes){
+                        out.push((("disk.inodes_free", disk.loc.clone()), free as f64));
+                        out.push((("disk.inodes_used_ratio", disk.loc.clone()), 1.0 - free as f64 / total as f64));
+                    }
+                    self.rate(&mut out, ("disk.read_rate", disk.loc.clone()), t_ms, disk.t_read);
+                    self.rate(&mut out, ("disk.write_rate", disk.loc.clone()), t_ms, disk.t_written);
+                }
+                &["disk.available_bytes", "disk.used_ratio", "disk.inodes_free", "disk.inodes_used_ratio", "disk.read_rate", "disk.write_rate"]
+            }
+            Data::Networks(nets) => {
+                for net in nets{
+                    self.rate(&mut out, ("net.rx_rate", net.name.clone()), t_ms, net.t_down);
+                    self.rate(&mut out, ("net.tx_rate", net.name.clone()), t_ms, net.t_up);
+                    self.rate(&mut out, ("net.rx_errors_rate", net.name.clone()), t_ms, net.t_err_rx);
+                    self.rate(&mut out, ("net.tx_errors_rate", net.name.clone()), t_ms, net.t_err_tx);
+                }
+                &["net.rx_rate", "net.tx_rate", "net.rx_errors_rate", "net.tx_errors_rate"]
+            }
+            Data::Interfaces(interfaces) => {
+                for i in interfaces{
+                    out.push((("net.up", i.name.clone()), if i.up() { 1.0 } else { 0.0 }));
+                }
+                &["net.up"]
+            }
+            Data::Process(procs) => {
+                // Per name, the subtrees of the topmost processes so nested ones are not counted twice.
+                let tree = ProcessTree::build(procs);
+                let mut totals: HashMap<&str, (f64, f64)> = HashMap::new();
+                for node in tree.nodes(){
+                    let name = procs[node.idx].name.as_str();
+                    if !tree.ancestors(node).any(|a| procs[a.idx].name == name){
+                        let total = totals.entry(name).or_default();
+                        total.0 += node.cpu as f64;
+                        total.1 += node.mem as f64;
+                    }
+                }
+                for (name, (cpu, mem)) in totals{
+                    out.push((("process.tree_cpu", name.to_string()), cpu));
+                    out.push((("process.tree_memory_bytes", name.to_string()), mem));
+                }
+                &["process.tree_cpu", "process.tree_memory_bytes"]
+            }
+            Data::Components(components) => {
+                for c in components{
+                    if let Some(t) = c.temperature{
+                        out.push((("component.temperature", c.label.clone()), t as f64));
+                    }
+                }
+                &["component.temperature"]
+            }
+            Data::Pressure(pressure) => {
+                for p in pressure{
+                    let resource = p.resource.name().to_string();
+                    out.push((("pressure.some_avg10", resource.clone()), p.some.avg10 as f64));
+                    if let Some(full) = &p.full{
+                        out.push((("pressure.full_avg10", resource), full.avg10 as f64));
+                    }
+                }
+                &["pressure.some_avg10", "pressure.full_avg10"]
+            }
+            Data::Cgroups(cgroups) => {
+                for c in cgroups{
+                    let n = out.len();
+                    self.rate(&mut out, ("cgroup.cpu", c.path.clone()), t_ms, c.cpu_usage);
+                    if let Some((_, cpu)) = out.get_mut(n){
+                        *cpu /= 1e4; // Microseconds per second to percent
+                    }
+                    if let (Some(mem), Some(max)) = (c.mem, c.mem_max.filter(|m| *m > 0)){
+                        out.push((("cgroup.memory_used_ratio", c.path.clone()), mem as f64 / max as f64));
+                    }
+                }
+                &["cgroup.cpu", "cgroup.memory_used_ratio"]
+            }
+            Data::Units(units) => {
+                for u in units{
+                    out.push((("unit.failed", u.name.clone()), if u.failed() { 1.0 } else { 0.0 }));
+                    if let Some(restarts) = u.restarts{
+                        out.push((("unit.restarts", u.name.clone()), restarts as f64));
+                    }
+                }
+                &["unit.failed", "unit.restarts"]
+            }
+            Data::BlockDevices(devices) => {
+                for d in devices{
+                    out.push((("block.busy", d.name.clone()), d.busy as f64));
+                    let ios = d.reads + d.writes;
+                    if ios > 0.0{
+                        out.push((("block.await_ms", d.name.clone()), (d.r_await * d.reads + d.w_await * d.writes) / ios));
+                    }
+                }
+                &["block.busy", "block.await_ms"]
+            }
+            _ => return None
+        };
+        Some((metrics, out))
+    }
+
+    fn rate(&mut self, out: &mut Vec<(Series, f64)>, series: Series, t_ms: u64, total: u64){
+        if let Some(rate) = self.rates.rate(series.clone(), t_ms, total){
+            out.push((series, rate));
+        }
+    }
+}
+
+/// The labels `data` has series for, whether or not each had a value this time.
+fn labels(data: &Data) -> Vec<String>{
+    match data{
+        Data::Cpus(cpus) => cpus.iter().map(|c| c.cpu_name.clone()).chain([String::new()]).collect(),
+        Data::Disk(disks) => disks.iter().map(|d| d.loc.clone()).collect(),
+        Data::Networks(nets) => nets.iter().map(|n| n.name.clone()).collect(),
+        Data::Interfaces(interfaces) => interfaces.iter().map(|i| i.name.clone()).collect(),
+        Data::Process(procs) => procs.iter().filter(|p| !p.thread).map(|p| p.name.clone()).collect(),
+        Data::Components(components) => components.iter().map(|c| c.label.clone()).collect(),
+        Data::Pressure(pressure) => pressure.iter().map(|p| p.resource.name().to_string()).collect(),
+        Data::Cgroups(cgroups) => cgroups.iter().map(|c| c.path.clone()).collect(),
+        Data::Units(units) => units.iter().map(|u| u.name.clone()).collect(),
+        Data::BlockDevices(devices) => devices.iter().map(|d| d.name.clone()).collect(),
+        _ => vec![String::new()]
+    }
+}
+
+fn event(rule: &AlertRule, (metric, label): &Series, state: AlertState, value: f64, since_ms: u64, t_ms: u64) -> AlertEvent{
+    AlertEvent{
+        rule: rule.name.clone(),
+        metric: metric.to_string(),
+        label: label.clone(),
+        state,
+        value,
+        threshold: rule.threshold,
+        since_ms,
+        t_ms
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::models::{Cpus, Networks};
+
+    fn rule(metric: &str, for_s: u64) -> AlertRule{
+        AlertRule { name: "hot".to_string(), metric: metric.to_string(), label: None, op: AlertOp::Above, threshold: 90.0, for_s, clear: None }
+    }
+
+    fn cpus(usage: f32) -> Data{
+        usages(&[("cpu0", usage)])
+    }
+
+    fn usages(cpus: &[(&str, f32)]) -> Data{
+        Data::Cpus(cpus.iter().map(|(name, usage)| Cpus { brand: String::new(), cpu_name: name.to_string(), cpu_per: *usage, freq: 0 }).collect())
+    }
+
+    fn received(nets: &[(&str, u64)]) -> Data{
+        Data::Networks(nets.iter().map(|(name, total)| Networks{
+            name: name.to_string(), t_down: *total, down: 0, t_up: 0, up: 0, t_packet_rx: 0, packet_rx: 0, t_packet_tx: 0, packet_tx: 0,
+            t_err_rx: 0, err_rx: 0, t_err_tx: 0, err_tx: 0
+        }).collect())
+    }
+
+    fn states(events: &[AlertEvent]) -> Vec<(&str, AlertState)>{
+        events.iter().map(|e| (e.metric.as_str(), e.state)).collect()
+    }
+
+    #[test]
+    fn pending_that_never_fired_is_cancelled(){
+        let mut engine = Engine::default();
+        let rules = [rule("cpu.usage", 10)];
+        assert_eq!(states(&engine.check(&rules, &cpus(95.0), 0)), [("cpu.usage", AlertState::Pending)]);
+        assert_eq!(states(&engine.check(&rules, &cpus(50.0), 5_000)), [("cpu.usage", AlertState::Cancelled)]);
+
+        // Once it fired, dropping back resolves it.
+        engine.check(&rules, &cpus(95.0), 10_000);
+        assert_eq!(states(&engine.check(&rules, &cpus(95.0), 20_000)), [("cpu.usage", AlertState::Firing)]);
+        assert_eq!(states(&engine.check(&rules, &cpus(50.0), 25_000)), [("cpu.usage", AlertState::Resolved)]);
+    }
+
+    #[test]
+    fn rule_moved_to_another_metric_ends_its_alerts(){
+        let mut engine = Engine::default();
+        assert_eq!(states(&engine.check(&[rule("cpu.usage", 0)], &cpus(95.0), 0)), [("cpu.usage", AlertState::Firing)]);
+
+        // A reload keeps the name but points the rule at the total.
+        let events = engine.check(&[rule("cpu.total", 0)], &cpus(95.0), 1_000);
+        assert_eq!(states(&events), [("cpu.usage", AlertState::Resolved), ("cpu.total", AlertState::Firing)]);
+        assert!(events[0].value.is_nan());
+    }
+
+    #[test]
+    fn fires_once_for_s_passed(){
+        let mut engine = Engine::default();
+        let rules = [rule("cpu.usage", 10)];
+        assert_eq!(states(&engine.check(&rules, &cpus(95.0), 0)), [("cpu.usage", AlertState::Pending)]);
+        assert!(engine.check(&rules, &cpus(99.0), 9_999).is_empty());
+        let events = engine.check(&rules, &cpus(95.0), 10_000);
+        assert_eq!(states(&events), [("cpu.usage", AlertState::Firing)]);
+        assert_eq!(events[0].since_ms, 0);
+        assert!(engine.check(&rules, &cpus(95.0), 20_000).is_empty());
+    }
+
+    #[test]
+    fn resolves_past_clear_only(){
+        let mut engine = Engine::default();
+        let rules = [AlertRule { clear: Some(80.0), ..rule("cpu.usage", 0) }];
+        assert_eq!(states(&engine.check(&rules, &cpus(95.0), 0)), [("cpu.usage", AlertState::Firing)]);
+        // Under the threshold but not the clear level, it keeps firing.
+        assert!(engine.check(&rules, &cpus(85.0), 1_000).is_empty());
+        assert_eq!(states(&engine.check(&rules, &cpus(75.0), 2_000)), [("cpu.usage", AlertState::Resolved)]);
+        // And it takes the threshold, not the clear level, to fire again.
+        assert!(engine.check(&rules, &cpus(85.0), 3_000).is_empty());
+    }
+
+    #[test]
+    fn label_filter(){
+        let mut engine = Engine::default();
+        let rules = [AlertRule { label: Some("cpu1".to_string()), ..rule("cpu.usage", 0) }];
+        let events = engine.check(&rules, &usages(&[("cpu0", 95.0), ("cpu1", 95.0)]), 0);
+        assert_eq!(events.iter().map(|e| (e.label.as_str(), e.state)).collect::<Vec<_>>(), [("cpu1", AlertState::Firing)]);
+
+        // The series going away resolves it.
+        let events = engine.check(&rules, &usages(&[("cpu0", 95.0)]), 1_000);
+        assert_eq!(events.iter().map(|e| (e.label.as_str(), e.state)).collect::<Vec<_>>(), [("cpu1", AlertState::Resolved)]);
+        assert!(events[0].value.is_nan());
+    }
+
+    #[test]
+    fn rate_rules(){
+        let mut engine = Engine::default();
+        let rules = [AlertRule { threshold: 500.0, ..rule("net.rx_rate", 0) }];
+        assert!(engine.check(&rules, &received(&[("eth0", 0)]), 0).is_empty()); // No rate from one counter
+        assert!(engine.check(&rules, &received(&[("eth0", 400)]), 1_000).is_empty());
+        let events = engine.check(&rules, &received(&[("eth0", 2_400)]), 2_000);
+        assert_eq!(states(&events), [("net.rx_rate", AlertState::Firing)]);
+        assert_eq!(events[0].value, 2_000.0);
+
+        // A counter that went back is a reset, not a rate, and what came before is forgotten with the interface.
+        let mut engine = Engine::default();
+        engine.check(&rules, &received(&[("eth0", 0), ("eth1", 0)]), 0);
+        engine.check(&rules, &received(&[("eth1", 0)]), 1_000);
+        assert!(engine.check(&rules, &received(&[("eth0", 10_000), ("eth1", 0)]), 2_000).is_empty());
+        assert!(engine.check(&rules, &received(&[("eth0", 5), ("eth1", 0)]), 3_000).is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+use anyhow::{anyhow, Error};
+use iceoryx2::{node::Node, pending_response::PendingResponse, port::client::Client, service::ipc};
+
+use crate::{config::config, models::{ControlAction, ControlRequest, ControlResponse, ControlStatus}, state::AppState};
+
+use super::service;
+
+pub type ControlClient = Client<ipc::Service, ControlRequest, (), ControlResponse, ()>;
+pub type PendingControl = PendingResponse<ipc::Service, ControlRequest, (), ControlResponse, ()>;
+
+/// Open the agent's control service as a client.
+pub fn connect(node: &Node<ipc::Service>) -> Result<ControlClient, Error>{
+    service::connect(node, &config().transport.control_service)
+}
+
+pub fn parse_state(name: &str) -> Option<AppState>{
+    match name.to_lowercase().as_str(){
+        "meta" => Some(AppState::Meta),
+        "cpu" | "cpus" => Some(AppState::Cpu),
+        "disk" | "disks" => Some(AppState::Disk),
+        "proc" | "process" | "processes" => Some(AppState::Processes),
+        "net" | "network" | "networks" => Some(AppState::Network),
+        "socket" | "sockets" => Some(AppState::Sockets),
+        "mem" | "memory" => Some(AppState::Memory),
+        _ => None
+    }
+}
+
+/// Entry point for `agent ctl status|focus <STATE>|shutdown`.
+pub fn main(args: &[String]) -> Result<(), Error>{
+    let (action, state) = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice(){
+        ["status"] => (ControlAction::Status, None),
+        ["shutdown"] => (ControlAction::Shutdown, None),
+        ["focus", name] => match parse_state(name){
+            Some(state) => (ControlAction::Focus, Some(state)),
+            None => return Err(anyhow!("Unknown state '{}', expected one of: meta, cpu, disk, processes, network, sockets, memory", name))
+        },
+        _ => return Err(anyhow!("Usage: agent ctl status | focus <STATE> | shutdown"))
+    };
+
+    let node = iceoryx2::node::NodeBuilder::new()
+        .name(&"AwareCtl".try_into()?)
+        .create::<ipc::Service>()?;
+    let client = connect(&node)?;
+
+    let res = service::request(&node, &client, ControlRequest::new(action, state), "Control")?;
+    let state = res.state().map_or(format!("an unknown state {}", res.state), |s| format!("{:?}", s));
+    match res.status(){
+        Some(ControlStatus::Ok) => println!("{}", state),
+        Some(ControlStatus::Rejected) => return Err(anyhow!("The agent rejected {:?}, it is in {}", action, state)),
+        None => return Err(anyhow!("The agent answered with an unknown status {}", res.status))
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use anyhow::{anyhow, Error};
+use iceoryx2::{node::Node, port::client::Client, service::ipc};
+
+use crate::{config::config, history::now_ms, models::{HistoryMetric, HistoryPoint, HistoryRequest, HistoryResponse, HistoryStatus}};
+
+use super::{reciever::human_bytes, service};
+
+pub type HistoryClient = Client<ipc::Service, HistoryRequest, (), HistoryResponse, ()>;
+
+/// Open the agent's history service as a client.
+pub fn connect(node: &Node<ipc::Service>) -> Result<HistoryClient, Error>{
+    service::connect(node, &config().transport.history_service)
+}
+
+/// Every series of `metric` since `from_ms`, as (label, points), a page at a time.
+pub fn fetch(node: &Node<ipc::Service>, client: &HistoryClient, metric: HistoryMetric, from_ms: u64) -> Result<Vec<(String, Vec<HistoryPoint>)>, Error>{
+    let mut all = Vec::new();
+    let mut series = 0;
+    loop{
+        let mut req = HistoryRequest::new(metric, series, from_ms, 0);
+        let mut label = None;
+        let mut points = Vec::new();
+        loop{
+            let res = service::request(node, client, req, "History")?;
+            match res.status(){
+                Some(HistoryStatus::Ok) => {}
+                Some(HistoryStatus::NoSuchSeries) => return Ok(all), // Past the last one
+                Some(HistoryStatus::Disabled) => return Err(anyhow!("History is disabled on the agent")),
+                Some(HistoryStatus::InvalidRequest) => return Err(anyhow!("The agent does not know the metric {:?}", metric)),
+                None => return Err(anyhow!("The agent answered with an unknown status {}", res.status))
+            }
+            label.get_or_insert_with(|| res.label().to_string());
+            points.extend_from_slice(res.points());
+            match points.last(){
+                Some(last) if res.more() => req.from_ms = last.t_ms + 1,
+                _ => break
+            }
+        }
+        all.push((label.unwrap_or_default(), points));
+        series += 1;
+    }
+}
+
+pub fn format_value(metric: HistoryMetric, value: f64) -> String{
+    match metric{
+        HistoryMetric::CpuUsage => format!("{:.1}%", value),
+        HistoryMetric::MemoryUsed | HistoryMetric::SwapUsed => human_bytes(value as u64),
+        _ => format!("{}/s", human_bytes(value as u64))
+    }
+}
+
+pub fn parse_metric(name: &str) -> Option<HistoryMetric>{
+    match name.to_lowercase().as_str(){
+        "cpu" => Some(HistoryMetric::CpuUsage),
+        "mem" | "memory" => Some(HistoryMetric::MemoryUsed),
+        "swap" => Some(HistoryMetric::SwapUsed),
+        "down" | "net-down" => Some(HistoryMetric::NetDown),
+        "up" | "net-up" => Some(HistoryMetric::NetUp),
+        "read" | "disk-read" => Some(HistoryMetric::DiskRead),
+        "written" | "disk-written" => Some(HistoryMetric::DiskWritten),
+        _ => None
+    }
+}
+
+/// Entry point for `agent history <METRIC> [LABEL] [--last SECONDS]`.
+pub fn main(args: &[String]) -> Result<(), Error>{
+    let usage = "Usage: agent history cpu|memory|swap|down|up|read|written [LABEL] [--last SECONDS]";
+    let mut metric = None;
+    let mut label = None;
+    let mut last_s = 60;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next(){
+        match arg.as_str(){
+            "--last" => last_s = iter.next().and_then(|s| s.parse().ok()).ok_or(anyhow!(usage))?,
+            name if metric.is_none() => metric = Some(parse_metric(name).ok_or(anyhow!("Unknown metric '{}'. {}", name, usage))?),
+            name if label.is_none() => label = Some(name.to_string()),
+            _ => return Err(anyhow!(usage))
+        }
+    }
+    let metric = metric.ok_or(anyhow!(usage))?;
+
+    let node = iceoryx2::node::NodeBuilder::new()
+        .name(&"AwareHistory".try_into()?)
+        .create::<ipc::Service>()?;
+    let client = connect(&node)?;
+
+    let now = now_ms();
+    let series = fetch(&node, &client, metric, now.saturating_sub(last_s * 1000))?;
+    for (name, points) in series.iter().filter(|(name, _)| label.as_ref().is_none_or(|l| l == name)){
+        println!("== {:?} {} ({} points) ==", metric, name, points.len());
+        for p in points{
+            println!("  {:>8.1}s ago  {}", now.saturating_sub(p.t_ms) as f64 / 1000.0, format_value(metric, p.value));
+        }
+    }
+    Ok(())
+}
@@ -1,6 +1,15 @@
-use anyhow::Error;
+use anyhow::{anyhow, Error};
 
-pub async fn main() -> Result<(), Error>{
-    let stdin = std::io::stdin();
-    
-}
\ No newline at end of file
+use super::reciever;
+
+/// Entry point for `agent cli [KIND..]`, prints what the agent publishes.
+pub async fn main(args: &[String]) -> Result<(), Error>{
+    let mut kinds = Vec::new();
+    for arg in args{
+        match reciever::parse_kind(arg){
+            Some(kind) => kinds.push(kind),
+            None => return Err(anyhow!("Unknown kind '{}', expected one of: meta, cpu, memory, disk, network, sockets, processes, alerts, lifecycle, sensors, pressure, cgroups, units, blockio, interfaces", arg))
+        }
+    }
+    reciever::main(&kinds)
+}
@@ -1,2 +1,9 @@
 pub mod reciever;
-pub mod main;
\ No newline at end of file
+pub mod main;
+pub mod control;
+pub mod history;
+pub mod process;
+pub mod service;
+pub mod storage;
+pub mod tui;
+mod ui;
@@ -0,0 +1,97 @@
+use anyhow::{anyhow, Error};
+use iceoryx2::{node::Node, pending_response::PendingResponse, port::client::Client, service::ipc};
+
+use crate::{config::config, history::now_ms, models::{ProcessAction, ProcessInfo, ProcessRequest, ProcessResponse, ProcessSignal, ProcessStatus}};
+
+use super::{reciever::human_bytes, service};
+
+pub type ProcessClient = Client<ipc::Service, ProcessRequest, (), ProcessResponse, ()>;
+pub type PendingProcess = PendingResponse<ipc::Service, ProcessRequest, (), ProcessResponse, ()>;
+
+/// Open the agent's process service as a client.
+pub fn connect(node: &Node<ipc::Service>) -> Result<ProcessClient, Error>{
+    service::connect(node, &config().transport.process_service)
+}
+
+/// Why the agent refused or failed `req`, `None` if it did not.
+pub fn refusal(req: &ProcessRequest, res: &ProcessResponse) -> Option<String>{
+    let errno = if res.errno != 0 { format!(" ({})", std::io::Error::from_raw_os_error(res.errno)) } else { String::new() };
+    let why = match res.status(){
+        Some(ProcessStatus::Ok) => return None,
+        Some(ProcessStatus::NoSuchProcess) if req.start_time != 0 => format!("process {} is gone, the pid may have been reused", req.pid),
+        Some(ProcessStatus::NoSuchProcess) => format!("there is no process {}", req.pid),
+        Some(ProcessStatus::NotAllowed) => "the agent's config does not allow it".to_string(),
+        Some(ProcessStatus::PermissionDenied) => format!("the agent lacks the privileges{}", errno),
+        Some(ProcessStatus::InvalidArgument) => "the pid or nice value is not valid".to_string(),
+        Some(ProcessStatus::Failed) => format!("it failed{}", errno),
+        None => format!("the agent answered with an unknown status {}", res.status)
+    };
+    Some(format!("{} of {} refused: {}", describe(req), req.pid, why))
+}
+
+pub fn describe(req: &ProcessRequest) -> String{
+    match (req.action(), req.signal()){
+        (Some(ProcessAction::Signal), Some(signal)) => format!("SIG{}", format!("{:?}", signal).to_uppercase()),
+        (Some(ProcessAction::Signal), None) => format!("Signal {}", req.signal),
+        (Some(ProcessAction::Renice), _) => format!("Renice to {}", req.nice),
+        (Some(ProcessAction::Lookup), _) => "Lookup".to_string(),
+        (None, _) => format!("Action {}", req.action)
+    }
+}
+
+/// One line about a looked up process.
+pub fn summary(info: &ProcessInfo) -> String{
+    format!("{} {} uid {} nice {} threads {} cpu {:.1}% mem {} {}",
+        info.pid, info.name(), info.uid, info.nice, info.threads, info.cpu, human_bytes(info.mem), info.status())
+}
+
+pub fn parse_signal(name: &str) -> Option<ProcessSignal>{
+    match name.to_lowercase().trim_start_matches("sig"){
+        "term" => Some(ProcessSignal::Term),
+        "kill" => Some(ProcessSignal::Kill),
+        "stop" => Some(ProcessSignal::Stop),
+        "cont" => Some(ProcessSignal::Cont),
+        _ => None
+    }
+}
+
+/// Entry point for `agent proc lookup <PID> | signal <TERM|KILL|STOP|CONT> <PID> | renice <PID> <NICE>`.
+pub fn main(args: &[String]) -> Result<(), Error>{
+    let usage = "Usage: agent proc lookup <PID> | signal TERM|KILL|STOP|CONT <PID> | renice <PID> <NICE>";
+    let pid = |s: &str| s.parse::<u32>().map_err(|_| anyhow!("'{}' is not a pid. {}", s, usage));
+    let req = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice(){
+        ["lookup", p] => ProcessRequest::new(ProcessAction::Lookup, pid(p)?),
+        ["signal", signal, p] => ProcessRequest{
+            signal: parse_signal(signal).ok_or(anyhow!("Unknown signal '{}'. {}", signal, usage))? as u32,
+            ..ProcessRequest::new(ProcessAction::Signal, pid(p)?)
+        },
+        ["renice", p, nice] => ProcessRequest{
+            nice: nice.parse().map_err(|_| anyhow!("'{}' is not a nice value. {}", nice, usage))?,
+            ..ProcessRequest::new(ProcessAction::Renice, pid(p)?)
+        },
+        _ => return Err(anyhow!(usage))
+    };
+
+    let node = iceoryx2::node::NodeBuilder::new()
+        .name(&"AwareProc".try_into()?)
+        .create::<ipc::Service>()?;
+    let client = connect(&node)?;
+
+    let res = service::request(&node, &client, req, "Process")?;
+    if let Some(why) = refusal(&req, &res){
+        return Err(anyhow!(why));
+    }
+    match req.action(){
+        Some(ProcessAction::Lookup) => {
+            let info = &res.info;
+            println!("{}", summary(info));
+            println!("  parent   {}", info.parent);
+            println!("  vmem     {}", human_bytes(info.virtual_mem));
+            println!("  started  {} (running {}s)", info.start_time, (now_ms() / 1000).saturating_sub(info.start_time));
+            println!("  exe      {}", info.exe());
+            println!("  cmd      {}", info.cmd());
+        }
+        _ => println!("{} sent to {}", describe(&req), req.pid)
+    }
+    Ok(())
+}
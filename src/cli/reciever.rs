@@ -0,0 +1,329 @@
+use anyhow::Error;
+use iceoryx2::{node::{self as ice_node, Node}, port::subscriber::Subscriber, service::ipc};
+use sysinfo::MINIMUM_CPU_UPDATE_INTERVAL;
+
+use crate::{config::config, framing::Reassembler, models::{AlertEvent, BlockDevice, Cgroup, Component, Cpus, Data, DiskData, Interface, Lifecycle, Memory, Meta, Networks, Pressure, Process, ProcessEvent, Sockets, Telemetry, TelemetryKind, Unit}};
+
+// Rows printed for the process and socket tables, the rest is summarized.
+const MAX_ROWS: usize = 20;
+
+/// Subscribe to the agent's telemetry service and print every message that matches `kinds`.
+/// An empty `kinds` prints everything.
+pub fn main(kinds: &[TelemetryKind]) -> Result<(), Error>{
+    let (node, reader) = subscribe("AwareCli")?;
+    let mut reassembler = Reassembler::new();
+
+    while node.wait(MINIMUM_CPU_UPDATE_INTERVAL / 4).is_ok(){
+        while let Some(sample) = reader.receive()?{
+            reassembler.push(&sample);
+        }
+        while let Some(res) = reassembler.pop(){
+            match res{
+                Ok(Data::ShuttingDown) => {
+                    println!("== The agent is shutting down ==");
+                    print_stats(&reassembler);
+                    return Ok(());
+                }
+                Ok(data) => {
+                    if kinds.is_empty() || kinds.contains(&data.kind()){
+                        print_data(&data);
+                    }
+                }
+                Err(e) => eprintln!("WARN: {}", e)
+            }
+        }
+    }
+    print_stats(&reassembler);
+    Ok(())
+}
+
+fn print_stats(reassembler: &Reassembler){
+    let stats = reassembler.stats();
+    eprintln!("{} messages from {} samples, {} lost, {} out of order, {} corrupt, {} incomplete, {} undecodable, {} process deltas out of sync",
+        stats.messages, stats.samples, stats.lost, stats.out_of_order, stats.corrupt, stats.incomplete, stats.undecodable, stats.desynced);
+}
+
+pub type TelemetryReader = Subscriber<ipc::Service, Telemetry, ()>;
+
+/// Create a node called `name` with a subscriber on the agent's telemetry service.
+pub fn subscribe(name: &str) -> Result<(Node<ipc::Service>, TelemetryReader), Error>{
+    let node = ice_node::NodeBuilder::new()
+        .name(&name.try_into()?)
+        .create::<ipc::Service>()?;
+
+    let cfg = config();
+    let transport = &cfg.transport;
+    let service = node.service_builder(&transport.service_name.as_str().try_into()?)
+        .publish_subscribe::<Telemetry>()
+        .subscriber_max_buffer_size(transport.subscriber_buffer)
+        .open_or_create()?;
+
+    let reader = service.subscriber_builder()
+        .buffer_size(transport.subscriber_buffer)
+        .create()?;
+    Ok((node, reader))
+}
+
+pub fn parse_kind(name: &str) -> Option<TelemetryKind>{
+    match name.to_lowercase().as_str(){
+        "meta" => Some(TelemetryKind::Meta),
+        "disk" | "disks" => Some(TelemetryKind::Disk),
+        "net" | "network" | "networks" => Some(TelemetryKind::Networks),
+        "socket" | "sockets" => Some(TelemetryKind::Sockets),
+        "cpu" | "cpus" => Some(TelemetryKind::Cpus),
+        "proc" | "process" | "processes" => Some(TelemetryKind::Process),
+        "mem" | "memory" => Some(TelemetryKind::Memory),
+        "alert" | "alerts" => Some(TelemetryKind::Alert),
+        "lifecycle" | "spawn" | "exit" => Some(TelemetryKind::ProcessEvents),
+        "temp" | "sensors" | "components" => Some(TelemetryKind::Components),
+        "psi" | "pressure" => Some(TelemetryKind::Pressure),
+        "cgroup" | "cgroups" | "containers" => Some(TelemetryKind::Cgroups),
+        "unit" | "units" | "services" | "systemd" => Some(TelemetryKind::Units),
+        "block" | "blockio" | "iostat" => Some(TelemetryKind::BlockDevices),
+        "iface" | "ifaces" | "interfaces" | "addr" => Some(TelemetryKind::Interfaces),
+        _ => None
+    }
+}
+
+fn print_data(data: &Data){
+    match data{
+        Data::Meta(meta) => print_meta(meta),
+        Data::Cpus(cpus) => print_cpus(cpus),
+        Data::Memory(mem) => print_memory(mem),
+        Data::Disk(disks) => print_disks(disks),
+        Data::Networks(nets) => print_networks(nets),
+        Data::Sockets(socks) => print_sockets(socks),
+        Data::Process(procs) => print_processes(procs),
+        Data::Alert(event) => print_alert(event),
+        Data::ProcessEvents(events) => print_process_events(events),
+        Data::Components(components) => print_components(components),
+        Data::Pressure(pressure) => print_pressure(pressure),
+        Data::Cgroups(cgroups) => print_cgroups(cgroups),
+        Data::Units(units) => print_units(units),
+        Data::BlockDevices(devices) => print_block_devices(devices),
+        Data::Interfaces(interfaces) => print_interfaces(interfaces),
+        Data::ProcessDelta(_) | Data::ShuttingDown => {} // Deltas come out of the reassembler as whole tables
+    }
+}
+
+fn print_alert(event: &AlertEvent){
+    let series = if event.label.is_empty() { String::new() } else { format!(" on {}", event.label) };
+    println!("== Alert {:?}: {}{} ==", event.state, event.rule, series);
+    println!("  {} = {} (threshold {}), since {:.0}s", event.metric, event.value, event.threshold, event.t_ms.saturating_sub(event.since_ms) as f64 / 1000.0);
+}
+
+fn print_meta(meta: &Meta){
+    println!("== Meta ==");
+    println!("  host     {}", meta.host_name);
+    println!("  system   {} {} (kernel {}, {}, {})", meta.name, meta.os, meta.kernel, meta.distro, meta.arch);
+    println!("  cpus     {} ({} cores) @ {:.1}%", meta.n_cpu, meta.n_core.map(|n| n.to_string()).unwrap_or("?".to_string()), meta.glob_cpu);
+    println!("  load     {:.2} {:.2} {:.2}", meta.load[0], meta.load[1], meta.load[2]);
+    println!("  uptime   {} (booted at {})", human_duration(meta.uptime), meta.boot_time);
+    println!("  procs    {}", meta.n_proc);
+    println!("  memory   {}", human_bytes(meta.t_mem));
+    println!("  swap     {}", human_bytes(meta.t_swap));
+}
+
+fn print_cpus(cpus: &[Cpus]){
+    println!("== Cpus ==");
+    for cpu in cpus{
+        println!("  {:<8} {:>5.1}% {:>6} MHz  {}", cpu.cpu_name, cpu.cpu_per, cpu.freq, cpu.brand);
+    }
+}
+
+fn print_components(components: &[Component]){
+    println!("== Sensors ==");
+    if components.is_empty(){
+        println!("  no temperature sensors");
+    }
+    let celsius = |t: Option<f32>| t.map(|t| format!("{:.1}°C", t)).unwrap_or("-".to_string());
+    for c in components{
+        println!("  {:<32} {:>8}  max {:>8}  critical {:>8}", c.label, celsius(c.temperature), celsius(c.max), celsius(c.critical));
+    }
+}
+
+fn print_pressure(pressure: &[Pressure]){
+    println!("== Pressure ==");
+    println!("  {:<8} {:<5} {:>7} {:>7} {:>7} {:>12}", "RESOURCE", "STALL", "AVG10", "AVG60", "AVG300", "TOTAL");
+    for p in pressure{
+        let lines = [("some", Some(&p.some)), ("full", p.full.as_ref())];
+        for (kind, stalls) in lines.iter().filter_map(|(k, s)| s.map(|s| (k, s))){
+            println!("  {:<8} {:<5} {:>6.2}% {:>6.2}% {:>6.2}% {:>11.1}s{}", p.resource.name(), kind,
+                stalls.avg10, stalls.avg60, stalls.avg300, stalls.total as f64 / 1e6, if p.triggered { "  triggered" } else { "" });
+        }
+    }
+}
+
+fn print_cgroups(cgroups: &[Cgroup]){
+    println!("== Cgroups ==");
+    println!("  {:<40} {:>10} {:>6} {:>10} {:>10} {:>10} {:>10} {:>11} {:>6}", "CGROUP", "CPU", "CPUS", "MEM", "MEM MAX", "READ", "WRITTEN", "PIDS", "PROCS");
+    let bytes = |b: Option<u64>| b.map(human_bytes).unwrap_or("-".to_string());
+    // Unlimited reads "max" like the kernel's files, a limit the cgroup has no file for "-".
+    let limit = |m: Option<String>, known: bool| m.unwrap_or(if known { "max" } else { "-" }.to_string());
+    for c in cgroups{
+        println!("  {:<40} {:>9.1}s {:>6} {:>10} {:>10} {:>10} {:>10} {:>11} {:>6}",
+            c.path, c.cpu_usage as f64 / 1e6, limit(c.cpu_max.map(|m| format!("{:.2}", m)), true),
+            bytes(c.mem), limit(c.mem_max.map(human_bytes), c.mem.is_some()),
+            human_bytes(c.t_read), human_bytes(c.t_written),
+            format!("{}/{}", c.pids.map(|p| p.to_string()).unwrap_or("-".to_string()), limit(c.pids_max.map(|p| p.to_string()), c.pids.is_some())),
+            c.procs.len());
+    }
+}
+
+fn print_units(units: &[Unit]){
+    println!("== Units ==");
+    println!("  {:<40} {:<10} {:<12} {:<14} {:>8} {:>8}", "UNIT", "LOAD", "ACTIVE", "SUB", "PID", "RESTARTS");
+    let none = |n: Option<u32>| n.map(|n| n.to_string()).unwrap_or("-".to_string());
+    for u in units{
+        println!("  {:<40} {:<10} {:<12} {:<14} {:>8} {:>8}", u.name, u.load, u.active, u.sub, none(u.main_pid), none(u.restarts));
+    }
+}
+
+fn print_memory(mem: &Memory){
+    println!("== Memory ==");
+    println!("  ram      {} / {} used, {} available ({:.1}%)",
+        human_bytes(mem.u_ram), human_bytes(mem.t_ram), human_bytes(mem.a_ram), percent(mem.u_ram, mem.t_ram));
+    println!("  swap     {} / {} used, {} free ({:.1}%)",
+        human_bytes(mem.u_swap), human_bytes(mem.t_swap), human_bytes(mem.a_swap), percent(mem.u_swap, mem.t_swap));
+}
+
+fn print_disks(disks: &[DiskData]){
+    println!("== Disks ==");
+    println!("  {:<16} {:<20} {:<6} {:<8} {:>10} {:>10} {:>6} {:>6} {:>10} {:>10}  OPTIONS",
+        "NAME", "MOUNT", "FS", "DEVICE", "SIZE", "FREE", "USE%", "IUSE%", "READ", "WRITTEN");
+    for disk in disks{
+        let inodes = match (disk.t_inodes, disk.a_inodes){
+            (Some(total), Some(free)) => format!("{:>5.1}%", percent(total - free.min(total), total)),
+            _ => "-".to_string()
+        };
+        println!("  {:<16} {:<20} {:<6} {:<8} {:>10} {:>10} {:>5.1}% {:>6} {:>10} {:>10}  {}{}",
+            disk.name, disk.loc, disk.fs, disk.device.as_deref().unwrap_or("-"),
+            human_bytes(disk.t_space), human_bytes(disk.a_space),
+            percent(disk.t_space - disk.a_space.min(disk.t_space), disk.t_space), inodes,
+            human_bytes(disk.read), human_bytes(disk.written),
+            disk.options.join(","),
+            if disk.removable { " removable" } else { "" });
+    }
+}
+
+fn print_block_devices(devices: &[BlockDevice]){
+    println!("== Block devices ==");
+    println!("  {:<12} {:>8} {:>8} {:>10} {:>10} {:>8} {:>8} {:>6} {:>6} {:>5}  MOUNTS",
+        "DEVICE", "R/S", "W/S", "READ/S", "WRITE/S", "R_AWAIT", "W_AWAIT", "BUSY", "QUEUE", "INFL");
+    for d in devices{
+        println!("  {:<12} {:>8.1} {:>8.1} {:>10} {:>10} {:>6.2}ms {:>6.2}ms {:>5.1}% {:>6.2} {:>5}  {}",
+            d.name, d.reads, d.writes, human_bytes(d.read), human_bytes(d.written),
+            d.r_await, d.w_await, d.busy, d.queue, d.in_flight, d.mounts.join(" "));
+    }
+}
+
+fn print_networks(nets: &[Networks]){
+    println!("== Networks ==");
+    println!("  {:<16} {:>10} {:>10} {:>12} {:>12} {:>8} {:>8}", "IFACE", "DOWN", "UP", "TOTAL DOWN", "TOTAL UP", "ERR RX", "ERR TX");
+    for net in nets{
+        println!("  {:<16} {:>10} {:>10} {:>12} {:>12} {:>8} {:>8}",
+            net.name, human_bytes(net.down), human_bytes(net.up),
+            human_bytes(net.t_down), human_bytes(net.t_up), net.t_err_rx, net.t_err_tx);
+    }
+}
+
+fn print_interfaces(interfaces: &[Interface]){
+    println!("== Interfaces ==");
+    for i in interfaces{
+        let speed = i.speed.map(|s| format!(" {} Mbit/s", s)).unwrap_or_default();
+        let carrier = match i.carrier{
+            Some(false) => " no carrier",
+            _ => ""
+        };
+        println!("  {:<16} {}{}{}  mtu {}  mac {}", i.name, i.state, carrier, speed, i.mtu, i.mac.as_deref().unwrap_or("-"));
+        for addr in &i.addresses{
+            println!("    {}", addr);
+        }
+    }
+}
+
+fn print_sockets(socks: &[Sockets]){
+    println!("== Sockets ({}) ==", socks.len());
+    println!("  {:<5} {:<28} {:<28} {:<12} PIDS", "PROTO", "LOCAL", "REMOTE", "STATE");
+    for sock in socks.iter().take(MAX_ROWS){
+        match sock{
+            Sockets::Tcp { local_addr, local_port, remote_addr, remote_port, pids, state } => {
+                println!("  {:<5} {:<28} {:<28} {:<12} {:?}", "tcp",
+                    format!("{}:{}", local_addr, local_port), format!("{}:{}", remote_addr, remote_port), state, pids);
+            }
+            Sockets::Udp { local_addr, local_port, pid } => {
+                println!("  {:<5} {:<28} {:<28} {:<12} {:?}", "udp", format!("{}:{}", local_addr, local_port), "*", "", pid);
+            }
+        }
+    }
+    if socks.len() > MAX_ROWS{
+        println!("  ... {} more", socks.len() - MAX_ROWS);
+    }
+}
+
+fn print_processes(procs: &[Process]){
+    let mut sorted: Vec<&Process> = procs.iter().collect();
+    sorted.sort_by(|a, b| b.cpu.total_cmp(&a.cpu));
+    let detail = procs.iter().any(|p| p.detail.is_some());
+
+    println!("== Processes ({}) ==", procs.len());
+    if detail{
+        println!("  {:>8} {:>8} {:>6} {:>10} {:>10} {:>10} {:>5} {:>5} {:<10} NAME", "PID", "PPID", "CPU%", "MEM", "READ", "WRITTEN", "THR", "FDS", "STATUS");
+    }
+    else{
+        println!("  {:>8} {:>8} {:>6} {:>10} {:<10} NAME", "PID", "PPID", "CPU%", "MEM", "STATUS");
+    }
+    for proc in sorted.iter().take(MAX_ROWS){
+        let parent = proc.parent.map(|p| p.to_string()).unwrap_or_default();
+        match &proc.detail{
+            Some(d) => println!("  {:>8} {:>8} {:>6.1} {:>10} {:>10} {:>10} {:>5} {:>5} {:<10} {}",
+                proc.pid, parent, proc.cpu, human_bytes(proc.mem), human_bytes(d.read), human_bytes(d.written),
+                d.threads.map(|t| t.to_string()).unwrap_or_default(), d.fds.map(|f| f.to_string()).unwrap_or_default(),
+                proc.status, proc.name),
+            None => println!("  {:>8} {:>8} {:>6.1} {:>10} {:<10} {}",
+                proc.pid, parent, proc.cpu, human_bytes(proc.mem), proc.status, proc.name)
+        }
+    }
+    if procs.len() > MAX_ROWS{
+        println!("  ... {} more", procs.len() - MAX_ROWS);
+    }
+}
+
+fn print_process_events(events: &[ProcessEvent]){
+    for event in events{
+        let what = match event.event{
+            Lifecycle::Started => "Started",
+            Lifecycle::Exited => "Exited "
+        };
+        println!("{} {:>8} ppid {:>8} uid {:>6} ran {:>6}s cpu {:>5.1}% mem {:>10} {} {}",
+            what, event.pid,
+            event.parent.map(|p| p.to_string()).unwrap_or_default(),
+            event.user_id.as_deref().unwrap_or(""),
+            event.run_time, event.cpu, human_bytes(event.mem), event.name, event.exe);
+    }
+}
+
+pub fn percent(part: u64, whole: u64) -> f64{
+    if whole == 0 { 0.0 } else { part as f64 * 100.0 / whole as f64 }
+}
+
+/// `secs` as days, hours and minutes, e.g. "3d 4h 12m".
+pub fn human_duration(secs: u64) -> String{
+    let (d, h, m) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+    match (d, h){
+        (0, 0) => format!("{}m {}s", m, secs % 60),
+        (0, _) => format!("{}h {}m", h, m),
+        _ => format!("{}d {}h {}m", d, h, m)
+    }
+}
+
+pub fn human_bytes(bytes: u64) -> String{
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1{
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{} {}", bytes, UNITS[0]) } else { format!("{:.1} {}", value, UNITS[unit]) }
+}
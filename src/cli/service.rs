@@ -0,0 +1,43 @@
+use std::fmt::Debug;
+
+use anyhow::{anyhow, Error};
+use iceoryx2::{node::Node, port::client::Client, prelude::ZeroCopySend, service::ipc};
+use tokio::time::{Duration, Instant};
+
+// How long `request` waits for the agent to answer.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+const POLL_CYCLE: Duration = Duration::from_millis(10);
+
+/// Open the agent's request-response service `name` as a client.
+pub fn connect<Req, Res>(node: &Node<ipc::Service>, name: &str) -> Result<Client<ipc::Service, Req, (), Res, ()>, Error>
+where
+    Req: Debug + ZeroCopySend,
+    Res: Debug + ZeroCopySend
+{
+    let service = node.service_builder(&name.try_into()?)
+        .request_response::<Req, Res>()
+        .open_or_create()?;
+    Ok(service.client_builder().create()?)
+}
+
+/// Send `req` and wait for the agent's answer, `what` names the service in errors.
+pub fn request<Req, Res>(node: &Node<ipc::Service>, client: &Client<ipc::Service, Req, (), Res, ()>, req: Req, what: &str) -> Result<Res, Error>
+where
+    Req: Debug + ZeroCopySend,
+    Res: Debug + ZeroCopySend + Copy
+{
+    let pending = client.send_copy(req)?;
+    if pending.number_of_server_connections() == 0{
+        return Err(anyhow!("No agent is serving the {} service, is it running?", what));
+    }
+    let deadline = Instant::now() + REPLY_TIMEOUT;
+    while Instant::now() < deadline{
+        if let Some(res) = pending.receive()?{
+            return Ok(*res);
+        }
+        if node.wait(POLL_CYCLE).is_err(){
+            break;
+        }
+    }
+    Err(anyhow!("The agent did not answer within {:?}, is it running?", REPLY_TIMEOUT))
+}
@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Error};
+
+use crate::{config::config, history::now_ms, storage::{Reader, Resolution}};
+
+use super::history::{format_value, parse_metric};
+
+/// `90`, `90s`, `15m`, `6h` or `7d`, in milliseconds.
+fn parse_duration(text: &str) -> Option<u64>{
+    let (num, unit) = match text.find(|c: char| !c.is_ascii_digit()){
+        Some(idx) => text.split_at(idx),
+        None => (text, "s")
+    };
+    let secs = match unit{
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return None
+    };
+    num.parse::<u64>().ok().map(|n| n * secs * 1000)
+}
+
+/// Entry point for `agent storage <METRIC> [LABEL] [--last DURATION] [--res 1s|1m|1h] [--dir DIR]`,
+/// reads the on-disk store directly, whether or not an agent is running.
+pub fn main(args: &[String]) -> Result<(), Error>{
+    let usage = "Usage: agent storage cpu|memory|swap|down|up|read|written [LABEL] [--last DURATION] [--res 1s|1m|1h] [--dir DIR]";
+    let mut metric = None;
+    let mut label = None;
+    let mut span_ms = 3_600_000;
+    let mut res = None;
+    let mut dir: PathBuf = config().storage.dir.clone();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next(){
+        match arg.as_str(){
+            "--last" => span_ms = iter.next().and_then(|s| parse_duration(s)).ok_or(anyhow!(usage))?,
+            "--res" => res = Some(iter.next().and_then(|s| Resolution::parse(s)).ok_or(anyhow!(usage))?),
+            "--dir" => dir = iter.next().ok_or(anyhow!(usage))?.into(),
+            name if metric.is_none() => metric = Some(parse_metric(name).ok_or(anyhow!("Unknown metric '{}'. {}", name, usage))?),
+            name if label.is_none() => label = Some(name.to_string()),
+            _ => return Err(anyhow!(usage))
+        }
+    }
+    let metric = metric.ok_or(anyhow!(usage))?;
+    let res = res.unwrap_or(Resolution::for_span(span_ms));
+
+    let now = now_ms();
+    let records = Reader::new(dir).query(res, metric, label.as_deref(), now.saturating_sub(span_ms), now)?;
+    let mut current = None;
+    for rec in &records{
+        if current != Some(&rec.label){
+            println!("== {:?} {} ({}) ==", metric, rec.label, res.name());
+            current = Some(&rec.label);
+        }
+        println!(
+            "  {:>10.0}s ago  avg {:>12}  min {:>12}  max {:>12}  ({} values)",
+            now.saturating_sub(rec.t_ms) as f64 / 1000.0,
+            format_value(metric, rec.avg), format_value(metric, rec.min), format_value(metric, rec.max), rec.count
+        );
+    }
+    Ok(())
+}
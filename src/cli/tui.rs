@@ -0,0 +1,430 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use iceoryx2::{node::Node, service::ipc};
+use ratatui::{crossterm::event::{self, Event, KeyCode, KeyEventKind}, widgets::TableState, DefaultTerminal};
+
+use crate::{framing::{FrameStats, Reassembler}, history::now_ms, models::{AlertEvent, AlertState, BlockDevice, Cgroup, Component, ControlAction, ControlRequest, ControlStatus, Cpus, Data, DiskData, HistoryMetric, Interface, Memory, Meta, Networks, Pressure, Process, ProcessAction, ProcessRequest, ProcessSignal, ProcessStatus, Sockets, Unit}, rates::Rates, state::AppState, tree::ProcessTree};
+
+use super::{control::{self, ControlClient, PendingControl}, history, process::{self, PendingProcess, ProcessClient}, reciever::{self, TelemetryReader}, ui};
+
+// How long we wait for a key press before looking for new samples again.
+const TICK: Duration = Duration::from_millis(100);
+// Points kept per interface for the throughput sparklines.
+pub const HISTORY_LEN: usize = 120;
+// How far back the sparklines are filled from the agent's history on start.
+const BACKFILL: Duration = Duration::from_secs(300);
+// How long a process action waits for the agent before it is given up on.
+const PROCESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub const TABS: [AppState; 7] = [
+    AppState::Meta,
+    AppState::Cpu,
+    AppState::Disk,
+    AppState::Processes,
+    AppState::Network,
+    AppState::Sockets,
+    AppState::Memory
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcSort{
+    Pid,
+    Name,
+    Cpu,
+    Mem
+}
+
+/// Throughput of an interface in bytes per second, oldest first.
+#[derive(Default)]
+pub struct History{
+    pub down: VecDeque<u64>,
+    pub up: VecDeque<u64>
+}
+
+impl History{
+    fn push(&mut self, down: u64, up: u64){
+        if self.down.len() == HISTORY_LEN{
+            self.down.pop_front();
+            self.up.pop_front();
+        }
+        self.down.push_back(down);
+        self.up.push_back(up);
+    }
+}
+
+pub struct App{
+    pub tab: usize,
+    pub meta: Option<Meta>,
+    pub cpus: Vec<Cpus>,
+    pub components: Option<Vec<Component>>, // None until the agent sent any, empty without sensors
+    pub memory: Option<Memory>,
+    pub pressure: Vec<Pressure>,
+    pub units: Vec<Unit>, // Failed first, then those starting or restarting
+    pub disks: Vec<DiskData>,
+    pub block_devices: Vec<BlockDevice>,
+    pub networks: Vec<Networks>,
+    pub interfaces: HashMap<String, Interface>,
+    pub net_history: HashMap<String, History>,
+    net_rates: Rates<(String, &'static str)>, // By interface and direction
+    pub net_table: TableState,
+    pub sockets: Vec<Sockets>,
+    pub processes: Vec<Process>,
+    pub cgroups: Vec<(Cgroup, Option<f32>)>, // With their cpu percent since the last sample, busiest first
+    cgroup_rates: Rates<String>,
+    pub proc_sort: ProcSort,
+    pub proc_desc: bool,
+    pub proc_table: TableState,
+    pub tree_view: bool,
+    pub proc_tree: Option<ProcessTree>, // Built from `processes` in the tree view
+    pub confirm: Option<ProcessRequest>,  // A signal waiting for y before it is sent
+    pub proc_notice: Option<String>,      // What came of the last process action
+    outbox: Option<(ProcessRequest, Option<i32>)>, // To send, with a nice step to renice by once a lookup answers
+    pub stats: FrameStats,
+    pub alerts: Vec<AlertEvent>, // Pending and firing, by rule and series
+    pub last_error: Option<String>,
+    pub agent_down: bool,
+    pub agent_state: Option<AppState>, // Last state the agent acknowledged
+    quit: bool
+}
+
+impl App{
+    fn new() -> Self{
+        Self{
+            tab: 0,
+            meta: None,
+            cpus: Vec::new(),
+            components: None,
+            memory: None,
+            pressure: Vec::new(),
+            units: Vec::new(),
+            disks: Vec::new(),
+            block_devices: Vec::new(),
+            networks: Vec::new(),
+            interfaces: HashMap::new(),
+            net_history: HashMap::new(),
+            net_rates: Rates::default(),
+            net_table: TableState::default().with_selected(0),
+            sockets: Vec::new(),
+            processes: Vec::new(),
+            cgroups: Vec::new(),
+            cgroup_rates: Rates::default(),
+            proc_sort: ProcSort::Cpu,
+            proc_desc: true,
+            proc_table: TableState::default().with_selected(0),
+            tree_view: false,
+            proc_tree: None,
+            confirm: None,
+            proc_notice: None,
+            outbox: None,
+            stats: FrameStats::default(),
+            alerts: Vec::new(),
+            last_error: None,
+            agent_down: false,
+            agent_state: None,
+            quit: false
+        }
+    }
+
+    /// The agent state that matches the tab on screen.
+    pub fn focus(&self) -> AppState{
+        TABS[self.tab]
+    }
+
+    fn update(&mut self, data: Data){
+        self.agent_down = false;
+        match data{
+            Data::Meta(meta) => self.meta = Some(meta),
+            Data::Cpus(cpus) => self.cpus = cpus,
+            Data::Components(components) => self.components = Some(components),
+            Data::Pressure(pressure) => self.pressure = pressure,
+            Data::Memory(mem) => self.memory = Some(mem),
+            Data::Disk(disks) => self.disks = disks,
+            Data::BlockDevices(devices) => self.block_devices = devices,
+            Data::Networks(nets) => {
+                let t_ms = now_ms();
+                for net in &nets{
+                    let down = self.net_rates.rate((net.name.clone(), "down"), t_ms, net.t_down);
+                    let up = self.net_rates.rate((net.name.clone(), "up"), t_ms, net.t_up);
+                    if let (Some(down), Some(up)) = (down, up){
+                        self.net_history.entry(net.name.clone()).or_default().push(down as u64, up as u64);
+                    }
+                }
+                self.net_history.retain(|name, _| nets.iter().any(|net| &net.name == name));
+                self.net_rates.retain(|(name, _)| nets.iter().any(|net| &net.name == name));
+                self.networks = nets;
+                self.networks.sort_by(|a, b| a.name.cmp(&b.name));
+            }
+            Data::Interfaces(interfaces) => self.interfaces = interfaces.into_iter().map(|i| (i.name.clone(), i)).collect(),
+            Data::Sockets(socks) => self.sockets = socks,
+            Data::Process(procs) => {
+                self.processes = procs;
+                self.sort_processes();
+            }
+            Data::Units(units) => {
+                self.units = units;
+                self.units.sort_by_key(|u| (!u.failed(), u.active != "activating", u.name.clone()));
+            }
+            Data::Cgroups(cgroups) => {
+                let t_ms = now_ms();
+                self.cgroup_rates.retain(|path| cgroups.iter().any(|c| &c.path == path));
+                self.cgroups = cgroups.into_iter().map(|c| {
+                    let cpu = self.cgroup_rates.rate(c.path.clone(), t_ms, c.cpu_usage).map(|usage| (usage / 1e4) as f32); // Microseconds per second to percent
+                    (c, cpu)
+                }).collect();
+                self.cgroups.sort_by(|(a, a_cpu), (b, b_cpu)| b_cpu.unwrap_or(0.0).total_cmp(&a_cpu.unwrap_or(0.0)).then(a.path.cmp(&b.path)));
+            }
+            Data::Alert(event) => {
+                self.alerts.retain(|a| a.rule != event.rule || a.label != event.label);
+                if matches!(event.state, AlertState::Pending | AlertState::Firing){
+                    self.alerts.push(event);
+                }
+            }
+            Data::ProcessEvents(_) | Data::ProcessDelta(_) => {} // Deltas come out of the reassembler as whole tables
+            Data::ShuttingDown => self.agent_down = true
+        }
+    }
+
+    /// How many alerts are firing right now, pending ones aside.
+    pub fn firing(&self) -> usize{
+        self.alerts.iter().filter(|a| a.state == AlertState::Firing).count()
+    }
+
+    fn sort_processes(&mut self){
+        match self.proc_sort{
+            ProcSort::Pid => self.processes.sort_by_key(|p| p.pid),
+            ProcSort::Name => self.processes.sort_by_cached_key(|p| p.name.to_lowercase()),
+            ProcSort::Cpu => self.processes.sort_by(|a, b| a.cpu.total_cmp(&b.cpu)),
+            ProcSort::Mem => self.processes.sort_by_key(|p| p.mem)
+        }
+        if self.proc_desc{
+            self.processes.reverse();
+        }
+        // Siblings keep the order just sorted into, but by the totals of their subtrees for cpu and memory.
+        self.proc_tree = self.tree_view.then(|| {
+            let mut tree = ProcessTree::build(&self.processes);
+            let desc = self.proc_desc;
+            let order = |o: std::cmp::Ordering| if desc { o.reverse() } else { o };
+            match self.proc_sort{
+                ProcSort::Cpu => tree.sort_siblings(|a, b| order(a.cpu.total_cmp(&b.cpu))),
+                ProcSort::Mem => tree.sort_siblings(|a, b| order(a.mem.cmp(&b.mem))),
+                ProcSort::Pid | ProcSort::Name => {}
+            }
+            tree
+        });
+    }
+
+    /// Rows of the process table, fewer in the tree view which leaves threads out.
+    pub fn process_rows(&self) -> usize{
+        match &self.proc_tree{
+            Some(tree) => tree.nodes().len(),
+            None => self.processes.len()
+        }
+    }
+
+    /// The pid and, with full detail, the start time of the selected process.
+    fn selected(&self) -> Option<(u32, u64)>{
+        let list = process_list(self.proc_tree.as_ref(), &self.processes);
+        self.proc_table.selected().and_then(|i| list.get(i)).map(|(p, ..)| (p.pid, p.detail.as_ref().map_or(0, |d| d.start_time)))
+    }
+
+    /// Act on the selected process. Signals wait for a confirmation first.
+    fn process_action(&mut self, action: ProcessAction, signal: ProcessSignal, renice_by: Option<i32>){
+        let Some((pid, start_time)) = self.selected() else { return; };
+        // Pinning the start time keeps a signal confirmed late from reaching a process that took over the pid.
+        let req = ProcessRequest { signal: signal as u32, start_time, ..ProcessRequest::new(action, pid) };
+        match action{
+            ProcessAction::Signal => self.confirm = Some(req),
+            _ => self.outbox = Some((req, renice_by))
+        }
+    }
+
+    fn set_sort(&mut self, sort: ProcSort){
+        if self.proc_sort == sort{
+            self.proc_desc = !self.proc_desc;
+        }
+        else{
+            self.proc_sort = sort;
+            self.proc_desc = matches!(sort, ProcSort::Cpu | ProcSort::Mem);
+        }
+        self.sort_processes();
+    }
+
+    fn on_key(&mut self, code: KeyCode){
+        if let Some(req) = self.confirm.take(){
+            if code == KeyCode::Char('y'){
+                self.outbox = Some((req, None));
+            }
+            return;
+        }
+        match code{
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Tab | KeyCode::Right => self.tab = (self.tab + 1) % TABS.len(),
+            KeyCode::BackTab | KeyCode::Left => self.tab = (self.tab + TABS.len() - 1) % TABS.len(),
+            KeyCode::Char(c @ '1'..='7') => self.tab = c as usize - '1' as usize,
+            KeyCode::Up => self.scroll(-1),
+            KeyCode::Down => self.scroll(1),
+            KeyCode::PageUp => self.scroll(-20),
+            KeyCode::PageDown => self.scroll(20),
+            KeyCode::Char(c) if self.focus() == AppState::Processes => match c{
+                'p' => self.set_sort(ProcSort::Pid),
+                'n' => self.set_sort(ProcSort::Name),
+                'c' => self.set_sort(ProcSort::Cpu),
+                'm' => self.set_sort(ProcSort::Mem),
+                't' => {
+                    self.tree_view = !self.tree_view;
+                    self.sort_processes();
+                }
+                'k' => self.process_action(ProcessAction::Signal, ProcessSignal::Term, None),
+                'K' => self.process_action(ProcessAction::Signal, ProcessSignal::Kill, None),
+                'z' => self.process_action(ProcessAction::Signal, ProcessSignal::Stop, None),
+                'Z' => self.process_action(ProcessAction::Signal, ProcessSignal::Cont, None),
+                'i' => self.process_action(ProcessAction::Lookup, ProcessSignal::Term, None),
+                // Renice goes from the current nice value, so the process is looked up first.
+                '+' => self.process_action(ProcessAction::Lookup, ProcessSignal::Term, Some(1)),
+                '-' => self.process_action(ProcessAction::Lookup, ProcessSignal::Term, Some(-1)),
+                _ => {}
+            }
+            _ => {}
+        }
+    }
+
+    fn scroll(&mut self, by: isize){
+        let (table, len) = match self.focus(){
+            AppState::Processes => {
+                let rows = self.process_rows();
+                (&mut self.proc_table, rows)
+            }
+            AppState::Network => (&mut self.net_table, self.networks.len()),
+            _ => return
+        };
+        let current = table.selected().unwrap_or(0) as isize;
+        let last = len.saturating_sub(1) as isize;
+        table.select(Some((current + by).clamp(0, last) as usize));
+    }
+}
+
+/// The process table as shown, with the depth and the cpu and memory of each row.
+/// The tree view shows every process under its parent, with the totals of its subtree.
+pub fn process_list<'a>(tree: Option<&ProcessTree>, procs: &'a [Process]) -> Vec<(&'a Process, usize, f32, u64)>{
+    match tree{
+        Some(tree) => tree.walk().map(|n| (&procs[n.idx], n.depth, n.cpu, n.mem)).collect(),
+        None => procs.iter().map(|p| (p, 0, p.cpu, p.mem)).collect()
+    }
+}
+
+/// Entry point for `agent tui`, a full screen dashboard over the Telemetry service.
+pub fn main() -> Result<(), Error>{
+    let (node, reader) = reciever::subscribe("AwareTui")?;
+    let client = control::connect(&node)?;
+    let proc_client = process::connect(&node)?;
+    let mut app = App::new();
+    if let Err(e) = backfill(&node, &mut app){
+        app.last_error = Some(e.to_string());
+    }
+    let mut terminal = ratatui::init();
+    let res = run(&mut terminal, &node, &reader, &client, &proc_client, app);
+    ratatui::restore();
+    res
+}
+
+/// Fill the sparklines from the agent's history so they are not empty until enough samples came in.
+fn backfill(node: &Node<ipc::Service>, app: &mut App) -> Result<(), Error>{
+    let client = history::connect(node)?;
+    let from_ms = now_ms().saturating_sub(BACKFILL.as_millis() as u64);
+    let down = history::fetch(node, &client, HistoryMetric::NetDown, from_ms)?;
+    let up = history::fetch(node, &client, HistoryMetric::NetUp, from_ms)?;
+    for (name, down) in down{
+        let Some((_, up)) = up.iter().find(|(n, _)| *n == name) else { continue; };
+        let history = app.net_history.entry(name).or_default();
+        // Both are recorded from the same samples, so they line up.
+        for (d, u) in down.iter().zip(up){
+            history.push(d.value as u64, u.value as u64);
+        }
+    }
+    Ok(())
+}
+
+fn run(
+    terminal: &mut DefaultTerminal, node: &Node<ipc::Service>, reader: &TelemetryReader, client: &ControlClient, proc_client: &ProcessClient, mut app: App
+) -> Result<(), Error>{
+    let mut reassembler = Reassembler::new();
+    let mut pending: Option<(AppState, PendingControl)> = None;
+    let mut proc_pending: Option<(ProcessRequest, Option<i32>, Instant, PendingProcess)> = None;
+    let mut sent_focus: Option<AppState> = None;
+
+    while !app.quit{
+        // Keep the agent's focus on whatever tab is on screen so it gets the fast intervals.
+        if sent_focus != Some(app.focus()){
+            let focus = app.focus();
+            let req = client.send_copy(ControlRequest::new(ControlAction::Focus, Some(focus)))?;
+            if req.number_of_server_connections() > 0{
+                sent_focus = Some(focus);
+                pending = Some((focus, req));
+            }
+        }
+        if let Some((focus, req)) = &pending
+            && let Some(res) = req.receive()?{
+            app.agent_state = res.state();
+            if res.status() != Some(ControlStatus::Ok) || res.state() != Some(*focus){
+                sent_focus = None; // Try again
+            }
+            pending = None;
+        }
+
+        if proc_pending.is_none()
+            && let Some((req, renice_by)) = app.outbox.take(){
+            let res = proc_client.send_copy(req)?;
+            if res.number_of_server_connections() == 0{
+                app.proc_notice = Some("No agent is serving the Process service".to_string());
+            }
+            else{
+                proc_pending = Some((req, renice_by, Instant::now(), res));
+            }
+        }
+        if let Some((req, renice_by, sent, res)) = &proc_pending{
+            if let Some(res) = res.receive()?{
+                app.proc_notice = Some(match (process::refusal(req, &res), req.action()){
+                    (Some(why), _) => why,
+                    (None, Some(ProcessAction::Lookup)) => process::summary(&res.info),
+                    (None, _) => format!("{} sent to {}", process::describe(req), req.pid)
+                });
+                if let Some(by) = renice_by
+                    && res.status() == Some(ProcessStatus::Ok){
+                    let nice = (res.info.nice + by).clamp(-20, 19);
+                    let start_time = res.info.start_time; // The process looked up, not one that took over its pid since
+                    app.outbox = Some((ProcessRequest { action: ProcessAction::Renice as u32, nice, start_time, ..*req }, None));
+                }
+                proc_pending = None;
+            }
+            else if sent.elapsed() > PROCESS_TIMEOUT{
+                app.proc_notice = Some(format!("The agent did not answer {} of {}", process::describe(req), req.pid));
+                proc_pending = None;
+            }
+        }
+
+        while let Some(sample) = reader.receive()?{
+            reassembler.push(&sample);
+        }
+        while let Some(res) = reassembler.pop(){
+            match res{
+                Ok(data) => app.update(data),
+                Err(e) => app.last_error = Some(e.to_string())
+            }
+        }
+        app.stats = reassembler.stats();
+
+        terminal.draw(|frame| ui::draw(frame, &mut app))?;
+
+        if event::poll(TICK)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press{
+            app.on_key(key.code);
+        }
+        if node.wait(Duration::ZERO).is_err(){
+            break; // Interrupted
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,509 @@
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Borders, Cell, Gauge, LineGauge, Paragraph, Row, Sparkline, Table, Tabs},
+    Frame
+};
+
+use crate::{history::now_ms, models::{Component, Sockets, Stalls, Unit}, state::AppState};
+
+use super::{process, reciever::{human_bytes, human_duration, percent}, tui::{process_list, App, ProcSort, TABS}};
+
+// How many cgroups the processes tab has room for.
+const MAX_CGROUP_ROWS: usize = 6;
+
+pub fn draw(frame: &mut Frame, app: &mut App){
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(0),
+        Constraint::Length(1)
+    ]).areas(frame.area());
+
+    let titles = TABS.iter().enumerate().map(|(i, tab)| format!("{} {:?}", i + 1, tab));
+    frame.render_widget(
+        Tabs::new(titles)
+            .block(Block::bordered().title(" Aware "))
+            .select(app.tab)
+            .highlight_style(Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
+        header
+    );
+
+    match app.focus(){
+        AppState::Meta => draw_meta(frame, app, body),
+        AppState::Cpu => draw_cpus(frame, app, body),
+        AppState::Disk => draw_disks(frame, app, body),
+        AppState::Processes => draw_processes(frame, app, body),
+        AppState::Network => draw_networks(frame, app, body),
+        AppState::Sockets => draw_sockets(frame, app, body),
+        AppState::Memory => draw_memory(frame, app, body),
+        AppState::ShuttingDown => {}
+    }
+
+    let firing = app.firing();
+    let status = if app.agent_down{
+        Span::raw(" The agent is shutting down ").black().on_red()
+    }
+    else{
+        Span::raw(format!(" agent focus: {} | {} msgs, {} lost, {} incomplete{} ",
+            app.agent_state.map(|s| format!("{:?}", s)).unwrap_or("not connected".to_string()),
+            app.stats.messages, app.stats.lost, app.stats.incomplete,
+            app.last_error.as_ref().map(|e| format!(" | {}", e)).unwrap_or_default())).dark_gray()
+    };
+    let alerts = if firing > 0{
+        Span::raw(format!(" {} firing ", firing)).black().on_yellow()
+    }
+    else{
+        Span::raw("")
+    };
+    let keys = if app.focus() == AppState::Processes{
+        " q quit  ←/→ tab  ↑/↓ select  p/n/c/m sort  t tree  k/K term/kill  z/Z stop/cont  +/- nice  i info "
+    }
+    else{
+        " q quit  ←/→ tab  ↑/↓ select  p/n/c/m sort  t tree "
+    };
+    // A signal waiting for its confirmation hides everything else.
+    let notice = match (&app.confirm, &app.proc_notice){
+        (Some(req), _) => Span::raw(format!(" Send {} to {}? y/n ", process::describe(req), req.pid)).black().on_red(),
+        (None, Some(notice)) if app.focus() == AppState::Processes => Span::raw(format!(" {} ", notice)).black().on_cyan(),
+        _ => Span::raw("")
+    };
+    frame.render_widget(Paragraph::new(Line::from(vec![
+        Span::raw(keys).reversed(),
+        notice,
+        alerts,
+        status
+    ])), footer);
+}
+
+fn draw_meta(frame: &mut Frame, app: &App, area: Rect){
+    let Some(meta) = &app.meta else {
+        frame.render_widget(waiting("Meta"), area);
+        return;
+    };
+    let [info, cpu, mem, psi, units] = Layout::vertical([
+        Constraint::Length(10),
+        Constraint::Length(3),
+        Constraint::Length(3),
+        Constraint::Length(app.pressure.len() as u16 + 2),
+        Constraint::Fill(1)
+    ]).areas(area);
+
+    let lines = vec![
+        Line::from(vec![Span::raw("host     ").bold(), Span::raw(meta.host_name.as_str())]),
+        Line::from(vec![Span::raw("system   ").bold(), Span::raw(format!("{} {}", meta.name, meta.os))]),
+        Line::from(vec![Span::raw("kernel   ").bold(), Span::raw(format!("{} ({}, {})", meta.kernel, meta.distro, meta.arch))]),
+        Line::from(vec![Span::raw("cpus     ").bold(), Span::raw(match meta.n_core{
+            Some(cores) => format!("{} ({} cores)", meta.n_cpu, cores),
+            None => meta.n_cpu.to_string()
+        })]),
+        Line::from(vec![Span::raw("load     ").bold(), Span::raw(format!("{:.2} {:.2} {:.2}", meta.load[0], meta.load[1], meta.load[2]))]),
+        Line::from(vec![Span::raw("uptime   ").bold(), Span::raw(human_duration(meta.uptime))]),
+        Line::from(vec![Span::raw("procs    ").bold(), Span::raw(meta.n_proc.to_string())]),
+        Line::from(vec![Span::raw("swap     ").bold(), Span::raw(human_bytes(meta.t_swap))]),
+    ];
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" System ")), info);
+    frame.render_widget(gauge(" Cpu ", meta.glob_cpu as f64, format!("{:.1}%", meta.glob_cpu)), cpu);
+    if let Some(m) = &app.memory{
+        frame.render_widget(gauge(" Memory ", percent(m.u_ram, m.t_ram),
+            format!("{} / {}", human_bytes(m.u_ram), human_bytes(m.t_ram))), mem);
+    }
+    if !app.pressure.is_empty(){
+        // The share of the last 10, 60 and 300 seconds that tasks were stalled.
+        let avgs = |s: &Stalls| format!("{:>6.2}% {:>6.2}% {:>6.2}%", s.avg10, s.avg60, s.avg300);
+        let lines: Vec<Line> = app.pressure.iter().map(|p| Line::from(vec![
+            Span::raw(format!("{:<8} ", p.resource.name())).bold(),
+            Span::raw(format!("some {}", avgs(&p.some))).fg(usage_color(p.some.avg10 as f64)),
+            Span::raw(p.full.as_ref().map(|f| format!("   full {}", avgs(f))).unwrap_or_default()),
+            Span::raw(if p.triggered { "  triggered" } else { "" }).yellow()
+        ])).collect();
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" Pressure 10s/60s/300s ")), psi);
+    }
+    if !app.units.is_empty(){
+        draw_units(frame, app, units);
+    }
+}
+
+fn draw_units(frame: &mut Frame, app: &App, area: Rect){
+    let none = |n: Option<u32>| n.map(|n| n.to_string()).unwrap_or("-".to_string());
+    let rows = app.units.iter().map(|u: &Unit| {
+        let color = match u.active.as_str(){
+            "failed" => Color::Red,
+            "activating" | "deactivating" | "reloading" => Color::Yellow,
+            _ => Color::Reset
+        };
+        // Resource use comes from the main process, once the processes arrived.
+        let process = u.process(&app.processes);
+        Row::new(vec![
+            Cell::from(u.name.as_str()),
+            Cell::from(format!("{} ({})", u.active, u.sub)).style(Style::new().fg(color)),
+            Cell::from(none(u.main_pid)),
+            Cell::from(none(u.restarts)),
+            Cell::from(process.map(|p| format!("{:.1}", p.cpu)).unwrap_or("-".to_string())),
+            Cell::from(process.map(|p| human_bytes(p.mem)).unwrap_or("-".to_string())),
+            Cell::from(if u.load == "loaded" { "" } else { u.load.as_str() }).dark_gray(),
+        ])
+    });
+    let widths = [
+        Constraint::Fill(1), Constraint::Length(26), Constraint::Length(8), Constraint::Length(8),
+        Constraint::Length(6), Constraint::Length(10), Constraint::Length(10)
+    ];
+    let failed = app.units.iter().filter(|u| u.failed()).count();
+    frame.render_widget(
+        Table::new(rows, widths)
+            .header(header(&["UNIT", "STATE", "PID", "RESTARTS", "CPU%", "MEM", ""]))
+            .block(Block::bordered().title(format!(" Units ({}, {} failed) ", app.units.len(), failed))),
+        area
+    );
+}
+
+fn draw_cpus(frame: &mut Frame, app: &App, area: Rect){
+    if app.cpus.is_empty(){
+        frame.render_widget(waiting("Cpu"), area);
+        return;
+    }
+    // The sensors go under the cpus once the agent sent them.
+    let (area, sensors) = match &app.components{
+        Some(components) => {
+            let [cpus, sensors] = Layout::vertical([Constraint::Fill(1), Constraint::Length(components.len().max(1) as u16 + 2)]).areas(area);
+            (cpus, Some((components, sensors)))
+        }
+        None => (area, None)
+    };
+    if let Some((components, sensors)) = sensors{
+        draw_sensors(frame, components, sensors);
+    }
+    let block = Block::bordered().title(format!(" {} ", app.cpus[0].brand));
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let rows = Layout::vertical(app.cpus.iter().map(|_| Constraint::Length(1))).split(inner);
+    for (cpu, row) in app.cpus.iter().zip(rows.iter()){
+        frame.render_widget(
+            LineGauge::default()
+                .label(format!("{:<7} {:>5.1}% {:>5} MHz ", cpu.cpu_name, cpu.cpu_per, cpu.freq))
+                .filled_style(Style::new().fg(usage_color(cpu.cpu_per as f64)))
+                .ratio((cpu.cpu_per as f64 / 100.0).clamp(0.0, 1.0)),
+            *row
+        );
+    }
+}
+
+fn draw_sensors(frame: &mut Frame, components: &[Component], area: Rect){
+    let block = Block::bordered().title(" Sensors ");
+    if components.is_empty(){
+        frame.render_widget(Paragraph::new("No temperature sensors").dark_gray().block(block), area);
+        return;
+    }
+    let celsius = |t: Option<f32>| t.map(|t| format!("{:.1}°C", t)).unwrap_or("-".to_string());
+    let rows = components.iter().map(|c| {
+        // Red from the critical temperature on, yellow within 10 degrees of it.
+        let color = match (c.temperature, c.critical){
+            (Some(t), Some(crit)) if t >= crit => Color::Red,
+            (Some(t), Some(crit)) if t >= crit - 10.0 => Color::Yellow,
+            _ => Color::Reset
+        };
+        Row::new(vec![
+            Cell::from(c.label.as_str()),
+            Cell::from(celsius(c.temperature)).style(Style::new().fg(color)),
+            Cell::from(format!("max {}", celsius(c.max))),
+            Cell::from(format!("critical {}", celsius(c.critical)))
+        ])
+    });
+    let widths = [Constraint::Fill(1), Constraint::Length(9), Constraint::Length(14), Constraint::Length(19)];
+    frame.render_widget(Table::new(rows, widths).block(block), area);
+}
+
+fn draw_memory(frame: &mut Frame, app: &App, area: Rect){
+    let Some(m) = &app.memory else {
+        frame.render_widget(waiting("Memory"), area);
+        return;
+    };
+    let [ram, swap, info] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(3),
+        Constraint::Min(0)
+    ]).areas(area);
+
+    frame.render_widget(gauge(" Ram ", percent(m.u_ram, m.t_ram),
+        format!("{} / {}", human_bytes(m.u_ram), human_bytes(m.t_ram))), ram);
+    frame.render_widget(gauge(" Swap ", percent(m.u_swap, m.t_swap),
+        format!("{} / {}", human_bytes(m.u_swap), human_bytes(m.t_swap))), swap);
+    frame.render_widget(Paragraph::new(vec![
+        Line::from(format!("available ram   {}", human_bytes(m.a_ram))),
+        Line::from(format!("free swap       {}", human_bytes(m.a_swap))),
+    ]).block(Block::bordered()), info);
+}
+
+fn draw_disks(frame: &mut Frame, app: &App, area: Rect){
+    // The block devices go under the disks once the agent sent them.
+    let area = match app.block_devices.is_empty(){
+        true => area,
+        false => {
+            let [disks, devices] = Layout::vertical([Constraint::Fill(1), Constraint::Length(app.block_devices.len() as u16 + 3)]).areas(area);
+            draw_block_devices(frame, app, devices);
+            disks
+        }
+    };
+    let rows = app.disks.iter().map(|d| Row::new(vec![
+        Cell::from(d.name.as_str()),
+        Cell::from(d.loc.as_str()),
+        Cell::from(d.fs.as_str()),
+        Cell::from(d.type_.as_str()),
+        Cell::from(human_bytes(d.t_space)),
+        Cell::from(human_bytes(d.a_space)),
+        Cell::from(format!("{:.1}%", percent(d.t_space - d.a_space.min(d.t_space), d.t_space))),
+        match (d.t_inodes, d.a_inodes){
+            (Some(total), Some(free)) => {
+                let used = percent(total - free.min(total), total);
+                Cell::from(format!("{:.1}%", used)).style(Style::new().fg(usage_color(used)))
+            }
+            _ => Cell::from("-")
+        },
+        Cell::from(human_bytes(d.read)),
+        Cell::from(human_bytes(d.written)),
+    ]));
+    let widths = [
+        Constraint::Fill(2), Constraint::Fill(2), Constraint::Length(8), Constraint::Length(5),
+        Constraint::Length(10), Constraint::Length(10), Constraint::Length(6), Constraint::Length(6), Constraint::Length(10), Constraint::Length(10)
+    ];
+    frame.render_widget(
+        Table::new(rows, widths)
+            .header(header(&["NAME", "MOUNT", "FS", "TYPE", "SIZE", "FREE", "USE%", "IUSE%", "READ", "WRITTEN"]))
+            .block(Block::bordered().title(" Disks ")),
+        area
+    );
+}
+
+fn draw_block_devices(frame: &mut Frame, app: &App, area: Rect){
+    let rows = app.block_devices.iter().map(|d| Row::new(vec![
+        Cell::from(d.name.as_str()),
+        Cell::from(format!("{:.1}", d.reads)),
+        Cell::from(format!("{:.1}", d.writes)),
+        Cell::from(human_bytes(d.read)),
+        Cell::from(human_bytes(d.written)),
+        Cell::from(format!("{:.2}ms", d.r_await)),
+        Cell::from(format!("{:.2}ms", d.w_await)),
+        Cell::from(format!("{:.1}%", d.busy)).style(Style::new().fg(usage_color(d.busy as f64))),
+        Cell::from(format!("{:.2}", d.queue)),
+        Cell::from(d.in_flight.to_string()),
+        Cell::from(d.mounts.join(" ")),
+    ]));
+    let widths = [
+        Constraint::Length(12), Constraint::Length(8), Constraint::Length(8), Constraint::Length(10), Constraint::Length(10),
+        Constraint::Length(9), Constraint::Length(9), Constraint::Length(6), Constraint::Length(6), Constraint::Length(5), Constraint::Fill(1)
+    ];
+    frame.render_widget(
+        Table::new(rows, widths)
+            .header(header(&["DEVICE", "R/S", "W/S", "READ/S", "WRITE/S", "R_AWAIT", "W_AWAIT", "BUSY", "QUEUE", "INFL", "MOUNTS"]))
+            .block(Block::bordered().title(" Block devices ")),
+        area
+    );
+}
+
+fn draw_processes(frame: &mut Frame, app: &mut App, area: Rect){
+    let arrow = if app.proc_desc { "▼" } else { "▲" };
+    let title = |name: &str, sort: ProcSort| if app.proc_sort == sort { format!("{}{}", name, arrow) } else { name.to_string() };
+    let head = [
+        title("PID", ProcSort::Pid), "PPID".to_string(), "USER".to_string(),
+        title("CPU%", ProcSort::Cpu), title("MEM", ProcSort::Mem), "STATUS".to_string(), title("NAME", ProcSort::Name), "COMMAND".to_string()
+    ];
+
+    // The busiest cgroups go under the processes once the agent sent them.
+    let area = match app.cgroups.is_empty(){
+        true => area,
+        false => {
+            let [table, cgroups] = Layout::vertical([Constraint::Fill(1), Constraint::Length(app.cgroups.len().min(MAX_CGROUP_ROWS) as u16 + 3)]).areas(area);
+            draw_cgroups(frame, app, cgroups);
+            table
+        }
+    };
+    let rows = process_list(app.proc_tree.as_ref(), &app.processes);
+    // The full detail level of the agent adds a panel about the selected process.
+    let detail = app.proc_table.selected().and_then(|i| rows.get(i)).and_then(|(p, ..)| p.detail.as_ref().map(|d| (p.pid, d)));
+    let (area, panel) = match detail{
+        Some(_) => {
+            let [table, panel] = Layout::vertical([Constraint::Fill(1), Constraint::Length(4)]).areas(area);
+            (table, Some(panel))
+        }
+        None => (area, None)
+    };
+    if let (Some((pid, d)), Some(panel)) = (detail, panel){
+        let none = || "-".to_string();
+        let lines = vec![
+            Line::from(format!("io  {} read, {} written since the last sample, {} read, {} written in total",
+                human_bytes(d.read), human_bytes(d.written), human_bytes(d.t_read), human_bytes(d.t_written))),
+            Line::from(format!("vmem {}  threads {}  fds {}  running {}s  cwd {}",
+                human_bytes(d.virtual_mem), d.threads.map_or_else(none, |t| t.to_string()),
+                d.fds.map_or_else(none, |f| f.to_string()), d.run_time(now_ms()), d.cwd))
+        ];
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(format!(" Process {} ", pid))), panel);
+    }
+    let rows = rows.into_iter().map(|(p, depth, cpu, mem)| Row::new(vec![
+        Cell::from(p.pid.to_string()),
+        Cell::from(p.parent.map(|p| p.to_string()).unwrap_or_default()),
+        Cell::from(p.user_id.clone().unwrap_or_default()),
+        Cell::from(format!("{:.1}", cpu)).style(Style::new().fg(usage_color(cpu as f64))),
+        Cell::from(human_bytes(mem)),
+        Cell::from(p.status.as_str()),
+        Cell::from(format!("{}{}", "  ".repeat(depth), p.name)),
+        Cell::from(p.cmd.as_str()),
+    ]));
+    let widths = [
+        Constraint::Length(8), Constraint::Length(8), Constraint::Length(6), Constraint::Length(6),
+        Constraint::Length(10), Constraint::Length(9), Constraint::Length(20), Constraint::Fill(1)
+    ];
+    let table = Table::new(rows, widths)
+        .header(Row::new(head).bold().underlined())
+        .row_highlight_style(Style::new().reversed())
+        .block(Block::bordered().title(match app.proc_tree{
+            Some(_) => format!(" Process tree ({}), cpu and memory of whole subtrees ", app.process_rows()),
+            None => format!(" Processes ({}) ", app.processes.len())
+        }));
+    frame.render_stateful_widget(table, area, &mut app.proc_table);
+}
+
+fn draw_cgroups(frame: &mut Frame, app: &App, area: Rect){
+    // Unlimited reads "max" like the kernel's files, a limit the cgroup has no file for "-".
+    let limit = |m: Option<String>, known: bool| m.unwrap_or(if known { "max" } else { "-" }.to_string());
+    let rows = app.cgroups.iter().take(MAX_CGROUP_ROWS).map(|(c, cpu)| Row::new(vec![
+        Cell::from(c.path.as_str()),
+        match cpu{
+            Some(cpu) => Cell::from(format!("{:.1}", cpu)).style(Style::new().fg(usage_color(*cpu as f64 / c.cpu_max.unwrap_or(1.0).max(f32::EPSILON) as f64))),
+            None => Cell::from("-")
+        },
+        Cell::from(limit(c.cpu_max.map(|m| format!("{:.2}", m)), true)),
+        Cell::from(c.mem.map(human_bytes).unwrap_or("-".to_string())),
+        Cell::from(limit(c.mem_max.map(human_bytes), c.mem.is_some())),
+        Cell::from(format!("{}/{}", c.pids.map(|p| p.to_string()).unwrap_or("-".to_string()), limit(c.pids_max.map(|p| p.to_string()), c.pids.is_some()))),
+        Cell::from(c.procs.len().to_string()),
+    ]));
+    let widths = [
+        Constraint::Fill(1), Constraint::Length(6), Constraint::Length(6), Constraint::Length(10),
+        Constraint::Length(10), Constraint::Length(12), Constraint::Length(6)
+    ];
+    frame.render_widget(
+        Table::new(rows, widths)
+            .header(header(&["CGROUP", "CPU%", "CPUS", "MEM", "MEM MAX", "PIDS", "PROCS"]))
+            .block(Block::bordered().title(format!(" Cgroups ({}), busiest first ", app.cgroups.len()))),
+        area
+    );
+}
+
+fn draw_networks(frame: &mut Frame, app: &mut App, area: Rect){
+    let [list, graphs] = Layout::vertical([Constraint::Fill(1), Constraint::Fill(1)]).areas(area);
+
+    let rows = app.networks.iter().map(|n| Row::new(vec![
+        Cell::from(n.name.as_str()),
+        match app.interfaces.get(&n.name){
+            Some(i) => Cell::from(i.state.as_str()).style(Style::new().fg(if i.up() { Color::Green } else { Color::Red })),
+            None => Cell::from("")
+        },
+        Cell::from(human_bytes(n.down)),
+        Cell::from(human_bytes(n.up)),
+        Cell::from(human_bytes(n.t_down)),
+        Cell::from(human_bytes(n.t_up)),
+        Cell::from(n.t_err_rx.to_string()),
+        Cell::from(n.t_err_tx.to_string()),
+    ]));
+    let widths = [
+        Constraint::Fill(1), Constraint::Length(8), Constraint::Length(10), Constraint::Length(10), Constraint::Length(12),
+        Constraint::Length(12), Constraint::Length(8), Constraint::Length(8)
+    ];
+    let table = Table::new(rows, widths)
+        .header(header(&["IFACE", "STATE", "DOWN", "UP", "TOTAL DOWN", "TOTAL UP", "ERR RX", "ERR TX"]))
+        .row_highlight_style(Style::new().reversed())
+        .block(Block::bordered().title(" Networks "));
+    frame.render_stateful_widget(table, list, &mut app.net_table);
+
+    let selected = app.net_table.selected().and_then(|i| app.networks.get(i));
+    let Some(history) = selected.and_then(|n| app.net_history.get(&n.name)) else { return; };
+    let name = selected.map(|n| n.name.as_str()).unwrap_or_default();
+
+    // The addresses and link of the selected interface go above its graphs once the agent sent them.
+    let graphs = match app.interfaces.get(name){
+        Some(i) => {
+            let [details, graphs] = Layout::vertical([Constraint::Length(4), Constraint::Fill(1)]).areas(graphs);
+            let carrier = match i.carrier{
+                Some(true) => "carrier",
+                Some(false) => "no carrier",
+                None => "-"
+            };
+            let lines = vec![
+                Line::from(format!("{}, {}  speed {}  mtu {}  mac {}", i.state, carrier,
+                    i.speed.map(|s| format!("{} Mbit/s", s)).unwrap_or("-".to_string()), i.mtu, i.mac.as_deref().unwrap_or("-"))),
+                Line::from(if i.addresses.is_empty() { "no addresses".to_string() } else { i.addresses.join("  ") })
+            ];
+            frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(format!(" {} ", name))), details);
+            graphs
+        }
+        None => graphs
+    };
+    let [down, up] = Layout::vertical([Constraint::Fill(1), Constraint::Fill(1)]).areas(graphs);
+    let down_data: Vec<u64> = history.down.iter().copied().collect();
+    let up_data: Vec<u64> = history.up.iter().copied().collect();
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::new().borders(Borders::ALL).title(format!(" {} down {}/s ", name, human_bytes(*history.down.back().unwrap_or(&0)))))
+            .data(&down_data)
+            .style(Style::new().fg(Color::Green)),
+        down
+    );
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::new().borders(Borders::ALL).title(format!(" {} up {}/s ", name, human_bytes(*history.up.back().unwrap_or(&0)))))
+            .data(&up_data)
+            .style(Style::new().fg(Color::Blue)),
+        up
+    );
+}
+
+fn draw_sockets(frame: &mut Frame, app: &App, area: Rect){
+    let rows = app.sockets.iter().map(|s| match s{
+        Sockets::Tcp { local_addr, local_port, remote_addr, remote_port, pids, state } => Row::new(vec![
+            "tcp".to_string(),
+            format!("{}:{}", local_addr, local_port),
+            format!("{}:{}", remote_addr, remote_port),
+            state.clone(),
+            format!("{:?}", pids)
+        ]),
+        Sockets::Udp { local_addr, local_port, pid } => Row::new(vec![
+            "udp".to_string(),
+            format!("{}:{}", local_addr, local_port),
+            "*".to_string(),
+            String::new(),
+            format!("{:?}", pid)
+        ])
+    });
+    let widths = [Constraint::Length(5), Constraint::Fill(1), Constraint::Fill(1), Constraint::Length(12), Constraint::Length(16)];
+    frame.render_widget(
+        Table::new(rows, widths)
+            .header(header(&["PROTO", "LOCAL", "REMOTE", "STATE", "PIDS"]))
+            .block(Block::bordered().title(format!(" Sockets ({}) ", app.sockets.len()))),
+        area
+    );
+}
+
+fn header<'a>(names: &[&'a str]) -> Row<'a>{
+    Row::new(names.iter().copied()).bold().underlined()
+}
+
+fn gauge<'a>(title: &'a str, percent: f64, label: String) -> Gauge<'a>{
+    Gauge::default()
+        .block(Block::bordered().title(title))
+        .gauge_style(Style::new().fg(usage_color(percent)))
+        .label(format!("{} ({:.1}%)", label, percent))
+        .ratio((percent / 100.0).clamp(0.0, 1.0))
+}
+
+fn usage_color(percent: f64) -> Color{
+    match percent{
+        p if p >= 90.0 => Color::Red,
+        p if p >= 60.0 => Color::Yellow,
+        _ => Color::Green
+    }
+}
+
+fn waiting(what: &str) -> Paragraph<'_>{
+    Paragraph::new(format!("Waiting for {} data from the agent...", what)).block(Block::bordered())
+}
@@ -0,0 +1,232 @@
+use std::{collections::HashMap, fs, path::Path, time::Instant};
+
+use anyhow::Error;
+use tokio::sync::mpsc;
+
+use crate::{collectors::{disks, pause}, config::config, models::{BlockDevice, Data}, rates::increase, state::AppState, APPSTATE, DISKS};
+
+const DISKSTATS: &str = "/proc/diskstats";
+// Every block device has a directory here, partitions included.
+const SYS_BLOCK: &str = "/sys/class/block";
+// diskstats counts 512 byte sectors whatever the device's own sector size.
+const SECTOR: u64 = 512;
+
+/// The counters of one /proc/diskstats line.
+#[derive(Clone, Copy)]
+struct Stats{
+    reads: u64,
+    read_sectors: u64,
+    read_ms: u64,
+    writes: u64,
+    written_sectors: u64,
+    write_ms: u64,
+    in_flight: u64,
+    busy_ms: u64,
+    queue_ms: u64 // Time requests spent queued or in flight, added up over all of them
+}
+
+/// Where a device's queue settings come from, read once per device.
+#[derive(Clone, Copy)]
+struct Queue{
+    partition: bool,
+    rotational: Option<bool>,
+    depth: Option<u32>
+}
+
+/// IOPS, throughput, await, utilisation and queue depth per block device, like iostat, shown with the disks.
+/// Rates need two samples, so the first is sent an interval after the collector starts.
+pub async fn main(tx: mpsc::Sender<Data>) -> Result<(), Error>{
+    let mut last: Option<(Instant, HashMap<String, Stats>)> = None;
+    let mut queues: HashMap<String, Queue> = HashMap::new();
+    loop{
+        let state: AppState;
+        if let Some(appstate) = APPSTATE.get(){
+            state = *appstate.read().await;
+            if state == AppState::ShuttingDown || !config().collectors.blockio.enabled{
+                break;
+            }
+        }
+        else{
+            pause(config().collectors.blockio.interval(true)).await;
+            continue;
+        }
+
+        let now = Instant::now();
+        let stats = match read(){
+            Ok(stats) => stats,
+            Err(e) => {
+                eprintln!("{} can't be read, stopping the blockio collector: {:#}", DISKSTATS, e);
+                return Ok(());
+            }
+        };
+        if let Some((then, prev)) = &last{
+            let secs = now.duration_since(*then).as_secs_f64().max(f64::EPSILON);
+            let mounts = mounts();
+            let mut devices = Vec::new();
+            for (name, s) in &stats{
+                // Devices that never did any IO, like unused loop devices, are left out.
+                let Some(p) = prev.get(name).filter(|_| s.reads + s.writes > 0) else { continue };
+                let queue = *queues.entry(name.clone()).or_insert_with(|| queue(name));
+                devices.push(device(name, s, p, secs, queue, mounts.get(name).cloned().unwrap_or_default()));
+            }
+            devices.sort_by(|a, b| a.name.cmp(&b.name));
+            tx.send(Data::BlockDevices(devices)).await?;
+        }
+        queues.retain(|name, _| stats.contains_key(name));
+        last = Some((now, stats));
+
+        pause(config().collectors.blockio.interval(state == AppState::Disk)).await;
+    }
+    Ok(())
+}
+
+fn read() -> Result<HashMap<String, Stats>, Error>{
+    Ok(parse(&fs::read_to_string(DISKSTATS)?))
+}
+
+/// The counters of every device in `text`, a line that can't be read is skipped.
+fn parse(text: &str) -> HashMap<String, Stats>{
+    let mut stats = HashMap::new();
+    for line in text.lines(){
+        // major minor name, then at least 11 counters on every kernel since 2.6.
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 14{
+            continue;
+        }
+        let n = |idx: usize| fields[idx].parse::<u64>().ok();
+        let counters = (|| Some(Stats{
+            reads: n(3)?,
+            read_sectors: n(5)?,
+            read_ms: n(6)?,
+            writes: n(7)?,
+            written_sectors: n(9)?,
+            write_ms: n(10)?,
+            in_flight: n(11)?,
+            busy_ms: n(12)?,
+            queue_ms: n(13)?
+        }))();
+        match counters{
+            Some(s) => { stats.insert(fields[2].to_string(), s); }
+            None => eprintln!("Skipping an unexpected line in {}: {}", DISKSTATS, line)
+        }
+    }
+    stats
+}
+
+fn device(name: &str, s: &Stats, p: &Stats, secs: f64, queue: Queue, mounts: Vec<String>) -> BlockDevice{
+    let delta = |now: u64, then: u64| increase(now, then).unwrap_or(0); // An interval with a reset counts as idle
+    let (reads, writes) = (delta(s.reads, p.reads), delta(s.writes, p.writes));
+    let average = |ms: u64, ios: u64| if ios > 0 { ms as f64 / ios as f64 } else { 0.0 };
+    let interval_ms = secs * 1000.0;
+    BlockDevice{
+        name: name.to_string(),
+        partition: queue.partition,
+        mounts,
+        rotational: queue.rotational,
+        queue_depth: queue.depth,
+        reads: reads as f64 / secs,
+        writes: writes as f64 / secs,
+        read: (delta(s.read_sectors, p.read_sectors) as f64 * SECTOR as f64 / secs) as u64,
+        written: (delta(s.written_sectors, p.written_sectors) as f64 * SECTOR as f64 / secs) as u64,
+        r_await: average(delta(s.read_ms, p.read_ms), reads),
+        w_await: average(delta(s.write_ms, p.write_ms), writes),
+        busy: (delta(s.busy_ms, p.busy_ms) as f64 / interval_ms * 100.0).min(100.0) as f32,
+        queue: delta(s.queue_ms, p.queue_ms) as f64 / interval_ms,
+        in_flight: s.in_flight,
+        t_reads: s.reads,
+        t_writes: s.writes,
+        t_read: s.read_sectors * SECTOR,
+        t_written: s.written_sectors * SECTOR,
+        t_busy_ms: s.busy_ms
+    }
+}
+
+/// A partition has no queue of its own, it uses the one of the disk it is on.
+fn queue(name: &str) -> Queue{
+    let dev = Path::new(SYS_BLOCK).join(name);
+    let partition = dev.join("partition").exists();
+    let dir = match partition{
+        true => fs::canonicalize(&dev).ok().and_then(|p| p.parent().map(|disk| disk.join("queue"))),
+        false => Some(dev.join("queue"))
+    };
+    let number = |file: &str| dir.as_ref()
+        .and_then(|d| fs::read_to_string(d.join(file)).ok())
+        .and_then(|s| s.trim().parse::<u32>().ok());
+    Queue { partition, rotational: number("rotational").map(|r| r == 1), depth: number("nr_requests") }
+}
+
+/// Mount points by device name, from the disks the disks collector keeps.
+fn mounts() -> HashMap<String, Vec<String>>{
+    let mut mounts: HashMap<String, Vec<String>> = HashMap::new();
+    let Some(disks) = DISKS.get() else { return mounts };
+    let disks = match disks.read(){
+        Ok(data) => data,
+        Err(poisoned) => {
+            eprintln!("FATAL: DISKS lock was poisoned!, Recovering");
+            poisoned.into_inner()
+        }
+    };
+    for disk in &*disks{
+        if let Some(name) = disks::device(disk.name().as_ref()){
+            mounts.entry(name).or_default().push(disk.mount_point().to_string_lossy().to_string());
+        }
+    }
+    mounts
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const DISKSTATS_TEXT: &str = "\
+   8       0 sda 1000 10 16000 500 2000 20 32000 4000 1 3000 4500 0 0 0 0
+   8       1 sda1 900 5 14000 450 1800 10 30000 3800 0 2800 4200
+ 259       0 nvme0n1 12 0 96 x 3 0 24 2 0 10 12
+   7       0 loop0 0 0 0 0
+";
+
+    fn stats(reads: u64, read_ms: u64, writes: u64, write_ms: u64, busy_ms: u64) -> Stats{
+        Stats { reads, read_sectors: reads * 8, read_ms, writes, written_sectors: writes * 8, write_ms, in_flight: 2, busy_ms, queue_ms: busy_ms * 2 }
+    }
+
+    #[test]
+    fn parse_skips_bad_lines(){
+        let stats = parse(DISKSTATS_TEXT);
+        assert_eq!(stats.len(), 2); // nvme0n1 has a bad counter, loop0 too few
+        let sda = stats["sda"];
+        assert_eq!((sda.reads, sda.read_sectors, sda.read_ms, sda.writes, sda.written_sectors, sda.write_ms), (1000, 16000, 500, 2000, 32000, 4000));
+        assert_eq!((sda.in_flight, sda.busy_ms, sda.queue_ms), (1, 3000, 4500));
+        assert_eq!(stats["sda1"].queue_ms, 4200); // Older kernels stop after 11 counters
+    }
+
+    #[test]
+    fn rates_and_await(){
+        let queue = Queue { partition: false, rotational: Some(true), depth: Some(64) };
+        let d = device("sda", &stats(300, 400, 150, 600, 1500), &stats(100, 200, 50, 100, 500), 2.0, queue, vec!["/".to_string()]);
+        assert_eq!((d.reads, d.writes), (100.0, 50.0));
+        assert_eq!((d.read, d.written), (200 * 8 * SECTOR / 2, 100 * 8 * SECTOR / 2));
+        assert_eq!((d.r_await, d.w_await), (1.0, 5.0)); // 200 ms over 200 reads, 500 over 100 writes
+        assert_eq!(d.busy, 50.0);
+        assert_eq!(d.queue, 1.0);
+        assert_eq!((d.t_reads, d.t_written, d.t_busy_ms), (300, 150 * 8 * SECTOR, 1500));
+        assert_eq!((d.partition, d.rotational, d.queue_depth, d.mounts), (false, Some(true), Some(64), vec!["/".to_string()]));
+    }
+
+    #[test]
+    fn counter_reset_is_idle(){
+        let queue = Queue { partition: false, rotational: None, depth: None };
+        let d = device("sda", &stats(10, 5, 10, 5, 20), &stats(100, 200, 50, 100, 500), 1.0, queue, Vec::new());
+        assert_eq!((d.reads, d.writes, d.read, d.written), (0.0, 0.0, 0, 0));
+        assert_eq!((d.r_await, d.w_await, d.busy, d.queue), (0.0, 0.0, 0.0, 0.0));
+        assert_eq!(d.t_reads, 10); // Totals are the counters as they are now
+    }
+
+    #[test]
+    fn partition_takes_its_disks_queue(){
+        let queue = Queue { partition: true, rotational: Some(false), depth: Some(1023) };
+        let d = device("nvme0n1p2", &stats(1, 0, 0, 0, 0), &stats(0, 0, 0, 0, 0), 1.0, queue, Vec::new());
+        assert!(d.partition);
+        assert_eq!((d.rotational, d.queue_depth), (Some(false), Some(1023)));
+        assert_eq!(d.r_await, 0.0); // A read that took no time
+    }
+}
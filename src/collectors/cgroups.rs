@@ -0,0 +1,222 @@
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
+
+use anyhow::Error;
+use tokio::sync::mpsc;
+
+use crate::{collectors::pause, config::config, models::{Cgroup, Data}, state::AppState, APPSTATE};
+
+// Only the v2 hierarchy has this file at its root.
+const CONTROLLERS: &str = "cgroup.controllers";
+// Where hybrid hosts mount the v2 hierarchy below the v1 controllers.
+const UNIFIED: &str = "unified";
+
+/// Usage and limits of every cgroup v2 down to `cgroups.max_depth`, with the processes in each, shown with the processes.
+/// Hosts without a v2 hierarchy stop the collector.
+pub async fn main(tx: mpsc::Sender<Data>) -> Result<(), Error>{
+    let mut warned: Option<PathBuf> = None; // The root last found not to be a v2 hierarchy
+    loop{
+        let state: AppState;
+        if let Some(appstate) = APPSTATE.get(){
+            state = *appstate.read().await;
+            if state == AppState::ShuttingDown || !config().collectors.cgroups.enabled{
+                break;
+            }
+        }
+        else{
+            pause(config().collectors.cgroups.interval(true)).await;
+            continue;
+        }
+
+        // The root is looked up every cycle so a reload can point it elsewhere.
+        let cfg = config();
+        match hierarchy(&cfg.cgroups.root){
+            Some(root) => {
+                warned = None;
+                let cgroups = tokio::task::spawn_blocking(move || collect(&root, cfg.cgroups.max_depth, processes())).await?;
+                tx.send(Data::Cgroups(cgroups)).await?;
+            }
+            None if warned.as_ref() != Some(&cfg.cgroups.root) => {
+                eprintln!("No cgroup v2 hierarchy at {}, the cgroups collector will wait for cgroups.root to change", cfg.cgroups.root.display());
+                warned = Some(cfg.cgroups.root.clone());
+            }
+            None => {}
+        }
+
+        pause(config().collectors.cgroups.interval(state == AppState::Processes)).await;
+    }
+    Ok(())
+}
+
+/// `root` if it is a v2 hierarchy, or the v2 part of a hybrid one.
+fn hierarchy(root: &Path) -> Option<PathBuf>{
+    [root.to_path_buf(), root.join(UNIFIED)].into_iter().find(|p| p.join(CONTROLLERS).is_file())
+}
+
+/// The cgroups under `root`, with `procs` given as (pid, cgroup path) like `processes` lists them.
+fn collect(root: &Path, max_depth: usize, procs: Vec<(u32, String)>) -> Vec<Cgroup>{
+    let mut cgroups = Vec::new();
+    walk(root, root, 0, max_depth, &mut cgroups);
+
+    // Processes belong to the deepest collected cgroup their own one is in.
+    let index: HashMap<String, usize> = cgroups.iter().enumerate().map(|(idx, c)| (c.path.clone(), idx)).collect();
+    for (pid, mut path) in procs{
+        loop{
+            if let Some(&idx) = index.get(&path){
+                cgroups[idx].procs.push(pid);
+                break;
+            }
+            match path.rsplit_once('/'){
+                Some(("", _)) if path != "/" => path = "/".to_string(),
+                Some((parent, _)) if !parent.is_empty() => path = parent.to_string(),
+                _ => break // Outside of the tree, as with a fake root
+            }
+        }
+    }
+    cgroups
+}
+
+fn walk(root: &Path, dir: &Path, depth: usize, max_depth: usize, cgroups: &mut Vec<Cgroup>){
+    let path = match dir.strip_prefix(root).map(|p| p.to_string_lossy()){
+        Ok(p) if p.is_empty() => "/".to_string(),
+        Ok(p) => format!("/{}", p),
+        Err(_) => return
+    };
+    cgroups.push(read(dir, path));
+    if depth >= max_depth{
+        return;
+    }
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    let mut children: Vec<PathBuf> = entries.flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|e| e.path())
+        .collect();
+    children.sort();
+    for child in children{
+        walk(root, &child, depth + 1, max_depth, cgroups);
+    }
+}
+
+fn read(dir: &Path, path: String) -> Cgroup{
+    let file = |name: &str| fs::read_to_string(dir.join(name)).ok();
+    let number = |name: &str| file(name).and_then(|s| limit(s.trim()));
+
+    let (mut cpu_usage, mut cpu_throttled) = (0, 0);
+    for (key, value) in file("cpu.stat").iter().flat_map(|s| pairs(s.lines().map(|l| l.split_once(' ')))){
+        match key{
+            "usage_usec" => cpu_usage = value,
+            "throttled_usec" => cpu_throttled = value,
+            _ => {}
+        }
+    }
+    // "max 100000" or a quota and period in microseconds.
+    let cpu_max = file("cpu.max").and_then(|s| {
+        let (quota, period) = s.trim().split_once(' ')?;
+        Some(quota.parse::<f32>().ok()? / period.parse::<f32>().ok()?)
+    });
+
+    // A line per device, "8:0 rbytes=1 wbytes=2 rios=3 wios=4 dbytes=0 dios=0".
+    let (mut t_read, mut t_written) = (0, 0);
+    let io = file("io.stat").unwrap_or_default();
+    for line in io.lines(){
+        for (key, value) in pairs(line.split_whitespace().skip(1).map(|f| f.split_once('='))){
+            match key{
+                "rbytes" => t_read += value,
+                "wbytes" => t_written += value,
+                _ => {}
+            }
+        }
+    }
+
+    Cgroup{
+        path,
+        cpu_usage,
+        cpu_throttled,
+        cpu_max,
+        mem: number("memory.current"),
+        mem_max: number("memory.max"),
+        t_read,
+        t_written,
+        pids: number("pids.current"),
+        pids_max: number("pids.max"),
+        procs: Vec::new()
+    }
+}
+
+/// A number, `None` for "max" and anything unparsable.
+fn limit(value: &str) -> Option<u64>{
+    value.parse().ok()
+}
+
+fn pairs<'a>(fields: impl Iterator<Item = Option<(&'a str, &'a str)>>) -> impl Iterator<Item = (&'a str, u64)>{
+    fields.flatten().filter_map(|(key, value)| Some((key, value.trim().parse().ok()?)))
+}
+
+/// Every process with its cgroup v2 path, from the "0::/path" line of /proc/<pid>/cgroup.
+fn processes() -> Vec<(u32, String)>{
+    let Ok(entries) = fs::read_dir("/proc") else { return Vec::new() };
+    entries.flatten()
+        .filter_map(|e| {
+            let pid = e.file_name().to_str()?.parse::<u32>().ok()?;
+            let text = fs::read_to_string(e.path().join("cgroup")).ok()?; // Gone since the listing
+            let path = text.lines().find_map(|l| l.strip_prefix("0::"))?;
+            Some((pid, path.trim_end_matches(" (deleted)").to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::testutil::temp_dir;
+
+    fn write(dir: &Path, files: &[(&str, &str)]){
+        fs::create_dir_all(dir).unwrap();
+        for (name, text) in files{
+            fs::write(dir.join(name), text).unwrap();
+        }
+    }
+
+    #[test]
+    fn tree(){
+        let root = temp_dir("cgroups-tree");
+        write(&root, &[(CONTROLLERS, "cpu io memory pids\n"), ("cpu.stat", "usage_usec 9000\nuser_usec 6000\nsystem_usec 3000\n")]);
+        let slice = root.join("system.slice");
+        write(&slice, &[
+            ("cpu.stat", "usage_usec 5000\nuser_usec 4000\nsystem_usec 1000\nnr_periods 10\nnr_throttled 2\nthrottled_usec 300\n"),
+            ("cpu.max", "50000 100000\n"),
+            ("memory.current", "4096\n"),
+            ("memory.max", "max\n"),
+            ("io.stat", "8:0 rbytes=100 wbytes=200 rios=1 wios=2 dbytes=0 dios=0\n259:0 rbytes=1000 wbytes=2000 rios=3 wios=4 dbytes=0 dios=0\n"),
+            ("pids.current", "3\n"),
+            ("pids.max", "max\n")
+        ]);
+        let service = slice.join("nginx.service");
+        write(&service, &[("cpu.max", "max 100000\n"), ("memory.max", "8192\n")]);
+        write(&service.join("workers"), &[("cpu.stat", "usage_usec 1\n")]); // Below max_depth
+
+        let procs = vec![
+            (1, "/".to_string()),
+            (10, "/system.slice".to_string()),
+            (11, "/system.slice/nginx.service/workers".to_string()),
+            (12, "/user.slice/user-1000.slice".to_string()) // Not a cgroup of the tree, only the root is left
+        ];
+        let cgroups = collect(&hierarchy(&root).unwrap(), 2, procs);
+        let paths: Vec<&str> = cgroups.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, ["/", "/system.slice", "/system.slice/nginx.service"]);
+
+        let (top, slice, service) = (&cgroups[0], &cgroups[1], &cgroups[2]);
+        assert_eq!((top.cpu_usage, top.mem, top.mem_max, top.cpu_max), (9000, None, None, None));
+        assert_eq!(top.procs, [1, 12]);
+
+        assert_eq!((slice.cpu_usage, slice.cpu_throttled, slice.cpu_max), (5000, 300, Some(0.5)));
+        assert_eq!((slice.mem, slice.mem_max), (Some(4096), None));
+        assert_eq!((slice.t_read, slice.t_written), (1100, 2200));
+        assert_eq!((slice.pids, slice.pids_max), (Some(3), None));
+        assert_eq!(slice.procs, [10]);
+
+        assert_eq!((service.cpu_max, service.mem_max), (None, Some(8192)));
+        assert_eq!(service.procs, [11]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+use tokio::sync::mpsc;
+use anyhow::Error;
+use crate::{collectors::pause, config::config, models::{Component, Data}, state::AppState, Kind, APPSTATE, COMPONENTS};
+
+/// Temperature sensors, shown with the cpus. Machines without any, like most VMs, get an empty list.
+pub async fn main(tx: mpsc::Sender<Data>, refr_tx: mpsc::Sender<Kind>) -> Result<(), Error>{
+    let mut warned = false;
+    loop{
+        refr_tx.send(Kind::Components).await?; // refresh the data
+
+        let state: AppState;
+        if let Some(appstate) = APPSTATE.get(){
+            state = *appstate.read().await;
+            if state == AppState::ShuttingDown || !config().collectors.components.enabled{
+                break;
+            }
+        }
+        else{
+            pause(config().collectors.components.interval(true)).await;
+            continue; // wait until appstate is available
+        }
+
+        let mut comp_vec = Vec::new();
+        if let Some(components) = COMPONENTS.get(){
+            let components = match components.read(){
+                Ok(data) => data,
+                Err(poisoned) => {
+                    eprintln!("FATAL: COMPONENTS lock was poisoned!, Recovering");
+                    poisoned.into_inner()
+                }
+            };
+            for component in components.list(){
+                comp_vec.push(Component{
+                    label: component.label().to_string(),
+                    temperature: component.temperature(),
+                    max: component.max(),
+                    critical: component.critical()
+                });
+            }
+        }
+        if comp_vec.is_empty() && !warned{
+            eprintln!("No temperature sensors found, the components collector will send empty lists");
+            warned = true;
+        }
+        tx.send(Data::Components(comp_vec)).await?;
+
+        match state{
+            AppState::Cpu => {
+                pause(config().collectors.components.interval(true)).await;
+            }
+            _ => {
+                pause(config().collectors.components.interval(false)).await;
+            }
+        }
+    }
+    Ok(())
+}
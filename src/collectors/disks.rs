@@ -1,25 +1,36 @@
-use tokio::sync::mpsc;
+use std::{collections::HashMap, ffi::CString, fs, mem::MaybeUninit, os::unix::ffi::OsStrExt, path::{Path, PathBuf}};
+
+use tokio::{sync::mpsc, task::JoinHandle, time::{timeout, Duration}};
 use anyhow::Error;
-use crate::{models::{Data, DiskData}, state::AppState, Kind, APPSTATE, DISKS, DISK_DURATION, LDISK_DURATION};
+use crate::{collectors::pause, config::config, models::{Data, DiskData}, state::AppState, Kind, APPSTATE, DISKS};
+
+// How long the inode counts may take, statvfs blocks on a hung network mount.
+const INODES_TIMEOUT: Duration = Duration::from_secs(2);
+
+type Inodes = Vec<(Option<u64>, Option<u64>)>;
 
 pub async fn main(tx: mpsc::Sender<Data>, refr_tx: mpsc::Sender<Kind>) -> Result<(), Error>{
+    let mut stuck: Option<JoinHandle<Inodes>> = None; // Inode counts that timed out, none are started while they run
     loop{
         refr_tx.send(Kind::Disk).await?; // refresh the data
 
         let state: AppState;
         if let Some(appstate) = APPSTATE.get(){
             state = *appstate.read().await;
-            if state == AppState::ShuttingDown{
+            if state == AppState::ShuttingDown || !config().collectors.disks.enabled{
                 break;
             }
         }
         else{
+            pause(config().collectors.disks.interval(true)).await;
             continue;
         }
 
         let mut disc_vec = Vec::new();
+        let mut mounts = Vec::new();
 
-
+        let filter = config().disks.clone();
+        let options = mount_options();
         if let Some(disks) = DISKS.get(){
             let disks = match disks.read(){
                 Ok(data) => data,
@@ -28,7 +39,8 @@ pub async fn main(tx: mpsc::Sender<Data>, refr_tx: mpsc::Sender<Kind>) -> Result
                     poisoned.into_inner()
                 }
             };
-            for disk in &*disks{
+            for disk in disks.iter().filter(|d| filter.wanted(&d.file_system().to_string_lossy(), d.mount_point())){
+                mounts.push(disk.mount_point().to_path_buf());
                 disc_vec.push(DiskData { 
                     name: disk.name().to_string_lossy().to_string(), 
                     fs: disk.file_system().to_string_lossy().to_string(), 
@@ -41,20 +53,136 @@ pub async fn main(tx: mpsc::Sender<Data>, refr_tx: mpsc::Sender<Kind>) -> Result
                     t_written: disk.usage().total_written_bytes, 
                     written: disk.usage().written_bytes, 
                     t_read: disk.usage().total_read_bytes, 
-                    read: disk.usage().read_bytes 
+                    read: disk.usage().read_bytes,
+                    device: device(disk.name().as_ref()),
+                    options: options.get(disk.mount_point()).cloned().unwrap_or_default(),
+                    t_inodes: None,
+                    a_inodes: None
                 })
             }
         }
+
+        // Counted with the DISKS lock released, so a hung mount holds up neither the refresher nor blockio.
+        if stuck.as_ref().is_none_or(|s| s.is_finished()){
+            let mut counting = tokio::task::spawn_blocking(move || mounts.iter().map(|m| inodes(m)).collect::<Inodes>());
+            match timeout(INODES_TIMEOUT, &mut counting).await{
+                Ok(Ok(counted)) => {
+                    stuck = None;
+                    for (disk, (total, free)) in disc_vec.iter_mut().zip(counted){
+                        (disk.t_inodes, disk.a_inodes) = (total, free);
+                    }
+                }
+                Ok(Err(e)) => {
+                    stuck = None;
+                    eprintln!("Counting inodes failed, sending the disks without them: {}", e);
+                }
+                Err(_) => stuck = Some(counting)
+            }
+        }
         tx.send(Data::Disk(disc_vec)).await?;
         
         match state{
             AppState::Disk => {
-                tokio::time::sleep(DISK_DURATION).await;
+                pause(config().collectors.disks.interval(true)).await;
             }
             _ => {
-                tokio::time::sleep(LDISK_DURATION).await;
+                pause(config().collectors.disks.interval(false)).await;
             }
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// The kernel's name of the block device at `path`, following /dev/mapper and /dev/disk links.
+/// Filesystems like tmpfs or overlay have none.
+pub fn device(path: &Path) -> Option<String>{
+    let dev = fs::canonicalize(path).ok()?;
+    Some(dev.strip_prefix("/dev").ok()?.to_str()?.to_string())
+}
+
+/// The options of every mount point, the last mount wins where several are stacked.
+fn mount_options() -> HashMap<PathBuf, Vec<String>>{
+    parse_mounts(&fs::read_to_string("/proc/self/mounts").unwrap_or_default())
+}
+
+fn parse_mounts(text: &str) -> HashMap<PathBuf, Vec<String>>{
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ').skip(1);
+            let (mount, options) = (fields.next()?, fields.nth(1)?);
+            Some((PathBuf::from(unescape(mount)), options.split(',').map(str::to_string).collect()))
+        })
+        .collect()
+}
+
+/// /proc/self/mounts writes spaces, tabs, newlines and backslashes in paths as octal escapes like \040.
+fn unescape(field: &str) -> String{
+    let mut out = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(idx) = rest.find('\\'){
+        out.push_str(&rest[..idx]);
+        // from_str_radix would take a sign too, like \+12.
+        let octal = rest.get(idx + 1..idx + 4).filter(|o| o.bytes().all(|b| (b'0'..=b'7').contains(&b)));
+        match octal.and_then(|o| u8::from_str_radix(o, 8).ok()){
+            Some(byte) => {
+                out.push(byte as char);
+                rest = &rest[idx + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[idx + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Total and free inodes of the filesystem mounted at `mount`.
+fn inodes(mount: &Path) -> (Option<u64>, Option<u64>){
+    let Ok(path) = CString::new(mount.as_os_str().as_bytes()) else { return (None, None) };
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is a valid C string and `stat` is only read after statvfs filled it in.
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0{
+        return (None, None);
+    }
+    // SAFETY: statvfs returned 0, so it filled in all of `stat`.
+    let stat = unsafe { stat.assume_init() };
+    match stat.f_files{
+        0 => (None, None),
+        total => (Some(total), Some(stat.f_ffree))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn unescape_octal(){
+        assert_eq!(unescape(r"/mnt/my\040disk"), "/mnt/my disk");
+        assert_eq!(unescape(r"/mnt/a\011b\134c"), "/mnt/a\tb\\c");
+        assert_eq!(unescape(r"/mnt/plain"), "/mnt/plain");
+    }
+
+    #[test]
+    fn unescape_trailing_backslash(){
+        assert_eq!(unescape(r"/mnt/odd\"), r"/mnt/odd\");
+        assert_eq!(unescape(r"/mnt/odd\04"), r"/mnt/odd\04"); // Too short to be an escape
+        assert_eq!(unescape(r"/mnt/odd\9zz"), r"/mnt/odd\9zz");
+        assert_eq!(unescape(r"/mnt/odd\+12"), r"/mnt/odd\+12"); // Not octal digits, even if from_str_radix takes them
+    }
+
+    #[test]
+    fn stacked_mounts(){
+        let options = parse_mounts("\
+/dev/sda1 / ext4 rw,relatime 0 0
+/dev/sdb1 /mnt/my\\040disk vfat ro,noexec 0 0
+tmpfs /mnt/my\\040disk tmpfs rw,nosuid,size=1024k 0 0
+broken
+");
+        assert_eq!(options.len(), 2);
+        assert_eq!(options[Path::new("/")], ["rw", "relatime"]);
+        assert_eq!(options[Path::new("/mnt/my disk")], ["rw", "nosuid", "size=1024k"]); // The last mount wins
+    }
+}
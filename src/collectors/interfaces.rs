@@ -0,0 +1,52 @@
+use std::{fs, path::Path};
+
+use anyhow::Error;
+use sysinfo::Networks;
+use tokio::sync::mpsc;
+
+use crate::{collectors::pause, config::config, models::{Data, Interface}, state::AppState, APPSTATE};
+
+// The kernel's view of every interface, a directory each.
+const SYS_NET: &str = "/sys/class/net";
+
+/// Addresses, MAC, MTU and link of every interface, on a slower cadence than the networks counters.
+/// It keeps its own list apart from `NETWORKS`, which is not refreshed with the networks collector off.
+pub async fn main(tx: mpsc::Sender<Data>) -> Result<(), Error>{
+    let mut networks = Networks::new_with_refreshed_list();
+    loop{
+        let state: AppState;
+        if let Some(appstate) = APPSTATE.get(){
+            state = *appstate.read().await;
+            if state == AppState::ShuttingDown || !config().collectors.interfaces.enabled{
+                break;
+            }
+        }
+        else{
+            pause(config().collectors.interfaces.interval(true)).await;
+            continue;
+        }
+
+        networks.refresh(true); // Interfaces that went away are dropped
+        let mut interfaces = Vec::new();
+        for (name, data) in networks.iter(){
+            let mac = data.mac_address();
+            let sys = Path::new(SYS_NET).join(name);
+            let file = |f: &str| fs::read_to_string(sys.join(f)).ok().map(|s| s.trim().to_string());
+            interfaces.push(Interface{
+                name: name.to_string(),
+                mac: (!mac.is_unspecified()).then(|| mac.to_string()),
+                addresses: data.ip_networks().iter().map(|ip| ip.to_string()).collect(),
+                mtu: data.mtu(),
+                state: file("operstate").unwrap_or("unknown".to_string()),
+                // Both files can't be read while the interface is down, and speed is -1 where it is not known.
+                carrier: file("carrier").map(|c| c == "1"),
+                speed: file("speed").and_then(|s| s.parse::<i64>().ok()).and_then(|s| u64::try_from(s).ok())
+            });
+        }
+        interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+        tx.send(Data::Interfaces(interfaces)).await?;
+
+        pause(config().collectors.interfaces.interval(state == AppState::Network)).await;
+    }
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use anyhow::Error;
 use tokio::sync::mpsc;
 
-use crate::{models::{Data, Memory}, state::AppState, Kind, APPSTATE, LMEM_DURATION, MEM_DURATION, SYSTEM};
+use crate::{collectors::pause, config::config, models::{Data, Memory}, state::AppState, Kind, APPSTATE, SYSTEM};
 
 pub async fn main(tx: mpsc::Sender<Data>, rtx: mpsc::Sender<Kind>) -> Result<(), Error>{
     loop{
@@ -12,10 +12,10 @@ pub async fn main(tx: mpsc::Sender<Data>, rtx: mpsc::Sender<Kind>) -> Result<(),
 
         if let Some(appstate) = APPSTATE.get(){ // Make sure that Appstate is available
             state = *appstate.read().await;
-            if state == AppState::ShuttingDown{ break; } // Graceful Shutdown
+            if state == AppState::ShuttingDown || !config().collectors.memory.enabled{ break; } // Graceful Shutdown
         }
         else{
-            tokio::time::sleep(MEM_DURATION).await;
+            pause(config().collectors.memory.interval(true)).await;
             continue; // Wait until appstate is available
         }
         if let Some(sys) = SYSTEM.get(){
@@ -41,10 +41,10 @@ pub async fn main(tx: mpsc::Sender<Data>, rtx: mpsc::Sender<Kind>) -> Result<(),
         tx.send(Data::Memory(mem)).await?;
         match state{
             AppState::Memory => {
-                tokio::time::sleep(MEM_DURATION).await;
+                pause(config().collectors.memory.interval(true)).await;
             }
             _ => {
-                tokio::time::sleep(LMEM_DURATION).await;
+                pause(config().collectors.memory.interval(false)).await;
             }
         }
     }
@@ -1,27 +1,32 @@
-use tokio::sync::mpsc;
-use sysinfo::{System, MINIMUM_CPU_UPDATE_INTERVAL};
+use tokio::{sync::mpsc, time::Instant};
+use sysinfo::System;
 use anyhow::Error;
 
-use crate::{models::{Cpus, Data, Meta}, state::AppState, Kind, APPSTATE, SYSTEM};
+use crate::{collectors::pause, config::config, models::{Cpus, Data, Meta}, state::AppState, Kind, APPSTATE, SYSTEM};
 
 pub async fn main(tx: mpsc::Sender<Data>, refr_tx: mpsc::Sender<Kind>) -> Result<(), Error>{
+    // These do not change while the agent runs.
+    let arch = System::cpu_arch();
+    let distro = System::distribution_id();
+    let n_core = System::physical_core_count();
+    let mut meta_sent: Option<Instant> = None;
     loop{
         refr_tx.send(Kind::Cpu).await?; // refresh the data
 
         let state: AppState;
         if let Some(appstate) = APPSTATE.get(){
             state = *appstate.read().await;
-            if state == AppState::ShuttingDown{ break;}
+            if state == AppState::ShuttingDown || !config().collectors.meta.enabled{ break;}
         }
         else{
-            tokio::time::sleep(MINIMUM_CPU_UPDATE_INTERVAL).await;
+            pause(config().collectors.meta.interval(true)).await;
             continue; // wait until appstate is available
         }
         let mut vec_cpu = Vec::new();
 
         let (meta_data, cpu_data) = {
             if let Some(sys) = SYSTEM.get(){
-                let ref sys = match sys.read(){
+                let sys = &match sys.read(){
                     Ok(data) => data,
                     Err(poisoned) => {
                         eprintln!("FATAL: SYSTEM lock was poisoned!, Recovering");
@@ -37,8 +42,17 @@ pub async fn main(tx: mpsc::Sender<Data>, refr_tx: mpsc::Sender<Kind>) -> Result
                     os: System::os_version().unwrap_or("".to_string()),
                     host_name: System::host_name().unwrap_or("".to_string()),
                     n_cpu: sys.cpus().len(),
+                    n_core,
                     n_proc: sys.processes().len(),
-                    glob_cpu: sys.global_cpu_usage()
+                    glob_cpu: sys.global_cpu_usage(),
+                    load: {
+                        let load = System::load_average();
+                        [load.one, load.five, load.fifteen]
+                    },
+                    uptime: System::uptime(),
+                    boot_time: System::boot_time(),
+                    arch: arch.clone(),
+                    distro: distro.clone()
                 };
                 for cpu in sys.cpus(){
                     vec_cpu.push(Cpus{
@@ -54,19 +68,20 @@ pub async fn main(tx: mpsc::Sender<Data>, refr_tx: mpsc::Sender<Kind>) -> Result
                 continue;
             }
         };
+        // Meta changes slowly, so unless it is focused it only goes out once per meta.slow_interval_ms.
+        let slow = config().meta.slow_interval();
+        if state == AppState::Meta || meta_sent.is_none_or(|t| t.elapsed() >= slow){
+            tx.send(Data::Meta(meta_data)).await?;
+            meta_sent = Some(Instant::now());
+        }
         match state {
-            AppState::Meta => {
-                tx.send(Data::Meta(meta_data)).await?;
-                tx.send(Data::Cpus(cpu_data)).await?;
-                tokio::time::sleep(MINIMUM_CPU_UPDATE_INTERVAL).await; 
-            }
-            AppState::Cpu => {
+            AppState::Meta | AppState::Cpu => {
                 tx.send(Data::Cpus(cpu_data)).await?;
-                tokio::time::sleep(MINIMUM_CPU_UPDATE_INTERVAL).await;
+                pause(config().collectors.meta.interval(true)).await;
             }
             _ => { // We still need to send the data, but at slower refresh rates.
                 tx.send(Data::Cpus(cpu_data)).await?;
-                tokio::time::sleep(MINIMUM_CPU_UPDATE_INTERVAL*2).await;
+                pause(config().collectors.meta.interval(false)).await;
             }
         }
     }
@@ -4,3 +4,56 @@ pub mod disks;
 pub mod processes;
 pub mod sockets;
 pub mod meta;
+pub mod components;
+pub mod pressure;
+pub mod cgroups;
+pub mod units;
+pub mod blockio;
+pub mod interfaces;
+
+use tokio::{sync::mpsc, task::JoinHandle, time::Duration};
+
+use crate::{config, models::Data, Kind, SHUTDOWN};
+
+/// Sleep for `duration`, or less if the agent starts shutting down or the config is reloaded in the meantime.
+pub async fn pause(duration: Duration){
+    let mut cfg_rx = config::subscribe();
+    let Some(shutdown) = SHUTDOWN.get() else {
+        tokio::select!{
+            _ = tokio::time::sleep(duration) => {}
+            _ = cfg_rx.changed() => {}
+        }
+        return;
+    };
+    let mut rx = shutdown.subscribe();
+    tokio::select!{
+        _ = tokio::time::sleep(duration) => {}
+        _ = rx.wait_for(|down| *down) => {}
+        _ = cfg_rx.changed() => {}
+    }
+}
+
+/// Start the collector named like its `collectors.<name>` config table.
+/// It returns by itself once it is disabled or the agent shuts down.
+pub fn spawn(name: &'static str, tx: mpsc::Sender<Data>, refr_tx: mpsc::Sender<Kind>) -> JoinHandle<()>{
+    tokio::spawn(async move{
+        let res = match name{
+            "meta" => meta::main(tx, refr_tx).await,
+            "disks" => disks::main(tx, refr_tx).await,
+            "memory" => memory::main(tx, refr_tx).await,
+            "networks" => networks::main(tx, refr_tx).await,
+            "sockets" => sockets::main(tx).await,
+            "processes" => processes::main(tx, refr_tx).await,
+            "components" => components::main(tx, refr_tx).await,
+            "pressure" => pressure::main(tx).await,
+            "cgroups" => cgroups::main(tx).await,
+            "units" => units::main(tx).await,
+            "blockio" => blockio::main(tx).await,
+            "interfaces" => interfaces::main(tx).await,
+            _ => Err(anyhow::anyhow!("No such collector"))
+        };
+        if let Err(e) = res{
+            eprint!("The {} collector panicked: {:?}", name, e);
+        }
+    })
+}
@@ -1,7 +1,7 @@
 use anyhow::Error;
 use tokio::sync::mpsc;
 
-use crate::{models::{Data, Networks}, state::AppState, Kind, APPSTATE, LNET_DURATION, NETWORKS, NET_DURATION};
+use crate::{collectors::pause, config::config, models::{Data, Networks}, state::AppState, Kind, APPSTATE, NETWORKS};
 
 pub async fn main(tx: mpsc::Sender<Data>, refr_tx: mpsc::Sender<Kind>) -> Result<(), Error>{
     loop{
@@ -12,10 +12,10 @@ pub async fn main(tx: mpsc::Sender<Data>, refr_tx: mpsc::Sender<Kind>) -> Result
 
         if let Some(appstate) = APPSTATE.get(){ // Make sure that Appstate is available
             state = *appstate.read().await;
-            if state == AppState::ShuttingDown{ break; } // Graceful Shutdown
+            if state == AppState::ShuttingDown || !config().collectors.networks.enabled{ break; } // Graceful Shutdown
         }
         else{
-            tokio::time::sleep(NET_DURATION).await;
+            pause(config().collectors.networks.interval(true)).await;
             continue; // Wait until appstate is available
         }
 
@@ -50,10 +50,10 @@ pub async fn main(tx: mpsc::Sender<Data>, refr_tx: mpsc::Sender<Kind>) -> Result
         
         match state{
             AppState::Network => {
-                tokio::time::sleep(NET_DURATION).await;
+                pause(config().collectors.networks.interval(true)).await;
             }
             _ => {
-                tokio::time::sleep(LNET_DURATION).await; // Slower refresh rate
+                pause(config().collectors.networks.interval(false)).await; // Slower refresh rate
             }
         }
     }
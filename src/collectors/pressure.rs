@@ -0,0 +1,197 @@
+use std::{
+    collections::HashSet,
+    fs::OpenOptions,
+    io::{self, Write},
+    os::fd::AsRawFd,
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+    thread
+};
+
+use anyhow::{anyhow, bail, Context, Error};
+use tokio::sync::mpsc;
+
+use crate::{
+    collectors::pause,
+    config::{config, PsiTrigger, Stall},
+    models::{Data, Pressure, PressureResource, Stalls},
+    state::AppState, APPSTATE, SHUTDOWN
+};
+
+// Where the kernel publishes PSI, a file per resource.
+const PSI_DIR: &str = "/proc/pressure";
+const RESOURCES: [PressureResource; 3] = [PressureResource::Cpu, PressureResource::Memory, PressureResource::Io];
+// How long a trigger waits for an event before checking whether it should stop, in milliseconds.
+const TRIGGER_POLL: i32 = 500;
+
+/// Pressure Stall Information from /proc/pressure, shown with the system overview.
+/// `[psi]` triggers make it send as soon as stalls spike instead of at its next interval.
+pub async fn main(tx: mpsc::Sender<Data>) -> Result<(), Error>{
+    if let Err(e) = read(PressureResource::Cpu){
+        eprintln!("PSI is not available, stopping the pressure collector: {:#}", e);
+        return Ok(());
+    }
+    let (trig_tx, mut trig_rx) = mpsc::channel(16);
+    let mut armed: Option<(Vec<PsiTrigger>, Arc<AtomicBool>)> = None; // The triggers watched and how to stop them
+    let mut triggered = HashSet::new();
+    loop{
+        let state: AppState;
+        if let Some(appstate) = APPSTATE.get(){
+            state = *appstate.read().await;
+            if state == AppState::ShuttingDown || !config().collectors.pressure.enabled{
+                break;
+            }
+        }
+        else{
+            pause(config().collectors.pressure.interval(true)).await;
+            continue;
+        }
+
+        // Triggers are armed again whenever a reload changes them.
+        let cfg = config();
+        if armed.as_ref().map(|(triggers, _)| triggers) != Some(&cfg.psi.triggers){
+            if let Some((_, stop)) = armed.take(){
+                stop.store(true, Ordering::Relaxed);
+            }
+            let stop = Arc::new(AtomicBool::new(false));
+            for trigger in &cfg.psi.triggers{
+                let (trigger, tx, stop) = (trigger.clone(), trig_tx.clone(), stop.clone());
+                thread::spawn(move ||{
+                    if let Err(e) = watch(&trigger, tx, stop){
+                        eprintln!("The PSI trigger on {:?} stopped: {:#}", trigger.resource, e);
+                    }
+                });
+            }
+            armed = Some((cfg.psi.triggers.clone(), stop));
+        }
+
+        let mut pressure = Vec::with_capacity(RESOURCES.len());
+        for resource in RESOURCES{
+            match read(resource){
+                Ok(p) => pressure.push(Pressure { triggered: triggered.contains(&resource), ..p }),
+                Err(e) => eprintln!("Failed to read the {:?} pressure: {:#}", resource, e)
+            }
+        }
+        triggered.clear();
+        tx.send(Data::Pressure(pressure)).await?;
+
+        tokio::select!{
+            _ = pause(config().collectors.pressure.interval(state == AppState::Meta)) => {}
+            Some(resource) = trig_rx.recv() => {
+                triggered.insert(resource);
+            }
+        }
+        while let Ok(resource) = trig_rx.try_recv(){
+            triggered.insert(resource);
+        }
+    }
+    if let Some((_, stop)) = armed{
+        stop.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+fn path(resource: PressureResource) -> String{
+    format!("{}/{}", PSI_DIR, resource.name())
+}
+
+fn read(resource: PressureResource) -> Result<Pressure, Error>{
+    let path = path(resource);
+    let text = std::fs::read_to_string(&path)?;
+    parse(resource, &text).with_context(|| format!("Failed to parse {}", path))
+}
+
+/// The pressure of `resource` out of its /proc/pressure file, the cpu one has no `full` line on older kernels.
+fn parse(resource: PressureResource, text: &str) -> Result<Pressure, Error>{
+    let (mut some, mut full) = (None, None);
+    for line in text.lines(){
+        let mut fields = line.split_whitespace();
+        let kind = fields.next();
+        let stalls = stalls(fields).ok_or(anyhow!("Unexpected line: {}", line))?;
+        match kind{
+            Some("some") => some = Some(stalls),
+            Some("full") => full = Some(stalls),
+            _ => {}
+        }
+    }
+    Ok(Pressure { resource, some: some.ok_or(anyhow!("No some line"))?, full, triggered: false })
+}
+
+/// The `avg10=0.12 avg60=0.05 avg300=0.01 total=1234` fields of a line.
+fn stalls<'a>(fields: impl Iterator<Item = &'a str>) -> Option<Stalls>{
+    let mut stalls = Stalls::default();
+    for field in fields{
+        let (key, value) = field.split_once('=')?;
+        match key{
+            "avg10" => stalls.avg10 = value.parse().ok()?,
+            "avg60" => stalls.avg60 = value.parse().ok()?,
+            "avg300" => stalls.avg300 = value.parse().ok()?,
+            "total" => stalls.total = value.parse().ok()?,
+            _ => {}
+        }
+    }
+    Some(stalls)
+}
+
+/// Register `trigger` with the kernel and pass its resource to `tx` every time it fires, until `stop` is set.
+fn watch(trigger: &PsiTrigger, tx: mpsc::Sender<PressureResource>, stop: Arc<AtomicBool>) -> Result<(), Error>{
+    let path = path(trigger.resource);
+    let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+    let stall = match trigger.stall{
+        Stall::Partial => "some",
+        Stall::Full => "full"
+    };
+    // The kernel takes the trigger in a single write, in microseconds, and keeps it while the file is open.
+    file.write_all(format!("{} {} {}\0", stall, trigger.stall_ms * 1000, trigger.window_ms * 1000).as_bytes())
+        .with_context(|| match trigger.window_ms % 2000{
+            0 => "The kernel refused the trigger".to_string(),
+            _ => "The kernel refused the trigger, without CAP_SYS_RESOURCE window_ms must be a multiple of 2000".to_string()
+        })?;
+
+    let shutting_down = || SHUTDOWN.get().is_some_and(|s| *s.borrow());
+    while !stop.load(Ordering::Relaxed) && !shutting_down(){
+        let mut fd = libc::pollfd { fd: file.as_raw_fd(), events: libc::POLLPRI, revents: 0 };
+        // SAFETY: `fd` is a single valid pollfd that outlives the call.
+        let n = unsafe { libc::poll(&mut fd, 1, TRIGGER_POLL) };
+        if n < 0{
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted{
+                continue;
+            }
+            return Err(e.into());
+        }
+        if fd.revents & libc::POLLERR != 0{
+            bail!("{} stopped delivering events", path);
+        }
+        if fd.revents & libc::POLLPRI != 0{
+            let _ = tx.try_send(trigger.resource); // A full channel already has the collector on its way
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const SOME: &str = "some avg10=1.50 avg60=0.75 avg300=0.10 total=123456";
+
+    #[test]
+    fn with_and_without_full(){
+        let p = parse(PressureResource::Cpu, &format!("{}\nfull avg10=0.50 avg60=0.25 avg300=0.00 total=4567\n", SOME)).unwrap();
+        assert_eq!((p.some.avg10, p.some.avg60, p.some.avg300, p.some.total), (1.5, 0.75, 0.1, 123456));
+        let full = p.full.unwrap();
+        assert_eq!((full.avg10, full.total), (0.5, 4567));
+        assert!(!p.triggered);
+
+        let p = parse(PressureResource::Cpu, &format!("{}\n", SOME)).unwrap();
+        assert_eq!(p.some.total, 123456);
+        assert!(p.full.is_none());
+    }
+
+    #[test]
+    fn malformed(){
+        assert!(parse(PressureResource::Cpu, &format!("{}\nfull avg10=high total=1\n", SOME)).is_err());
+        assert!(parse(PressureResource::Cpu, "some avg10 0.5\n").is_err());
+        assert!(parse(PressureResource::Cpu, "full avg10=0.50 avg60=0.25 avg300=0.00 total=4567\n").is_err()); // No some line
+    }
+}
@@ -1,21 +1,32 @@
+use std::collections::HashMap;
+
 use anyhow::Error;
+use sysinfo::ThreadKind;
 use tokio::sync::mpsc;
 
-use crate::{models::{Data, Process}, state::AppState, Kind, APPSTATE, LPROC_DURATION, PROC_DURATION, SYSTEM};
+use crate::{
+    collectors::pause, config::{config, ProcessDetailLevel}, history::now_ms,
+    models::{Data, Lifecycle, Process, ProcessDetail, ProcessEvent}, state::AppState, Kind, APPSTATE, SYSTEM
+};
 
 pub async fn main(tx: mpsc::Sender<Data>, rtx: mpsc::Sender<Kind>) -> Result<(), Error>{
+    // The last snapshot by pid, as the start event each process would have. None until the first one.
+    let mut seen: Option<HashMap<u32, ProcessEvent>> = None;
     loop{
         rtx.send(Kind::Proc).await?;
 
         let mut proc_vec = Vec::new();
+        let mut current = HashMap::new();
+        let t_ms = now_ms();
+        let full = config().processes.detail == ProcessDetailLevel::Full;
         let state: AppState;
 
         if let Some(appstate) = APPSTATE.get(){ // Make sure that Appstate is available
             state = *appstate.read().await;
-            if state == AppState::ShuttingDown{ break; } // Graceful Shutdown
+            if state == AppState::ShuttingDown || !config().collectors.processes.enabled{ break; } // Graceful Shutdown
         }
         else{
-            tokio::time::sleep(PROC_DURATION).await;
+            pause(config().collectors.processes.interval(true)).await;
             continue; // Wait until appstate is available
         }
         if let Some(sys) = SYSTEM.get(){
@@ -27,6 +38,24 @@ pub async fn main(tx: mpsc::Sender<Data>, rtx: mpsc::Sender<Kind>) -> Result<(),
                 }
             };
             for proc in sys.processes(){
+                // Userland threads are listed as processes too, but only processes start and exit.
+                // Kernel threads count as processes here as they do in the table.
+                let thread = proc.1.thread_kind() == Some(ThreadKind::Userland);
+                if !thread{
+                    current.insert(proc.0.as_u32(), ProcessEvent{
+                        event: Lifecycle::Started,
+                        pid: proc.0.as_u32(),
+                        name: proc.1.name().to_string_lossy().to_string(),
+                        exe: proc.1.exe().map(|path| path.to_string_lossy().to_string()).unwrap_or_default(),
+                        parent: proc.1.parent().map(|pid| pid.as_u32()),
+                        user_id: proc.1.user_id().map(|uid| uid.to_string()),
+                        start_time: proc.1.start_time(),
+                        run_time: proc.1.run_time(),
+                        cpu: proc.1.cpu_usage(),
+                        mem: proc.1.memory(),
+                        t_ms
+                    });
+                }
                 proc_vec.push(Process{
                     pid: proc.0.as_u32(),
                     name: proc.1.name().to_string_lossy().to_string(),
@@ -37,27 +66,113 @@ pub async fn main(tx: mpsc::Sender<Data>, rtx: mpsc::Sender<Kind>) -> Result<(),
                     cpu: proc.1.cpu_usage(),
                     mem: proc.1.memory(),
                     status: proc.1.status().to_string(),
-                    cmd: proc.1.cmd().into_iter().map(|cmd| cmd.to_string_lossy().to_string()).collect(),
-                    parent: match proc.1.parent(){
-                        Some(pid) => Some(pid.as_u32()),
-                        None => None
-                    },
-                    user_id: match proc.1.user_id(){
-                        Some(uid) => Some(uid.to_string()),
-                        None => None
-                    }
+                    cmd: proc.1.cmd().iter().map(|cmd| cmd.to_string_lossy()).collect::<Vec<_>>().join(" "),
+                    parent: proc.1.parent().map(|pid| pid.as_u32()),
+                    user_id: proc.1.user_id().map(|uid| uid.to_string()),
+                    thread,
+                    detail: full.then(|| detail(proc.0.as_u32(), proc.1))
                 });
             }
         }
         tx.send(Data::Process(proc_vec)).await?;
+        if let Some(seen) = &seen{
+            let events = lifecycle(seen, &current, t_ms);
+            if !events.is_empty(){
+                tx.send(Data::ProcessEvents(events)).await?;
+            }
+        }
+        seen = Some(current);
         match state{
             AppState::Processes => {
-                tokio::time::sleep(PROC_DURATION).await;
+                pause(config().collectors.processes.interval(true)).await;
             }
             _ => {
-                tokio::time::sleep(LPROC_DURATION).await;
+                pause(config().collectors.processes.interval(false)).await;
             }
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+fn detail(pid: u32, proc: &sysinfo::Process) -> ProcessDetail{
+    let disk = proc.disk_usage();
+    let thread = proc.thread_kind().is_some();
+    ProcessDetail{
+        read: disk.read_bytes,
+        written: disk.written_bytes,
+        t_read: disk.total_read_bytes,
+        t_written: disk.total_written_bytes,
+        virtual_mem: proc.virtual_memory(),
+        threads: (!thread).then(|| proc.tasks().map_or(1, |tasks| tasks.len() as u32 + 1)), // Tasks leave the main thread out
+        start_time: proc.start_time(),
+        cwd: proc.cwd().map(|path| path.to_string_lossy().to_string()).unwrap_or_default(),
+        fds: if thread { None } else { std::fs::read_dir(format!("/proc/{}/fd", pid)).ok().map(|fds| fds.count() as u32) }
+    }
+}
+
+/// What started and exited from `before` to `now`. A pid with a new start time is a new process.
+fn lifecycle(before: &HashMap<u32, ProcessEvent>, now: &HashMap<u32, ProcessEvent>, t_ms: u64) -> Vec<ProcessEvent>{
+    let same = |a: &ProcessEvent, b: Option<&ProcessEvent>| b.is_some_and(|b| b.start_time == a.start_time);
+    let mut events = Vec::new();
+    for (pid, proc) in before{
+        if !same(proc, now.get(pid)){
+            events.push(ProcessEvent{
+                event: Lifecycle::Exited,
+                run_time: (t_ms / 1000).saturating_sub(proc.start_time).max(proc.run_time),
+                t_ms,
+                ..proc.clone()
+            });
+        }
+    }
+    for (pid, proc) in now{
+        if !same(proc, before.get(pid)){
+            events.push(proc.clone());
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn started(pid: u32, name: &str, start_time: u64) -> (u32, ProcessEvent){
+        (pid, ProcessEvent{
+            event: Lifecycle::Started, pid, name: name.to_string(), exe: String::new(), parent: Some(1), user_id: None,
+            start_time, run_time: 0, cpu: 1.5, mem: 4096, t_ms: start_time * 1000
+        })
+    }
+
+    fn events(before: &[(u32, ProcessEvent)], now: &[(u32, ProcessEvent)], t_ms: u64) -> Vec<(Lifecycle, u32, String, u64)>{
+        let (before, now) = (before.iter().cloned().collect(), now.iter().cloned().collect());
+        let mut events: Vec<_> = lifecycle(&before, &now, t_ms).into_iter().map(|e| (e.event, e.pid, e.name, e.run_time)).collect();
+        events.sort_by_key(|(event, pid, ..)| (*event == Lifecycle::Started, *pid));
+        events
+    }
+
+    #[test]
+    fn unchanged(){
+        let procs = [started(1, "init", 100), started(42, "cron", 200)];
+        assert!(events(&procs, &procs, 300_000).is_empty());
+    }
+
+    #[test]
+    fn exits_and_starts(){
+        let before = [started(1, "init", 100), started(42, "backup", 200)];
+        let now = [started(1, "init", 100), started(43, "worker", 290)];
+        assert_eq!(events(&before, &now, 300_000), [
+            (Lifecycle::Exited, 42, "backup".to_string(), 100), // Ran until it was noticed gone
+            (Lifecycle::Started, 43, "worker".to_string(), 0)
+        ]);
+    }
+
+    #[test]
+    fn pid_reuse(){
+        let before = [started(42, "backup", 200)];
+        let now = [started(42, "worker", 290)];
+        assert_eq!(events(&before, &now, 300_000), [
+            (Lifecycle::Exited, 42, "backup".to_string(), 100),
+            (Lifecycle::Started, 42, "worker".to_string(), 0)
+        ]);
+    }
+}
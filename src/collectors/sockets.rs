@@ -2,7 +2,7 @@ use anyhow::Error;
 use tokio::sync::mpsc;
 use netstat2::*;
 
-use crate::{models::Data, state::AppState, APPSTATE, LSOCKET_DURATION, SOCKET_DURATION};
+use crate::{collectors::pause, config::config, models::Data, state::AppState, APPSTATE};
 
 pub async fn main(tx: mpsc::Sender<Data>) -> Result<(), Error>{
     let af_flags = AddressFamilyFlags::IPV4 | AddressFamilyFlags::IPV6;
@@ -12,10 +12,10 @@ pub async fn main(tx: mpsc::Sender<Data>) -> Result<(), Error>{
         let state: AppState;
         if let Some(appstate) = APPSTATE.get(){
             state = *appstate.read().await;
-            if state == AppState::ShuttingDown{ break; } // Graceful Shutdown
+            if state == AppState::ShuttingDown || !config().collectors.sockets.enabled{ break; } // Graceful Shutdown
         }
         else{
-            tokio::time::sleep(SOCKET_DURATION).await;
+            pause(config().collectors.sockets.interval(true)).await;
             continue; // Wait until appstate is available
         }
 
@@ -47,10 +47,10 @@ pub async fn main(tx: mpsc::Sender<Data>) -> Result<(), Error>{
 
         match state{
             AppState::Sockets => {
-                tokio::time::sleep(SOCKET_DURATION).await;
+                pause(config().collectors.sockets.interval(true)).await;
             }
             _ => {
-                tokio::time::sleep(LSOCKET_DURATION).await; // Refresh slowly
+                pause(config().collectors.sockets.interval(false)).await; // Refresh slowly
             }
         }
     }
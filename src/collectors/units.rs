@@ -0,0 +1,126 @@
+use anyhow::{anyhow, bail, Context, Error};
+use tokio::{process::Command, sync::mpsc, time::{timeout, Duration}};
+
+use crate::{collectors::pause, config::{config, Systemd, UnitBackend}, models::{Data, Unit}, state::AppState, APPSTATE};
+
+// What `systemctl show` is asked for, one Key=Value line each.
+const PROPERTIES: &str = "Id,LoadState,ActiveState,SubState,MainPID,NRestarts";
+
+/// Systemd units and their states, shown with the system overview.
+/// Clients link them to their processes by `main_pid`.
+pub async fn main(tx: mpsc::Sender<Data>) -> Result<(), Error>{
+    let mut failing: Option<String> = None; // The last error, so a broken backend is reported once
+    loop{
+        let state: AppState;
+        if let Some(appstate) = APPSTATE.get(){
+            state = *appstate.read().await;
+            if state == AppState::ShuttingDown || !config().collectors.units.enabled{
+                break;
+            }
+        }
+        else{
+            pause(config().collectors.units.interval(true)).await;
+            continue;
+        }
+
+        match units(&config().systemd).await{
+            Ok(units) => {
+                failing = None;
+                tx.send(Data::Units(units)).await?;
+            }
+            Err(e) => {
+                let e = format!("{:#}", e);
+                if failing.as_ref() != Some(&e){
+                    eprintln!("Failed to list the systemd units, retrying every interval: {}", e);
+                    failing = Some(e);
+                }
+            }
+        }
+
+        pause(config().collectors.units.interval(state == AppState::Meta)).await;
+    }
+    Ok(())
+}
+
+async fn units(systemd: &Systemd) -> Result<Vec<Unit>, Error>{
+    let text = match &systemd.backend{
+        UnitBackend::Systemctl { timeout_ms } => {
+            let patterns = systemd.types.iter().map(|t| format!("*.{}", t));
+            let output = Command::new("systemctl")
+                .args(["show", "--no-pager", "--property", PROPERTIES])
+                .args(patterns)
+                .kill_on_drop(true)
+                .output();
+            let output = timeout(Duration::from_millis(*timeout_ms), output).await
+                .map_err(|_| anyhow!("systemctl did not answer within {}ms", timeout_ms))?
+                .context("Failed to run systemctl")?;
+            if !output.status.success(){
+                bail!("systemctl failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+            }
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        }
+        UnitBackend::Fixture { path } => {
+            tokio::fs::read_to_string(path).await.with_context(|| format!("Failed to read {}", path.display()))?
+        }
+    };
+    Ok(filter(parse(&text), &systemd.types))
+}
+
+/// The units of `types`, sorted by name. A fixture may hold any type.
+fn filter(mut units: Vec<Unit>, types: &[String]) -> Vec<Unit>{
+    units.retain(|u| types.iter().any(|t| u.name.rsplit_once('.').is_some_and(|(_, kind)| kind == t)));
+    units.sort_by(|a, b| a.name.cmp(&b.name));
+    units
+}
+
+/// Units out of `systemctl show` output, a block of Key=Value lines per unit with empty lines between them.
+fn parse(text: &str) -> Vec<Unit>{
+    text.split("\n\n")
+        .filter_map(|block| {
+            let mut unit = Unit{
+                name: String::new(),
+                load: String::new(),
+                active: String::new(),
+                sub: String::new(),
+                main_pid: None,
+                restarts: None
+            };
+            for (key, value) in block.lines().filter_map(|l| l.split_once('=')){
+                match key{
+                    "Id" => unit.name = value.to_string(),
+                    "LoadState" => unit.load = value.to_string(),
+                    "ActiveState" => unit.active = value.to_string(),
+                    "SubState" => unit.sub = value.to_string(),
+                    "MainPID" => unit.main_pid = value.parse().ok().filter(|pid| *pid != 0),
+                    "NRestarts" => unit.restarts = value.parse().ok(),
+                    _ => {}
+                }
+            }
+            (!unit.name.is_empty()).then_some(unit)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn fixture(){
+        let units = filter(parse(include_str!("../../fixtures/systemd-units.txt")), &["service".to_string()]);
+        let names: Vec<&str> = units.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, ["backup.service", "cron.service", "legacy.service", "nginx.service", "worker.service"]);
+        let unit = |name: &str| units.iter().find(|u| u.name == name).unwrap();
+
+        let nginx = unit("nginx.service");
+        assert_eq!((nginx.active.as_str(), nginx.sub.as_str()), ("failed", "failed"));
+
+        let worker = unit("worker.service");
+        assert_eq!((worker.active.as_str(), worker.sub.as_str(), worker.restarts), ("activating", "auto-restart", Some(12)));
+
+        let legacy = unit("legacy.service");
+        assert_eq!((legacy.load.as_str(), legacy.main_pid, legacy.restarts), ("not-found", None, None));
+
+        assert_eq!(unit("cron.service").main_pid, Some(1));
+    }
+}
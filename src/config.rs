@@ -0,0 +1,729 @@
+use std::{net::SocketAddr, path::{Path, PathBuf}, sync::{Arc, OnceLock}, time::SystemTime};
+
+use anyhow::{anyhow, bail, Context, Error};
+use iceoryx2::prelude::{NodeName, ServiceName};
+use serde::{Deserialize, Serialize};
+use sysinfo::MINIMUM_CPU_UPDATE_INTERVAL;
+use tokio::{sync::watch, time::Duration};
+
+use crate::{
+    alerts::METRICS, models::PressureResource, BLOCK_DURATION, CGROUP_DURATION, COMPONENT_DURATION, DISK_DURATION, IFACE_DURATION,
+    LBLOCK_DURATION, LCGROUP_DURATION, LCOMPONENT_DURATION, LDISK_DURATION, LIFACE_DURATION, LMEM_DURATION, LNET_DURATION, LPROC_DURATION,
+    LPSI_DURATION, LSOCKET_DURATION, LUNIT_DURATION, MAX_SIZE, MEM_DURATION, NET_DURATION, PROC_DURATION, PROC_KEYFRAME_EVERY, PSI_DURATION,
+    SOCKET_DURATION, SUB_BUFFER_SIZE, UNIT_DURATION
+};
+
+// Holds the current config, replaced as a whole on reload.
+pub static CONFIG: OnceLock<watch::Sender<Arc<Config>>> = OnceLock::new();
+
+// Used when neither `--config` nor $AWARE_CONFIG is given and the file exists.
+const DEFAULT_PATH: &str = "agent.toml";
+const PATH_ENV: &str = "AWARE_CONFIG";
+// `AWARE__COLLECTORS__MEMORY__ENABLED=false` overrides `collectors.memory.enabled`.
+const ENV_PREFIX: &str = "AWARE__";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config{
+    pub transport: Transport,
+    pub collectors: Collectors,
+    pub exporter: Exporter,
+    pub history: History,
+    pub storage: Storage,
+    pub processes: Processes,
+    pub meta: Meta,
+    pub disks: DiskFilter,
+    pub control: Control,
+    pub psi: Psi,
+    pub cgroups: Cgroups,
+    pub systemd: Systemd,
+    pub alerts: Vec<AlertRule>,
+    pub notify: Vec<Notifier>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Transport{
+    pub node_name: String,
+    pub service_name: String,
+    pub control_service: String,
+    pub history_service: String,
+    pub process_service: String,
+    pub chunk_size: usize,           // Payload bytes per sample, at most `MAX_SIZE`
+    pub subscriber_buffer: usize,    // Samples a subscriber can hold before the oldest are dropped
+    pub process_keyframe_every: u32  // Process tables sent whole every this many, as deltas in between
+}
+
+/// The Prometheus `/metrics` endpoint, see `exporter`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Exporter{
+    pub enabled: bool,
+    pub listen: String,       // Address the HTTP server binds to
+    pub max_processes: usize  // Processes exported per scrape, the busiest ones are kept
+}
+
+/// What the agent keeps in memory for `history`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct History{
+    pub enabled: bool,
+    pub window_s: u64,     // Points older than this are dropped
+    pub max_points: usize  // Points kept per series at most, whatever the window
+}
+
+/// The on-disk store, see `storage`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Storage{
+    pub enabled: bool,
+    pub dir: PathBuf,
+    pub segment_bytes: u64,      // A segment file is closed once it grows past this
+    pub max_bytes: u64,          // All tiers together, the finest data goes first
+    pub retention_1s_s: u64,     // How long each tier is kept
+    pub retention_1m_s: u64,
+    pub retention_1h_s: u64
+}
+
+/// Which mounts `collectors::disks` reports, an empty include list takes all of them.
+/// A mount point entry also matches the mounts below it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiskFilter{
+    pub include_fs: Vec<String>,
+    pub exclude_fs: Vec<String>,     // Like "tmpfs", "overlay" or "squashfs"
+    pub include_mounts: Vec<PathBuf>,
+    pub exclude_mounts: Vec<PathBuf> // Like "/snap"
+}
+
+/// What `collectors::processes` reads about every process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Processes{
+    pub detail: ProcessDetailLevel
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessDetailLevel{
+    Basic, // Pid, name, cpu, memory and the like
+    Full   // Also disk IO, virtual memory, threads, start and run time, cwd and open files
+}
+
+/// How often `collectors::meta` sends Meta itself, the cpus go out at `collectors.meta`'s intervals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Meta{
+    pub slow_interval_ms: u64 // How often Meta goes out while it is not focused
+}
+
+impl Meta{
+    pub fn slow_interval(&self) -> Duration{
+        Duration::from_millis(self.slow_interval_ms)
+    }
+}
+
+/// Who may act on processes over the process service, see `control`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Control{
+    pub process_actions: bool, // Signals and renice, lookups are always answered
+    pub any_user: bool,        // Act on any process the agent can, not only those of the agent's own user, none if that is root.
+                               // This limits which processes are acted on, not which clients may ask: any that can open the service can
+    pub audit_log: PathBuf     // Every request is appended here as a JSON line
+}
+
+/// Where `collectors::cgroups` reads the cgroup v2 tree, a fake one can stand in for it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cgroups{
+    pub root: PathBuf,
+    pub max_depth: usize // Cgroups deeper than this are counted in their ancestor at this depth
+}
+
+/// Which systemd units `collectors::units` reports and where it gets them from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Systemd{
+    pub backend: UnitBackend,
+    pub types: Vec<String> // Unit types like "service" or "timer"
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum UnitBackend{
+    Systemctl{ timeout_ms: u64 }, // Asks the system manager through `systemctl show`
+    Fixture{ path: PathBuf }      // Reads a saved `systemctl show` output instead, like fixtures/systemd-units.txt
+}
+
+/// Pressure stall triggers, see `collectors::pressure`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Psi{
+    pub triggers: Vec<PsiTrigger>
+}
+
+/// Fires once `stall_ms` of stalls add up within any `window_ms`, so the pressure collector sends at once.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PsiTrigger{
+    pub resource: PressureResource,
+    pub stall: Stall,
+    pub stall_ms: u64,
+    pub window_ms: u64  // 500 to 10000, a multiple of 2000 unless the agent has CAP_SYS_RESOURCE
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stall{
+    #[serde(rename = "some")]
+    Partial, // At least one task stalled
+    #[serde(rename = "full")]
+    Full     // All non-idle tasks stalled
+}
+
+/// A `[[alerts]]` entry, see `alerts`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule{
+    pub name: String,
+    pub metric: String,         // One of `alerts::METRICS`
+    #[serde(default)]
+    pub label: Option<String>,  // Only this mount, interface or cpu, all of them otherwise
+    pub op: AlertOp,
+    pub threshold: f64,
+    #[serde(default)]
+    pub for_s: u64,             // How long the condition must hold before firing
+    #[serde(default)]
+    pub clear: Option<f64>      // Where a firing alert resolves, the threshold if unset
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AlertOp{
+    #[serde(rename = ">")]
+    Above,
+    #[serde(rename = "<")]
+    Below
+}
+
+/// A `[[notify]]` entry, where alert events are sent, see `notify`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Notifier{
+    pub name: String,
+    pub sink: Sink,
+    #[serde(default)]
+    pub rules: Vec<String>,     // Only the alerts of these rules, all of them if empty
+    #[serde(default)]
+    pub pending: bool,          // Also send alerts that are pending, not firing yet
+    #[serde(default = "default_retries")]
+    pub retries: u32,           // Attempts after the first before an event is given up on
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,        // Wait before the first retry, doubled for every next one
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,        // How long one attempt may take
+    #[serde(default = "default_max_per_min")]
+    pub max_per_min: u32,       // Events sent in any minute at most, past it only each alert's latest state is kept to send later
+    #[serde(default = "default_dedup_s")]
+    pub dedup_s: u64            // The state an alert was last sent in is not sent again within this
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Sink{
+    Webhook{ url: String },       // JSON is POSTed here, only http:// is supported
+    Command{ argv: Vec<String> }, // Run with the event in AWARE_ALERT_* variables
+    File{ path: PathBuf }         // JSON lines are appended here
+}
+
+fn default_retries() -> u32 { 3 }
+fn default_backoff_ms() -> u64 { 1000 }
+fn default_timeout_ms() -> u64 { 5000 }
+fn default_max_per_min() -> u32 { 10 }
+fn default_dedup_s() -> u64 { 300 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Collectors{
+    pub meta: Collector,
+    pub disks: Collector,
+    pub memory: Collector,
+    pub networks: Collector,
+    pub sockets: Collector,
+    pub processes: Collector,
+    pub components: Collector,
+    pub pressure: Collector,
+    pub cgroups: Collector,
+    pub units: Collector,
+    pub blockio: Collector,
+    pub interfaces: Collector
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Collector{
+    pub enabled: bool,
+    pub focused_ms: u64,    // Interval while the collector's state is focused
+    pub background_ms: u64  // Interval otherwise
+}
+
+impl Collector{
+    fn new(focused: Duration, background: Duration) -> Self{
+        Self{
+            enabled: true,
+            focused_ms: focused.as_millis() as u64,
+            background_ms: background.as_millis() as u64
+        }
+    }
+
+    pub fn interval(&self, focused: bool) -> Duration{
+        Duration::from_millis(if focused { self.focused_ms } else { self.background_ms })
+    }
+}
+
+impl Default for Config{
+    fn default() -> Self{
+        Self{
+            transport: Transport{
+                node_name: "AwareAgent".to_string(),
+                service_name: "Telemetry".to_string(),
+                control_service: "Control".to_string(),
+                history_service: "History".to_string(),
+                process_service: "Process".to_string(),
+                chunk_size: MAX_SIZE,
+                subscriber_buffer: SUB_BUFFER_SIZE,
+                process_keyframe_every: PROC_KEYFRAME_EVERY
+            },
+            collectors: Collectors{
+                meta: Collector::new(MINIMUM_CPU_UPDATE_INTERVAL, MINIMUM_CPU_UPDATE_INTERVAL * 2),
+                disks: Collector::new(DISK_DURATION, LDISK_DURATION),
+                memory: Collector::new(MEM_DURATION, LMEM_DURATION),
+                networks: Collector::new(NET_DURATION, LNET_DURATION),
+                sockets: Collector::new(SOCKET_DURATION, LSOCKET_DURATION),
+                processes: Collector::new(PROC_DURATION, LPROC_DURATION),
+                components: Collector::new(COMPONENT_DURATION, LCOMPONENT_DURATION),
+                pressure: Collector::new(PSI_DURATION, LPSI_DURATION),
+                cgroups: Collector::new(CGROUP_DURATION, LCGROUP_DURATION),
+                units: Collector::new(UNIT_DURATION, LUNIT_DURATION),
+                blockio: Collector::new(BLOCK_DURATION, LBLOCK_DURATION),
+                interfaces: Collector::new(IFACE_DURATION, LIFACE_DURATION)
+            },
+            exporter: Exporter{
+                enabled: false,
+                listen: "127.0.0.1:9464".to_string(),
+                max_processes: 100
+            },
+            history: History{
+                enabled: true,
+                window_s: 600,
+                max_points: 1200
+            },
+            storage: Storage{
+                enabled: false,
+                dir: PathBuf::from("aware-data"),
+                segment_bytes: 4 << 20,
+                max_bytes: 512 << 20,
+                retention_1s_s: 24 * 3600,
+                retention_1m_s: 7 * 24 * 3600,
+                retention_1h_s: 90 * 24 * 3600
+            },
+            processes: Processes{
+                detail: ProcessDetailLevel::Basic
+            },
+            meta: Meta{
+                slow_interval_ms: 30000
+            },
+            disks: DiskFilter{
+                include_fs: Vec::new(),
+                exclude_fs: Vec::new(),
+                include_mounts: Vec::new(),
+                exclude_mounts: Vec::new()
+            },
+            control: Control{
+                process_actions: false,
+                any_user: false,
+                audit_log: PathBuf::from("aware-audit.jsonl")
+            },
+            psi: Psi{
+                triggers: Vec::new()
+            },
+            cgroups: Cgroups{
+                root: PathBuf::from("/sys/fs/cgroup"),
+                max_depth: 3
+            },
+            systemd: Systemd{
+                backend: UnitBackend::Systemctl { timeout_ms: 5000 },
+                types: vec!["service".to_string()]
+            },
+            alerts: Vec::new(),
+            notify: Vec::new()
+        }
+    }
+}
+
+/// Where the config comes from, kept around so a reload reads the same places.
+pub struct Sources{
+    pub path: Option<PathBuf>,
+    sets: Vec<String>
+}
+
+impl Sources{
+    /// Take `--config PATH` and `--set key=value` out of `args`.
+    /// Without `--config`, $AWARE_CONFIG or else `agent.toml` if it exists is used.
+    pub fn from_args(args: &mut Vec<String>) -> Result<Self, Error>{
+        let mut path = std::env::var(PATH_ENV).ok().map(PathBuf::from);
+        let mut sets = Vec::new();
+
+        let mut rest = Vec::new();
+        let mut iter = std::mem::take(args).into_iter();
+        while let Some(arg) = iter.next(){
+            match arg.as_str(){
+                "--config" => path = Some(iter.next().ok_or(anyhow!("--config needs a path"))?.into()),
+                "--set" => sets.push(iter.next().ok_or(anyhow!("--set needs a key=value"))?),
+                _ => rest.push(arg)
+            }
+        }
+        *args = rest;
+
+        let path = path.or_else(|| {
+            let default = PathBuf::from(DEFAULT_PATH);
+            default.exists().then_some(default)
+        });
+        Ok(Self { path, sets })
+    }
+
+    /// When the config file was last written, to notice edits.
+    pub fn modified(&self) -> Option<SystemTime>{
+        std::fs::metadata(self.path.as_ref()?).ok()?.modified().ok()
+    }
+
+    /// Build the config from, in increasing priority: the defaults, the config file,
+    /// `AWARE__*` environment variables and `--set key=value` arguments.
+    pub fn load(&self) -> Result<Config, Error>{
+        self.load_with(std::env::vars())
+    }
+
+    /// `load` with `env` in place of the environment.
+    fn load_with(&self, env: impl IntoIterator<Item = (String, String)>) -> Result<Config, Error>{
+        let mut table = toml::Table::try_from(Config::default())?;
+
+        if let Some(path) = &self.path{
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read the config file {}", path.display()))?;
+            let file: toml::Table = toml::from_str(&text)
+                .with_context(|| format!("Failed to parse the config file {}", path.display()))?;
+            merge(&mut table, file);
+        }
+
+        for (key, value) in env{
+            if let Some(key) = key.strip_prefix(ENV_PREFIX){
+                let key = key.to_lowercase().replace("__", ".");
+                set(&mut table, &key, &value).with_context(|| format!("Invalid environment override {}{}", ENV_PREFIX, key))?;
+            }
+        }
+        for kv in &self.sets{
+            let (key, value) = kv.split_once('=').ok_or(anyhow!("--set expects key=value, got '{}'", kv))?;
+            set(&mut table, key.trim(), value.trim()).with_context(|| format!("Invalid override --set {}", kv))?;
+        }
+
+        let config: Config = table.try_into().context("Invalid configuration")?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl Config{
+    pub fn validate(&self) -> Result<(), Error>{
+        let t = &self.transport;
+        NodeName::new(&t.node_name).map_err(|e| anyhow!("transport.node_name '{}' is not a valid node name: {:?}", t.node_name, e))?;
+        let services = [
+            ("transport.service_name", &t.service_name),
+            ("transport.control_service", &t.control_service),
+            ("transport.history_service", &t.history_service),
+            ("transport.process_service", &t.process_service)
+        ];
+        for (idx, (key, name)) in services.iter().enumerate(){
+            ServiceName::new(name).map_err(|e| anyhow!("{} '{}' is not a valid service name: {:?}", key, name, e))?;
+            if let Some((other, _)) = services[..idx].iter().find(|(_, n)| n == name){
+                bail!("{} and {} must differ", other, key);
+            }
+        }
+        if t.chunk_size == 0 || t.chunk_size > MAX_SIZE{
+            bail!("transport.chunk_size must be between 1 and {}, got {}", MAX_SIZE, t.chunk_size);
+        }
+        if t.subscriber_buffer == 0{
+            bail!("transport.subscriber_buffer must be at least 1");
+        }
+        if t.process_keyframe_every == 0{
+            bail!("transport.process_keyframe_every must be at least 1");
+        }
+
+        self.exporter.listen.parse::<SocketAddr>()
+            .map_err(|e| anyhow!("exporter.listen '{}' is not a socket address: {}", self.exporter.listen, e))?;
+
+        if self.history.window_s == 0 || self.history.max_points == 0{
+            bail!("history.window_s and history.max_points must be greater than 0");
+        }
+
+        let s = &self.storage;
+        if s.segment_bytes == 0 || s.max_bytes < s.segment_bytes{
+            bail!("storage.segment_bytes must be greater than 0 and not larger than storage.max_bytes");
+        }
+        if s.retention_1s_s == 0 || s.retention_1m_s == 0 || s.retention_1h_s == 0{
+            bail!("storage retentions must be greater than 0");
+        }
+
+        for (idx, rule) in self.alerts.iter().enumerate(){
+            if rule.name.is_empty() || self.alerts[..idx].iter().any(|r| r.name == rule.name){
+                bail!("alerts: every rule needs a name of its own, '{}' is empty or taken", rule.name);
+            }
+            if !METRICS.contains(&rule.metric.as_str()){
+                bail!("alerts.{}: unknown metric '{}', expected one of: {}", rule.name, rule.metric, METRICS.join(", "));
+            }
+            let clear = rule.clear.unwrap_or(rule.threshold);
+            let wrong_side = match rule.op{
+                AlertOp::Above => clear > rule.threshold,
+                AlertOp::Below => clear < rule.threshold
+            };
+            if wrong_side{
+                bail!("alerts.{}: clear ({}) must be on the other side of threshold ({}) than the alert", rule.name, clear, rule.threshold);
+            }
+        }
+
+        for (idx, n) in self.notify.iter().enumerate(){
+            if n.name.is_empty() || self.notify[..idx].iter().any(|o| o.name == n.name){
+                bail!("notify: every notifier needs a name of its own, '{}' is empty or taken", n.name);
+            }
+            if let Some(rule) = n.rules.iter().find(|r| !self.alerts.iter().any(|a| &a.name == *r)){
+                bail!("notify.{}: there is no alert rule named '{}'", n.name, rule);
+            }
+            if n.max_per_min == 0 || n.timeout_ms == 0{
+                bail!("notify.{}: max_per_min and timeout_ms must be greater than 0", n.name);
+            }
+            match &n.sink{
+                Sink::Webhook { url } => {
+                    crate::notify::parse_url(url).with_context(|| format!("notify.{}: invalid webhook url", n.name))?;
+                }
+                Sink::Command { argv } if argv.is_empty() => bail!("notify.{}: the command needs at least a program", n.name),
+                _ => {}
+            }
+        }
+
+        let d = &self.disks;
+        if d.include_fs.iter().chain(&d.exclude_fs).any(|fs| fs.is_empty()){
+            bail!("disks: filesystem types must not be empty");
+        }
+        if let Some(mount) = d.include_mounts.iter().chain(&d.exclude_mounts).find(|m| !m.is_absolute()){
+            bail!("disks: mount points must be absolute paths, got '{}'", mount.display());
+        }
+
+        for (idx, t) in self.psi.triggers.iter().enumerate(){
+            if !(500..=10_000).contains(&t.window_ms) || t.stall_ms == 0 || t.stall_ms > t.window_ms{
+                bail!("psi.triggers[{}]: window_ms must be between 500 and 10000 and stall_ms between 1 and window_ms", idx);
+            }
+        }
+
+        if let UnitBackend::Systemctl { timeout_ms: 0 } = self.systemd.backend{
+            bail!("systemd.backend.timeout_ms must be greater than 0");
+        }
+        if let Some(t) = self.systemd.types.iter().find(|t| t.is_empty() || !t.chars().all(|c| c.is_ascii_lowercase())){
+            bail!("systemd.types: '{}' is not a unit type like service or timer", t);
+        }
+
+        for (name, c) in self.collectors.iter(){
+            if c.focused_ms == 0 || c.background_ms == 0{
+                bail!("collectors.{}: intervals must be greater than 0", name);
+            }
+            if c.focused_ms > c.background_ms{
+                bail!("collectors.{}: focused_ms ({}) must not be larger than background_ms ({})", name, c.focused_ms, c.background_ms);
+            }
+        }
+        let min = MINIMUM_CPU_UPDATE_INTERVAL.as_millis() as u64;
+        if self.collectors.meta.focused_ms < min{
+            bail!("collectors.meta.focused_ms must be at least {} for cpu usage to be meaningful", min);
+        }
+        if self.meta.slow_interval_ms < self.collectors.meta.background_ms{
+            bail!("meta.slow_interval_ms ({}) must not be smaller than collectors.meta.background_ms ({})",
+                self.meta.slow_interval_ms, self.collectors.meta.background_ms);
+        }
+        Ok(())
+    }
+}
+
+impl DiskFilter{
+    /// Whether a disk with filesystem `fs` mounted at `mount` is reported.
+    pub fn wanted(&self, fs: &str, mount: &Path) -> bool{
+        let fs_ok = (self.include_fs.is_empty() || self.include_fs.iter().any(|f| f == fs)) && !self.exclude_fs.iter().any(|f| f == fs);
+        let mount_ok = (self.include_mounts.is_empty() || self.include_mounts.iter().any(|m| mount.starts_with(m)))
+            && !self.exclude_mounts.iter().any(|m| mount.starts_with(m));
+        fs_ok && mount_ok
+    }
+}
+
+impl Collectors{
+    pub fn iter(&self) -> [(&'static str, &Collector); 12]{
+        [
+            ("meta", &self.meta),
+            ("disks", &self.disks),
+            ("memory", &self.memory),
+            ("networks", &self.networks),
+            ("sockets", &self.sockets),
+            ("processes", &self.processes),
+            ("components", &self.components),
+            ("pressure", &self.pressure),
+            ("cgroups", &self.cgroups),
+            ("units", &self.units),
+            ("blockio", &self.blockio),
+            ("interfaces", &self.interfaces)
+        ]
+    }
+}
+
+fn current() -> &'static watch::Sender<Arc<Config>>{
+    CONFIG.get_or_init(|| watch::Sender::new(Arc::new(Config::default())))
+}
+
+/// The current config, or the defaults when nothing was loaded.
+pub fn config() -> Arc<Config>{
+    current().borrow().clone()
+}
+
+pub fn set_config(config: Config){
+    current().send_replace(Arc::new(config));
+}
+
+/// Notified every time the config is replaced.
+pub fn subscribe() -> watch::Receiver<Arc<Config>>{
+    current().subscribe()
+}
+
+fn merge(base: &mut toml::Table, other: toml::Table){
+    for (key, value) in other{
+        match (base.get_mut(&key), value){
+            (Some(toml::Value::Table(base)), toml::Value::Table(other)) => merge(base, other),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Set the dotted `key` to `value`, parsed as a TOML value or taken as a string otherwise.
+fn set(table: &mut toml::Table, key: &str, value: &str) -> Result<(), Error>{
+    let value = toml::from_str::<toml::Table>(&format!("v = {}", value))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or(toml::Value::String(value.to_string()));
+
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().filter(|k| !k.is_empty()).ok_or(anyhow!("Empty key"))?;
+    let mut current = table;
+    for part in parts{
+        current = match current.get_mut(part){
+            Some(toml::Value::Table(t)) => t,
+            _ => bail!("Unknown key '{}'", key)
+        };
+    }
+    if !current.contains_key(last){
+        bail!("Unknown key '{}'", key);
+    }
+    current.insert(last.to_string(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::testutil::temp_dir;
+
+    fn sources(file: Option<&str>, sets: &[&str]) -> Sources{
+        let path = file.map(|text| {
+            let path = temp_dir(&format!("config-{:x}", crc32fast::hash(text.as_bytes()))).join("agent.toml");
+            std::fs::write(&path, text).unwrap();
+            path
+        });
+        Sources { path, sets: sets.iter().map(|s| s.to_string()).collect() }
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)>{
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn notifier(name: &str, sink: Sink) -> Notifier{
+        Notifier{
+            name: name.to_string(), sink, rules: Vec::new(), pending: false, retries: default_retries(), backoff_ms: default_backoff_ms(),
+            timeout_ms: default_timeout_ms(), max_per_min: default_max_per_min(), dedup_s: default_dedup_s()
+        }
+    }
+
+    fn rule(name: &str) -> AlertRule{
+        AlertRule { name: name.to_string(), metric: "cpu.total".to_string(), label: None, op: AlertOp::Above, threshold: 90.0, for_s: 0, clear: None }
+    }
+
+    #[test]
+    fn layers(){
+        let file = "[collectors.memory]\nfocused_ms = 500\nbackground_ms = 2500\n[history]\nwindow_s = 100\nmax_points = 50\n";
+        let vars = env(&[("AWARE__COLLECTORS__MEMORY__FOCUSED_MS", "600"), ("AWARE__HISTORY__WINDOW_S", "200"), ("PATH", "/bin")]);
+        let config = sources(Some(file), &["collectors.memory.focused_ms=700"]).load_with(vars).unwrap();
+
+        // --set over the environment over the file over the defaults.
+        assert_eq!(config.collectors.memory.focused_ms, 700);
+        assert_eq!(config.history.window_s, 200);
+        assert_eq!((config.collectors.memory.background_ms, config.history.max_points), (2500, 50));
+        assert_eq!(config.exporter.listen, Config::default().exporter.listen);
+        assert!(config.collectors.memory.enabled);
+
+        // A value that is not valid TOML is taken as a string.
+        let config = sources(None, &["exporter.listen=0.0.0.0:9100"]).load_with(Vec::new()).unwrap();
+        assert_eq!(config.exporter.listen, "0.0.0.0:9100");
+    }
+
+    #[test]
+    fn unknown_keys(){
+        assert!(sources(Some("[transport]\nchunk = 512\n"), &[]).load_with(Vec::new()).is_err());
+        assert!(sources(Some("[nonsense]\n"), &[]).load_with(Vec::new()).is_err());
+        assert!(sources(None, &["collectors.memory.often=true"]).load_with(Vec::new()).is_err());
+        assert!(sources(None, &[]).load_with(env(&[("AWARE__HISTORY__SIZE", "3")])).is_err());
+        assert!(sources(None, &["history.window_s"]).load_with(Vec::new()).is_err()); // No value
+    }
+
+    #[test]
+    fn validate(){
+        assert!(Config::default().validate().is_ok());
+
+        let broken: [(fn(&mut Config), &str); 27] = [
+            (|c| c.transport.node_name = "n".repeat(1024), "transport.node_name"),
+            (|c| c.transport.history_service = String::new(), "transport.history_service"),
+            (|c| c.transport.process_service = c.transport.control_service.clone(), "transport.control_service and transport.process_service"),
+            (|c| c.transport.chunk_size = MAX_SIZE + 1, "transport.chunk_size"),
+            (|c| c.transport.subscriber_buffer = 0, "transport.subscriber_buffer"),
+            (|c| c.transport.process_keyframe_every = 0, "transport.process_keyframe_every"),
+            (|c| c.exporter.listen = "localhost".to_string(), "exporter.listen"),
+            (|c| c.history.max_points = 0, "history.window_s"),
+            (|c| c.storage.max_bytes = c.storage.segment_bytes - 1, "storage.segment_bytes"),
+            (|c| c.storage.retention_1m_s = 0, "storage retentions"),
+            (|c| c.alerts = vec![rule("hot"), rule("hot")], "alerts: every rule"),
+            (|c| c.alerts = vec![AlertRule { metric: "cpu.temperature".to_string(), ..rule("hot") }], "unknown metric"),
+            (|c| c.alerts = vec![AlertRule { clear: Some(95.0), ..rule("hot") }], "clear (95)"),
+            (|c| c.notify = vec![notifier("", Sink::File { path: "a".into() })], "notify: every notifier"),
+            (|c| c.notify = vec![Notifier { rules: vec!["cold".to_string()], ..notifier("ops", Sink::File { path: "a".into() }) }], "no alert rule named 'cold'"),
+            (|c| c.notify = vec![Notifier { max_per_min: 0, ..notifier("ops", Sink::File { path: "a".into() }) }], "max_per_min"),
+            (|c| c.notify = vec![notifier("ops", Sink::Webhook { url: "https://example.com".to_string() })], "invalid webhook url"),
+            (|c| c.notify = vec![notifier("ops", Sink::Command { argv: Vec::new() })], "needs at least a program"),
+            (|c| c.disks.exclude_fs = vec![String::new()], "filesystem types"),
+            (|c| c.disks.include_mounts = vec!["home".into()], "must be absolute"),
+            (|c| c.psi.triggers = vec![PsiTrigger { resource: PressureResource::Io, stall: Stall::Full, stall_ms: 100, window_ms: 20_000 }], "psi.triggers[0]"),
+            (|c| c.systemd.backend = UnitBackend::Systemctl { timeout_ms: 0 }, "systemd.backend.timeout_ms"),
+            (|c| c.systemd.types = vec!["Service".to_string()], "systemd.types"),
+            (|c| c.collectors.sockets.background_ms = 0, "collectors.sockets: intervals"),
+            (|c| c.collectors.disks.focused_ms = c.collectors.disks.background_ms + 1, "collectors.disks: focused_ms"),
+            (|c| c.collectors.meta.focused_ms = 1, "collectors.meta.focused_ms"),
+            (|c| c.meta.slow_interval_ms = 1, "meta.slow_interval_ms")
+        ];
+        for (breaks, expected) in broken{
+            let mut config = Config::default();
+            breaks(&mut config);
+            let err = config.validate().expect_err(expected).to_string();
+            assert!(err.contains(expected), "'{}' does not mention '{}'", err, expected);
+        }
+    }
+}
@@ -0,0 +1,426 @@
+use std::{fs::{self, OpenOptions}, io::{self, Write}, os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd}};
+
+use anyhow::{anyhow, Error};
+use iceoryx2::{node as ice_node, prelude::SignalHandlingMode, service::ipc};
+use serde::Serialize;
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+use tokio::time::Duration;
+
+use crate::{
+    config::{config, Control},
+    history::now_ms,
+    models::{
+        ControlAction, ControlRequest, ControlResponse, ControlStatus, ProcessAction, ProcessRequest, ProcessResponse, ProcessSignal,
+        ProcessStatus
+    },
+    state::AppState, APPSTATE, SHUTDOWN, SYSTEM
+};
+
+const CONTROL_CYCLE: Duration = Duration::from_millis(50);
+
+/// A line of the audit log, one per process request whatever came of it.
+#[derive(Serialize)]
+struct Audit<'a>{
+    t_ms: u64,
+    action: String,
+    pid: u32,
+    start_time: u64, // As the client asked for it, 0 for any
+    name: &'a str, // Of the process, empty if it was not found
+    status: String,
+    errno: i32
+}
+
+/// Serves the control service so clients can change `APPSTATE`, and the process service
+/// so they can signal, renice and look up processes.
+/// The iceoryx2 ports are not Send, so this runs on its own blocking thread.
+pub fn main() -> Result<(), Error>{
+    let cfg = config();
+    let transport = &cfg.transport;
+    let node = ice_node::NodeBuilder::new()
+        .name(&format!("{}Control", transport.node_name).as_str().try_into()?)
+        .signal_handling_mode(SignalHandlingMode::Disabled) // Signals are handled in main
+        .create::<ipc::Service>()?;
+
+    let service = node.service_builder(&transport.control_service.as_str().try_into()?)
+        .request_response::<ControlRequest, ControlResponse>()
+        .open_or_create()?;
+
+    let server = service.server_builder().create()?;
+
+    let procs = node.service_builder(&transport.process_service.as_str().try_into()?)
+        .request_response::<ProcessRequest, ProcessResponse>()
+        .open_or_create()?;
+    let proc_server = procs.server_builder().create()?;
+
+    while node.wait(CONTROL_CYCLE).is_ok(){
+        while let Some(active) = server.receive()?{
+            let res = handle(*active.payload())?;
+            active.send_copy(res)?;
+        }
+        while let Some(active) = proc_server.receive()?{
+            let res = handle_process(active.payload());
+            active.send_copy(res)?;
+        }
+        if current_state()? == AppState::ShuttingDown{
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn handle(req: ControlRequest) -> Result<ControlResponse, Error>{
+    let appstate = APPSTATE.get().ok_or(anyhow!("APPSTATE is not initialized"))?;
+    let mut state = appstate.blocking_write();
+
+    let status = match req.action(){
+        None => ControlStatus::Rejected,
+        Some(ControlAction::Status) => ControlStatus::Ok,
+        // Only a focus looks at the requested state.
+        Some(ControlAction::Focus) => match req.state(){
+            Some(focus) if *state != AppState::ShuttingDown && focus != AppState::ShuttingDown => {
+                *state = focus;
+                ControlStatus::Ok
+            }
+            _ => ControlStatus::Rejected
+        }
+        Some(ControlAction::Shutdown) => {
+            *state = AppState::ShuttingDown;
+            if let Some(shutdown) = SHUTDOWN.get(){
+                shutdown.send_replace(true);
+            }
+            ControlStatus::Ok
+        }
+    };
+    Ok(ControlResponse::new(status, *state))
+}
+
+fn current_state() -> Result<AppState, Error>{
+    let appstate = APPSTATE.get().ok_or(anyhow!("APPSTATE is not initialized"))?;
+    Ok(*appstate.blocking_read())
+}
+
+fn handle_process(req: &ProcessRequest) -> ProcessResponse{
+    let (res, name) = act(req, &config().control);
+    let action = match (req.action(), req.signal()){
+        (Some(ProcessAction::Signal), Some(signal)) => format!("Signal {:?}", signal),
+        (Some(ProcessAction::Signal), None) => format!("Signal {}", req.signal),
+        (Some(ProcessAction::Renice), _) => format!("Renice {}", req.nice),
+        (Some(ProcessAction::Lookup), _) => "Lookup".to_string(),
+        (None, _) => format!("Action {}", req.action)
+    };
+    let status = res.status().map_or(res.status.to_string(), |s| format!("{:?}", s));
+    let entry = Audit { t_ms: now_ms(), action, pid: req.pid, start_time: req.start_time, name: &name, status, errno: res.errno };
+    if let Err(e) = audit(&entry){
+        eprintln!("Failed to write the audit log: {:#}", e);
+    }
+    res
+}
+
+/// Carry out `req`, with the name of the process for the audit log.
+fn act(req: &ProcessRequest, control: &Control) -> (ProcessResponse, String){
+    let invalid = |name: String| (ProcessResponse::new(ProcessStatus::InvalidArgument, 0), name);
+    // Pids above i32::MAX turn into process groups for kill(2), 0 is the caller's own group, 1 is init.
+    if req.pid <= 1 || req.pid > i32::MAX as u32 || req.pid == std::process::id(){
+        return invalid(String::new());
+    }
+    let name = fs::read_to_string(format!("/proc/{}/comm", req.pid)).map(|n| n.trim_end().to_string()).unwrap_or_default();
+    let signal = match (req.action(), req.signal()){
+        (None, _) => return invalid(name),
+        (Some(ProcessAction::Lookup), _) => return (lookup(req.pid), name),
+        (Some(ProcessAction::Signal), None) => return invalid(name),
+        (Some(ProcessAction::Signal), Some(signal)) => Some(match signal{
+            ProcessSignal::Term => libc::SIGTERM,
+            ProcessSignal::Kill => libc::SIGKILL,
+            ProcessSignal::Stop => libc::SIGSTOP,
+            ProcessSignal::Cont => libc::SIGCONT
+        }),
+        (Some(ProcessAction::Renice), _) if !(-20..=19).contains(&req.nice) => return invalid(name),
+        (Some(ProcessAction::Renice), _) => None
+    };
+
+    if !control.process_actions{
+        return (ProcessResponse::new(ProcessStatus::NotAllowed, 0), name);
+    }
+
+    // The pidfd stays with the process it was opened for, even once the pid is reused.
+    let pidfd = match pidfd_open(req.pid){
+        Ok(fd) => fd,
+        Err(e) => return (failure(e), name)
+    };
+    // Which user a client runs as can't be told from its request, so without any_user only the agent's own processes are acted on.
+    // Run as root that would be every process, so there nothing is.
+    if !control.any_user{
+        // SAFETY: geteuid has no preconditions.
+        let euid = unsafe { libc::geteuid() };
+        if euid == 0{
+            return (ProcessResponse::new(ProcessStatus::NotAllowed, 0), name);
+        }
+        match owner(req.pid){
+            Ok(uid) if uid == euid => {}
+            Ok(_) => return (ProcessResponse::new(ProcessStatus::NotAllowed, 0), name),
+            Err(e) => return (failure(e), name)
+        }
+    }
+    if req.start_time != 0{
+        match started(req.pid){
+            // btime, which sysinfo adds to the start time too, can move by a second as the clock is adjusted.
+            Ok(started) if started.abs_diff(req.start_time) <= 1 => {}
+            Ok(_) => return (ProcessResponse::new(ProcessStatus::NoSuchProcess, libc::ESRCH), name),
+            Err(e) => return (failure(e), name)
+        }
+    }
+    // What was read from /proc after the pidfd was opened is the pidfd's process' if that still runs.
+    if (!control.any_user || req.start_time != 0)
+        && let Err(e) = pidfd_send_signal(&pidfd, 0){
+        return (failure(e), name);
+    }
+    let done = match signal{
+        Some(signal) => pidfd_send_signal(&pidfd, signal),
+        // setpriority has no pidfd form, so the process is checked again once its threads were reniced by tid.
+        None => renice(req.pid, req.nice).and_then(|()| pidfd_send_signal(&pidfd, 0))
+    };
+    match done{
+        Ok(()) => (ProcessResponse::new(ProcessStatus::Ok, 0), name),
+        Err(e) => (failure(e), name)
+    }
+}
+
+fn pidfd_open(pid: u32) -> Result<OwnedFd, io::Error>{
+    // SAFETY: pidfd_open takes no pointers, the pid was checked to be a single process.
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0 as libc::c_uint) };
+    if fd < 0{
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the fd was just opened and nothing else owns it.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// Send `signal` to the process of `pidfd`, 0 only checks that it still runs.
+fn pidfd_send_signal(pidfd: &OwnedFd, signal: i32) -> Result<(), io::Error>{
+    // SAFETY: the fd is open for the whole call, a null siginfo makes it send like kill(2).
+    let ret = unsafe {
+        libc::syscall(libc::SYS_pidfd_send_signal, pidfd.as_raw_fd(), signal, std::ptr::null::<libc::siginfo_t>(), 0 as libc::c_uint)
+    };
+    match ret{
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error())
+    }
+}
+
+/// Set the nice value of every thread of `pid`, setpriority on a pid only changes its main thread.
+fn renice(pid: u32, nice: i32) -> Result<(), io::Error>{
+    for task in fs::read_dir(format!("/proc/{}/task", pid))?{
+        let Some(tid) = task?.file_name().to_str().and_then(|t| t.parse::<libc::id_t>().ok()) else { continue };
+        // SAFETY: setpriority has no memory effects on this process.
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, nice) } != 0{
+            let e = io::Error::last_os_error();
+            // A thread that exited since the directory was read
+            if e.raw_os_error() != Some(libc::ESRCH){
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The reply for a system call that failed with `e`.
+fn failure(e: io::Error) -> ProcessResponse{
+    let errno = e.raw_os_error().unwrap_or(0);
+    let status = match e.kind(){
+        // A /proc file that is gone, as kill(2) would have said
+        io::ErrorKind::NotFound => return ProcessResponse::new(ProcessStatus::NoSuchProcess, libc::ESRCH),
+        io::ErrorKind::PermissionDenied => ProcessStatus::PermissionDenied,
+        io::ErrorKind::InvalidInput => ProcessStatus::InvalidArgument,
+        _ if errno == libc::ESRCH => ProcessStatus::NoSuchProcess,
+        _ => ProcessStatus::Failed
+    };
+    ProcessResponse::new(status, errno)
+}
+
+/// `pid` as of the last process refresh, refreshed on its own if the agent has not seen it yet.
+fn lookup(pid: u32) -> ProcessResponse{
+    let Some(sys) = SYSTEM.get() else {
+        return ProcessResponse::new(ProcessStatus::Failed, 0);
+    };
+    let mut sys = match sys.write(){
+        Ok(data) => data,
+        Err(poisoned) => {
+            eprintln!("FATAL: SYSTEM lock was poisoned!, Recovering");
+            poisoned.into_inner()
+        }
+    };
+    let key = Pid::from_u32(pid);
+    if sys.process(key).is_none(){
+        sys.refresh_processes_specifics(ProcessesToUpdate::Some(&[key]), false, ProcessRefreshKind::everything());
+    }
+    let Some(proc) = sys.process(key) else {
+        return ProcessResponse::new(ProcessStatus::NoSuchProcess, libc::ESRCH);
+    };
+
+    let mut res = ProcessResponse::new(ProcessStatus::Ok, 0);
+    let info = &mut res.info;
+    info.pid = pid;
+    info.parent = proc.parent().map(|p| p.as_u32()).unwrap_or(0);
+    info.uid = proc.user_id().map(|uid| **uid).unwrap_or(u32::MAX);
+    info.nice = nice(pid).unwrap_or(0);
+    info.cpu = proc.cpu_usage();
+    info.mem = proc.memory();
+    info.virtual_mem = proc.virtual_memory();
+    info.start_time = proc.start_time();
+    info.threads = proc.tasks().map_or(1, |t| t.len() as u32 + 1);
+    let cmd = proc.cmd().iter().map(|c| c.to_string_lossy()).collect::<Vec<_>>().join(" ");
+    let exe = proc.exe().map(|e| e.display().to_string()).unwrap_or_default();
+    info.set_strs(&proc.status().to_string(), &proc.name().to_string_lossy(), &exe, &cmd);
+    res
+}
+
+/// Field `n` of /proc/pid/stat, counted from 1 as proc(5) does.
+fn stat_field(pid: u32, n: usize) -> Result<String, io::Error>{
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    // The name in parentheses may hold spaces, the fields after it are counted from the 3rd.
+    stat.rsplit_once(')')
+        .and_then(|(_, fields)| fields.split_whitespace().nth(n - 3))
+        .map(str::to_string)
+        .ok_or(io::Error::new(io::ErrorKind::InvalidData, format!("no field {} in /proc/{}/stat", n, pid)))
+}
+
+/// The real uid of `pid`, from /proc/pid/status.
+fn owner(pid: u32) -> Result<u32, io::Error>{
+    let status = fs::read_to_string(format!("/proc/{}/status", pid))?;
+    status.lines()
+        .find_map(|l| l.strip_prefix("Uid:"))
+        .and_then(|uids| uids.split_whitespace().next()?.parse().ok())
+        .ok_or(io::Error::new(io::ErrorKind::InvalidData, format!("no Uid in /proc/{}/status", pid)))
+}
+
+/// The nice value of `pid`.
+fn nice(pid: u32) -> Option<i32>{
+    stat_field(pid, 19).ok()?.parse().ok()
+}
+
+/// When `pid` started, in seconds since the epoch worked out the way sysinfo does for a lookup.
+fn started(pid: u32) -> Result<u64, io::Error>{
+    let ticks: u64 = stat_field(pid, 22)?.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad start time"))?;
+    // SAFETY: sysconf has no preconditions.
+    let hz = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+    Ok(System::boot_time() + ticks / hz)
+}
+
+fn audit(entry: &Audit) -> Result<(), Error>{
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    let cfg = config();
+    let mut file = OpenOptions::new().create(true).append(true).open(&cfg.control.audit_log)?;
+    file.write_all(&line)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests{
+    use std::{path::PathBuf, process::{Child, Command}, sync::Arc};
+
+    use super::*;
+
+    fn sleeper() -> Child{
+        Command::new("sleep").arg("30").spawn().unwrap()
+    }
+
+    fn allowed() -> Control{
+        Control { process_actions: true, any_user: true, audit_log: PathBuf::new() }
+    }
+
+    fn status(req: &ProcessRequest, control: &Control) -> Option<ProcessStatus>{
+        act(req, control).0.status()
+    }
+
+    #[test]
+    fn start_time_matches_sysinfo(){
+        let mut child = sleeper();
+        let pid = Pid::from_u32(child.id());
+        let mut sys = System::new();
+        sys.refresh_processes_specifics(ProcessesToUpdate::Some(&[pid]), false, ProcessRefreshKind::nothing());
+        let expected = sys.process(pid).unwrap().start_time();
+        assert!(started(child.id()).unwrap().abs_diff(expected) <= 1);
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn signal_checks_the_start_time(){
+        let mut child = sleeper();
+        let start_time = started(child.id()).unwrap();
+        let kill = ProcessRequest { signal: ProcessSignal::Kill as u32, ..ProcessRequest::new(ProcessAction::Signal, child.id()) };
+
+        // Another process took over the pid as far as the request knows.
+        let other = ProcessRequest { start_time: start_time - 3600, ..kill };
+        assert_eq!(status(&other, &allowed()), Some(ProcessStatus::NoSuchProcess));
+        assert!(child.try_wait().unwrap().is_none());
+
+        assert_eq!(status(&ProcessRequest { start_time, ..kill }, &allowed()), Some(ProcessStatus::Ok));
+        assert!(child.wait().unwrap().code().is_none()); // Killed by the signal
+    }
+
+    #[test]
+    fn own_user_only_or_unknown_values(){
+        let mut child = sleeper();
+        let stop = ProcessRequest { signal: ProcessSignal::Stop as u32, ..ProcessRequest::new(ProcessAction::Signal, child.id()) };
+        let own_user_only = Control { any_user: false, ..allowed() };
+        assert_eq!(status(&stop, &Control { process_actions: false, ..allowed() }), Some(ProcessStatus::NotAllowed));
+        assert_eq!(status(&ProcessRequest { action: 7, ..stop }, &allowed()), Some(ProcessStatus::InvalidArgument));
+        assert_eq!(status(&ProcessRequest { signal: 7, ..stop }, &allowed()), Some(ProcessStatus::InvalidArgument));
+        assert_eq!(ProcessResponse { status: 7, ..ProcessResponse::new(ProcessStatus::Ok, 0) }.status(), None);
+
+        // SAFETY: geteuid has no preconditions.
+        let euid = unsafe { libc::geteuid() };
+        if euid == 0{
+            // Root's processes are everyone's, so without any_user none are acted on.
+            assert_eq!(status(&stop, &own_user_only), Some(ProcessStatus::NotAllowed));
+            child.kill().unwrap();
+            child.wait().unwrap();
+            return;
+        }
+
+        // The agent's own process is fair game without any_user.
+        assert_eq!(status(&stop, &own_user_only), Some(ProcessStatus::Ok));
+        let cont = ProcessRequest { signal: ProcessSignal::Cont as u32, ..stop };
+        assert_eq!(status(&cont, &own_user_only), Some(ProcessStatus::Ok));
+        assert!(child.try_wait().unwrap().is_none());
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        // Another user's is not, if there is one to try.
+        let other = fs::read_dir("/proc").unwrap().flatten()
+            .filter_map(|e| e.file_name().to_str()?.parse::<u32>().ok())
+            .find(|pid| *pid > 1 && owner(*pid).is_ok_and(|uid| uid != euid));
+        if let Some(pid) = other{
+            let cont = ProcessRequest { signal: ProcessSignal::Cont as u32, ..ProcessRequest::new(ProcessAction::Signal, pid) };
+            assert_eq!(status(&cont, &own_user_only), Some(ProcessStatus::NotAllowed));
+        }
+    }
+
+    #[test]
+    fn renice_every_thread(){
+        let mut child = sleeper();
+        let renice = ProcessRequest { nice: 5, ..ProcessRequest::new(ProcessAction::Renice, child.id()) };
+        assert_eq!(status(&renice, &allowed()), Some(ProcessStatus::Ok));
+        assert_eq!(nice(child.id()), Some(5));
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn control_rejects_unknown_values(){
+        let appstate = APPSTATE.get_or_init(|| Arc::new(tokio::sync::RwLock::new(AppState::Meta)));
+        let focus = ControlRequest::new(ControlAction::Focus, Some(AppState::Cpu));
+        for req in [ControlRequest { action: 9, ..focus }, ControlRequest { state: 42, ..focus }]{
+            let res = handle(req).unwrap();
+            assert_eq!((res.status(), res.state()), (Some(ControlStatus::Rejected), Some(AppState::Meta)));
+        }
+        // A status has no state to check.
+        let res = handle(ControlRequest { state: 42, ..ControlRequest::new(ControlAction::Status, None) }).unwrap();
+        assert_eq!((res.status(), res.state()), (Some(ControlStatus::Ok), Some(AppState::Meta)));
+        let res = handle(focus).unwrap();
+        assert_eq!((res.status(), res.state()), (Some(ControlStatus::Ok), Some(AppState::Cpu)));
+        assert_eq!(*appstate.blocking_read(), AppState::Cpu);
+    }
+}
@@ -0,0 +1,290 @@
+use std::{collections::HashMap, fmt};
+
+use crate::models::{DetailChange, Process, ProcessChange, ProcessDelta, ProcessDetail, ProcessDetailChange};
+
+/// Turns the full process tables of the collector into `ProcessDelta`s on the agent side.
+/// Every `every`th table, and the first one, goes out whole as a keyframe.
+#[derive(Default)]
+pub struct Encoder{
+    last: HashMap<u32, Process>,
+    seq: u64,
+    since_keyframe: Option<u32>, // Deltas sent since the last keyframe, None before the first
+    subscribers: usize           // Seen at the last table, a new one needs a keyframe to start from
+}
+
+impl Encoder{
+    pub fn encode(&mut self, procs: &[Process], every: u32, subscribers: usize) -> ProcessDelta{
+        self.seq += 1;
+        let keyframe = subscribers > self.subscribers || self.since_keyframe.is_none_or(|n| n + 1 >= every);
+        self.subscribers = subscribers;
+
+        let mut delta = ProcessDelta { seq: self.seq, keyframe, ..Default::default() };
+        let mut next = HashMap::with_capacity(procs.len());
+        for proc in procs{
+            match self.last.get(&proc.pid){
+                Some(old) if !keyframe => {
+                    if old != proc{
+                        delta.changed.push(diff(old, proc));
+                    }
+                }
+                _ => delta.added.push(proc.clone())
+            }
+            next.insert(proc.pid, proc.clone());
+        }
+        if !keyframe{
+            delta.removed = self.last.keys().filter(|pid| !next.contains_key(pid)).copied().collect();
+        }
+        self.since_keyframe = Some(if keyframe { 0 } else { self.since_keyframe.unwrap_or(0) + 1 });
+        self.last = next;
+        delta
+    }
+}
+
+fn field<T: PartialEq + Clone>(old: &T, new: &T) -> Option<T>{
+    (old != new).then(|| new.clone())
+}
+
+/// The fields of `new` that differ from `old`.
+fn diff(old: &Process, new: &Process) -> ProcessChange{
+    ProcessChange{
+        pid: new.pid,
+        name: field(&old.name, &new.name),
+        exe: field(&old.exe, &new.exe),
+        cpu: field(&old.cpu, &new.cpu),
+        mem: field(&old.mem, &new.mem),
+        status: field(&old.status, &new.status),
+        cmd: field(&old.cmd, &new.cmd),
+        parent: field(&old.parent, &new.parent),
+        user_id: field(&old.user_id, &new.user_id),
+        thread: field(&old.thread, &new.thread),
+        detail: match (&old.detail, &new.detail){
+            (Some(old), Some(new)) => (old != new).then(|| DetailChange::Fields(diff_detail(old, new))),
+            (old, new) => field(old, new).map(DetailChange::Whole)
+        }
+    }
+}
+
+fn diff_detail(old: &ProcessDetail, new: &ProcessDetail) -> ProcessDetailChange{
+    ProcessDetailChange{
+        read: field(&old.read, &new.read),
+        written: field(&old.written, &new.written),
+        t_read: field(&old.t_read, &new.t_read),
+        t_written: field(&old.t_written, &new.t_written),
+        virtual_mem: field(&old.virtual_mem, &new.virtual_mem),
+        threads: field(&old.threads, &new.threads),
+        start_time: field(&old.start_time, &new.start_time),
+        cwd: field(&old.cwd, &new.cwd),
+        fds: field(&old.fds, &new.fds)
+    }
+}
+
+/// A delta that does not follow the last table applied, some were lost in between.
+#[derive(Debug)]
+pub struct Desync{
+    pub expected: u64,
+    pub got: u64
+}
+
+impl fmt::Display for Desync{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        write!(f, "process delta {} received while expecting {}, waiting for a keyframe", self.got, self.expected)
+    }
+}
+
+/// Rebuilds the process table from `ProcessDelta`s on the client side.
+///
+/// ```ignore
+/// match table.apply(delta){
+///     Ok(Some(procs)) => ..., // The whole table
+///     Ok(None) => {}          // Waiting for the first keyframe
+///     Err(e) => eprintln!("{}", e)
+/// }
+/// ```
+#[derive(Default)]
+pub struct ProcessTable{
+    procs: HashMap<u32, Process>,
+    seq: Option<u64> // Of the last delta applied, None until a keyframe arrives
+}
+
+impl ProcessTable{
+    /// Apply `delta` and return the whole table. Deltas are refused from a gap until the next keyframe.
+    pub fn apply(&mut self, delta: ProcessDelta) -> Result<Option<Vec<Process>>, Desync>{
+        if delta.keyframe{
+            self.procs = delta.added.into_iter().map(|p| (p.pid, p)).collect();
+        }
+        else{
+            match self.seq{
+                None => return Ok(None),
+                Some(seq) if seq + 1 != delta.seq => {
+                    self.seq = None;
+                    return Err(Desync { expected: seq + 1, got: delta.seq });
+                }
+                Some(_) => {}
+            }
+            for pid in delta.removed{
+                self.procs.remove(&pid);
+            }
+            for proc in delta.added{
+                self.procs.insert(proc.pid, proc);
+            }
+            for change in delta.changed{
+                if let Some(proc) = self.procs.get_mut(&change.pid){
+                    patch(proc, change);
+                }
+            }
+        }
+        self.seq = Some(delta.seq);
+        Ok(Some(self.procs.values().cloned().collect()))
+    }
+
+    /// Forget the table, e.g. after the agent restarted.
+    pub fn reset(&mut self){
+        self.procs.clear();
+        self.seq = None;
+    }
+}
+
+fn set<T>(field: &mut T, value: Option<T>){
+    if let Some(value) = value{
+        *field = value;
+    }
+}
+
+fn patch(proc: &mut Process, change: ProcessChange){
+    set(&mut proc.name, change.name);
+    set(&mut proc.exe, change.exe);
+    set(&mut proc.cpu, change.cpu);
+    set(&mut proc.mem, change.mem);
+    set(&mut proc.status, change.status);
+    set(&mut proc.cmd, change.cmd);
+    set(&mut proc.parent, change.parent);
+    set(&mut proc.user_id, change.user_id);
+    set(&mut proc.thread, change.thread);
+    match (change.detail, proc.detail.as_mut()){
+        (Some(DetailChange::Whole(detail)), _) => proc.detail = detail,
+        (Some(DetailChange::Fields(change)), Some(detail)) => patch_detail(detail, change),
+        _ => {}
+    }
+}
+
+fn patch_detail(detail: &mut ProcessDetail, change: ProcessDetailChange){
+    set(&mut detail.read, change.read);
+    set(&mut detail.written, change.written);
+    set(&mut detail.t_read, change.t_read);
+    set(&mut detail.t_written, change.t_written);
+    set(&mut detail.virtual_mem, change.virtual_mem);
+    set(&mut detail.threads, change.threads);
+    set(&mut detail.start_time, change.start_time);
+    set(&mut detail.cwd, change.cwd);
+    set(&mut detail.fds, change.fds);
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn proc(pid: u32, cpu: f32) -> Process{
+        Process{
+            pid, name: format!("proc{}", pid), exe: String::new(), cpu, mem: 1 << 20, status: "Sleeping".to_string(),
+            cmd: String::new(), parent: Some(1), user_id: None, thread: false, detail: None
+        }
+    }
+
+    fn sorted(mut procs: Vec<Process>) -> Vec<Process>{
+        procs.sort_by_key(|p| p.pid);
+        procs
+    }
+
+    #[test]
+    fn round_trip(){
+        let mut encoder = Encoder::default();
+        let mut table = ProcessTable::default();
+        let tables = [
+            vec![proc(1, 0.0), proc(2, 5.0), proc(3, 1.0)],
+            vec![proc(1, 0.0), proc(2, 7.5), proc(4, 2.0)], // 2 changed, 3 gone, 4 new
+            vec![proc(1, 0.5), proc(4, 2.0), proc(5, 0.0)]
+        ];
+        for (idx, procs) in tables.iter().enumerate(){
+            let delta = encoder.encode(procs, 100, 1);
+            assert_eq!(delta.keyframe, idx == 0);
+            if idx == 1{
+                assert_eq!(delta.added.iter().map(|p| p.pid).collect::<Vec<_>>(), [4]);
+                assert_eq!(delta.removed, [3]);
+                assert_eq!(delta.changed.iter().map(|c| (c.pid, c.cpu, c.mem)).collect::<Vec<_>>(), [(2, Some(7.5), None)]);
+            }
+            assert_eq!(sorted(table.apply(delta).unwrap().unwrap()), *procs);
+        }
+    }
+
+    #[test]
+    fn detail_changes_by_field(){
+        let detail = ProcessDetail{
+            read: 0, written: 0, t_read: 4096, t_written: 0, virtual_mem: 1 << 30, threads: Some(4),
+            start_time: 1_700_000_000, cwd: "/srv".to_string(), fds: Some(12)
+        };
+        let old = Process { detail: Some(detail.clone()), ..proc(1, 0.0) };
+        let new = Process { detail: Some(ProcessDetail { read: 512, t_read: 4608, ..detail.clone() }), ..proc(1, 0.0) };
+
+        let mut encoder = Encoder::default();
+        let mut table = ProcessTable::default();
+        table.apply(encoder.encode(std::slice::from_ref(&old), 100, 1)).unwrap();
+        let mut delta = encoder.encode(std::slice::from_ref(&new), 100, 1);
+        let Some(DetailChange::Fields(change)) = &delta.changed[0].detail else { panic!("{:?}", delta.changed) };
+        assert_eq!((change.read, change.t_read, change.written, change.cwd.as_ref()), (Some(512), Some(4608), None, None));
+        assert_eq!(table.apply(delta).unwrap().unwrap(), std::slice::from_ref(&new));
+
+        // Unchanged detail keeps an otherwise unchanged process out of the delta.
+        delta = encoder.encode(std::slice::from_ref(&new), 100, 1);
+        assert!(delta.changed.is_empty());
+        assert_eq!(new.detail.as_ref().unwrap().run_time(1_700_000_090_500), 90); // Run time grows without anything to send
+        table.apply(delta).unwrap();
+
+        // Detail that goes away, as after a reload to the basic level, is sent whole.
+        let basic = Process { detail: None, ..new };
+        delta = encoder.encode(std::slice::from_ref(&basic), 100, 1);
+        assert!(matches!(delta.changed[0].detail, Some(DetailChange::Whole(None))));
+        assert_eq!(table.apply(delta).unwrap().unwrap(), [basic]);
+    }
+
+    #[test]
+    fn keyframe_every(){
+        let mut encoder = Encoder::default();
+        let keyframes: Vec<bool> = (0..7).map(|_| encoder.encode(&[proc(1, 0.0)], 3, 1).keyframe).collect();
+        assert_eq!(keyframes, [true, false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn keyframe_for_a_new_subscriber(){
+        let mut encoder = Encoder::default();
+        let procs = [proc(1, 0.0)];
+        assert!(encoder.encode(&procs, 100, 1).keyframe);
+        assert!(!encoder.encode(&procs, 100, 1).keyframe);
+        assert!(encoder.encode(&procs, 100, 2).keyframe);
+        assert!(!encoder.encode(&procs, 100, 1).keyframe); // One leaving needs nothing
+    }
+
+    /// Six tables of one process, a keyframe every five.
+    fn deltas() -> Vec<ProcessDelta>{
+        let mut encoder = Encoder::default();
+        (0..6).map(|n| encoder.encode(&[proc(1, n as f32)], 5, 1)).collect()
+    }
+
+    #[test]
+    fn desync_until_the_next_keyframe(){
+        // Nothing can be built before the first keyframe.
+        let mut table = ProcessTable::default();
+        assert!(matches!(table.apply(deltas().remove(1)), Ok(None)));
+
+        // The third table is lost, what follows is refused until the keyframe of the sixth.
+        let mut table = ProcessTable::default();
+        let mut deltas = deltas().into_iter();
+        assert!(table.apply(deltas.next().unwrap()).unwrap().is_some());
+        assert!(table.apply(deltas.next().unwrap()).unwrap().is_some());
+        deltas.next();
+        assert!(matches!(table.apply(deltas.next().unwrap()), Err(Desync { expected: 3, got: 4 })));
+        assert!(matches!(table.apply(deltas.next().unwrap()), Ok(None)));
+        let sixth = deltas.next().unwrap();
+        assert!(sixth.keyframe);
+        assert_eq!(table.apply(sixth).unwrap().unwrap(), [proc(1, 5.0)]);
+    }
+}
@@ -0,0 +1,625 @@
+use std::{
+    fmt::Write as _,
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use anyhow::Error;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, time::Duration};
+
+use crate::{config::config, models::{BlockDevice, Cgroup, Component, Cpus, Data, DiskData, Interface, Lifecycle, Memory, Meta, Networks, Pressure, Process, Unit}, SHUTDOWN};
+
+// The latest sample of every kind, replaced by the transmitter as data goes out.
+static LATEST: OnceLock<Mutex<Latest>> = OnceLock::new();
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+// Largest request head we read, scrapers send a few hundred bytes.
+const MAX_REQUEST: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Metric name, help text and how to read the value from a sample.
+type Field<T> = (&'static str, &'static str, fn(&T) -> u64);
+// The same for readings a sample may not have.
+type Reading<T> = (&'static str, &'static str, fn(&T) -> Option<f32>);
+// The same for values a sample may not have, already in the metric's unit.
+type Limit<T> = (&'static str, &'static str, fn(&T) -> Option<f64>);
+
+#[derive(Default)]
+struct Latest{
+    meta: Option<Meta>,
+    cpus: Vec<Cpus>,
+    components: Vec<Component>,
+    pressure: Vec<Pressure>,
+    cgroups: Vec<Cgroup>,
+    units: Vec<Unit>,
+    memory: Option<Memory>,
+    disks: Vec<DiskData>,
+    block_devices: Vec<BlockDevice>,
+    networks: Vec<Networks>,
+    interfaces: Vec<Interface>,
+    processes: Vec<Process>,
+    started: u64, // Process starts and exits seen since the agent started
+    exited: u64,
+    updated: Vec<(&'static str, f64)> // Unix time of the last sample per kind
+}
+
+impl Latest{
+    fn touch(&mut self, kind: &'static str){
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        match self.updated.iter_mut().find(|(k, _)| *k == kind){
+            Some((_, t)) => *t = now,
+            None => self.updated.push((kind, now))
+        }
+    }
+}
+
+/// Keep `data` as the latest sample of its kind for the next scrape.
+pub fn record(data: Data){
+    let mut latest = match LATEST.get_or_init(Default::default).lock(){
+        Ok(latest) => latest,
+        Err(poisoned) => {
+            eprintln!("FATAL: LATEST lock was poisoned!, Recovering");
+            poisoned.into_inner()
+        }
+    };
+    match data{
+        Data::Meta(meta) => {
+            latest.meta = Some(meta);
+            latest.touch("meta");
+        }
+        Data::Cpus(cpus) => {
+            latest.cpus = cpus;
+            latest.touch("cpus");
+        }
+        Data::Components(components) => {
+            latest.components = components;
+            latest.touch("components");
+        }
+        Data::Pressure(pressure) => {
+            latest.pressure = pressure;
+            latest.touch("pressure");
+        }
+        Data::Cgroups(cgroups) => {
+            latest.cgroups = cgroups;
+            latest.touch("cgroups");
+        }
+        Data::Units(units) => {
+            latest.units = units;
+            latest.touch("units");
+        }
+        Data::Memory(mem) => {
+            latest.memory = Some(mem);
+            latest.touch("memory");
+        }
+        Data::Disk(disks) => {
+            latest.disks = disks;
+            latest.touch("disks");
+        }
+        Data::BlockDevices(devices) => {
+            latest.block_devices = devices;
+            latest.touch("blockio");
+        }
+        Data::Networks(nets) => {
+            latest.networks = nets;
+            latest.touch("networks");
+        }
+        Data::Interfaces(interfaces) => {
+            latest.interfaces = interfaces;
+            latest.touch("interfaces");
+        }
+        Data::Process(procs) => {
+            latest.processes = procs;
+            latest.touch("processes");
+        }
+        Data::ProcessEvents(events) => {
+            for event in events{
+                match event.event{
+                    Lifecycle::Started => latest.started += 1,
+                    Lifecycle::Exited => latest.exited += 1
+                }
+            }
+        }
+        Data::Sockets(_) | Data::Alert(_) | Data::ProcessDelta(_) | Data::ShuttingDown => {}
+    }
+}
+
+/// Serve `/metrics` on `exporter.listen` until the agent shuts down.
+pub async fn main() -> Result<(), Error>{
+    let listener = TcpListener::bind(&config().exporter.listen).await?;
+    let Some(shutdown) = SHUTDOWN.get() else {
+        return Ok(());
+    };
+    let mut shutdown = shutdown.subscribe();
+
+    loop{
+        tokio::select!{
+            conn = listener.accept() => {
+                match conn{
+                    Ok((stream, _)) => {
+                        tokio::spawn(async move{
+                            if let Err(e) = serve(stream).await{
+                                eprintln!("Exporter connection failed: {:?}", e);
+                            }
+                        });
+                    }
+                    Err(e) => eprintln!("Exporter failed to accept a connection: {:?}", e)
+                }
+            }
+            _ = shutdown.wait_for(|down| *down) => break
+        }
+    }
+    Ok(())
+}
+
+/// Answer one request and close the connection.
+async fn serve(mut stream: TcpStream) -> Result<(), Error>{
+    let mut head = Vec::with_capacity(512);
+    let mut buf = [0u8; 1024];
+    tokio::time::timeout(REQUEST_TIMEOUT, async{
+        while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_REQUEST{
+            let n = stream.read(&mut buf).await?;
+            if n == 0{
+                break;
+            }
+            head.extend_from_slice(&buf[..n]);
+        }
+        Ok::<_, Error>(())
+    }).await??;
+
+    let head = String::from_utf8_lossy(&head);
+    let mut parts = head.lines().next().unwrap_or("").split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = path.split('?').next().unwrap_or("");
+
+    let (status, content_type, body) = match (method, path){
+        ("GET" | "HEAD", "/metrics") => ("200 OK", CONTENT_TYPE, render()),
+        ("GET" | "HEAD", _) => ("404 Not Found", "text/plain", "Not found, try /metrics\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "Only GET and HEAD are supported\n".to_string())
+    };
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, content_type, body.len()
+    );
+    if method != "HEAD"{
+        response.push_str(&body);
+    }
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// The exposition of the latest samples in the OpenMetrics text format.
+fn render() -> String{
+    let latest = match LATEST.get_or_init(Default::default).lock(){
+        Ok(latest) => latest,
+        Err(poisoned) => {
+            eprintln!("FATAL: LATEST lock was poisoned!, Recovering");
+            poisoned.into_inner()
+        }
+    };
+    exposition(&latest, config().exporter.max_processes)
+}
+
+/// `latest` in the OpenMetrics text format, with `max_processes` processes at most.
+fn exposition(latest: &Latest, max_processes: usize) -> String{
+    let mut out = Metrics::default();
+
+    if let Some(meta) = &latest.meta{
+        out.family("aware_system", "info", "Host and operating system of the agent");
+        out.sample("aware_system_info", &[
+            ("host", &meta.host_name), ("name", &meta.name), ("os", &meta.os), ("kernel", &meta.kernel), ("arch", &meta.arch), ("distro", &meta.distro)
+        ], 1.0);
+        out.family("aware_cpu_count", "gauge", "Number of logical cpus");
+        out.sample("aware_cpu_count", &[], meta.n_cpu as f64);
+        if let Some(cores) = meta.n_core{
+            out.family("aware_cpu_physical_count", "gauge", "Number of physical cores");
+            out.sample("aware_cpu_physical_count", &[], cores as f64);
+        }
+        for (name, help, load) in [
+            ("aware_load1", "Load average over 1 minute", meta.load[0]),
+            ("aware_load5", "Load average over 5 minutes", meta.load[1]),
+            ("aware_load15", "Load average over 15 minutes", meta.load[2])
+        ]{
+            out.family(name, "gauge", help);
+            out.sample(name, &[], load);
+        }
+        out.family("aware_boot_time_seconds", "gauge", "Unix time the system booted");
+        out.sample("aware_boot_time_seconds", &[], meta.boot_time as f64);
+        out.family("aware_uptime_seconds", "gauge", "Time since the system booted");
+        out.sample("aware_uptime_seconds", &[], meta.uptime as f64);
+        out.family("aware_processes", "gauge", "Number of processes");
+        out.sample("aware_processes", &[], meta.n_proc as f64);
+        out.family("aware_cpu_usage_ratio", "gauge", "Usage of all cpus together");
+        out.sample("aware_cpu_usage_ratio", &[], meta.glob_cpu as f64 / 100.0);
+    }
+
+    if !latest.cpus.is_empty(){
+        out.family("aware_cpu_core_usage_ratio", "gauge", "Usage per logical cpu");
+        for cpu in &latest.cpus{
+            out.sample("aware_cpu_core_usage_ratio", &[("cpu", &cpu.cpu_name)], cpu.cpu_per as f64 / 100.0);
+        }
+        out.family("aware_cpu_core_frequency_hertz", "gauge", "Frequency per logical cpu");
+        for cpu in &latest.cpus{
+            out.sample("aware_cpu_core_frequency_hertz", &[("cpu", &cpu.cpu_name)], cpu.freq as f64 * 1e6);
+        }
+    }
+
+    if !latest.pressure.is_empty(){
+        let stalls = || latest.pressure.iter().flat_map(|p|
+            [("some", Some(p.some)), ("full", p.full)].into_iter().filter_map(|(kind, s)| s.map(|s| (p.resource.name(), kind, s)))
+        );
+        out.family("aware_pressure_stalled_seconds", "counter", "Time tasks were stalled waiting for the resource");
+        for (resource, kind, s) in stalls(){
+            out.sample("aware_pressure_stalled_seconds_total", &[("resource", resource), ("kind", kind)], s.total as f64 / 1e6);
+        }
+        out.family("aware_pressure_avg10_ratio", "gauge", "Share of the last 10 seconds tasks were stalled");
+        for (resource, kind, s) in stalls(){
+            out.sample("aware_pressure_avg10_ratio", &[("resource", resource), ("kind", kind)], s.avg10 as f64 / 100.0);
+        }
+    }
+
+    // Sensors that do not report a value are left out of its family.
+    let families: [Reading<Component>; 3] = [
+        ("aware_component_temperature_celsius", "Temperature per sensor", |c| c.temperature),
+        ("aware_component_max_temperature_celsius", "Highest temperature per sensor since the agent started", |c| c.max),
+        ("aware_component_critical_temperature_celsius", "Temperature per sensor the hardware considers critical", |c| c.critical)
+    ];
+    for (name, help, value) in families{
+        if latest.components.iter().any(|c| value(c).is_some()){
+            out.family(name, "gauge", help);
+            for c in &latest.components{
+                if let Some(t) = value(c){
+                    out.sample(name, &[("component", &c.label)], t as f64);
+                }
+            }
+        }
+    }
+
+    if let Some(mem) = &latest.memory{
+        for (name, help, value) in [
+            ("aware_memory_total_bytes", "Total memory", mem.t_ram),
+            ("aware_memory_used_bytes", "Used memory", mem.u_ram),
+            ("aware_memory_available_bytes", "Memory available for new allocations", mem.a_ram),
+            ("aware_swap_total_bytes", "Total swap", mem.t_swap),
+            ("aware_swap_used_bytes", "Used swap", mem.u_swap),
+            ("aware_swap_free_bytes", "Free swap", mem.a_swap)
+        ]{
+            out.family(name, "gauge", help);
+            out.sample(name, &[], value as f64);
+        }
+    }
+
+    if !latest.disks.is_empty(){
+        let gauges: [Field<DiskData>; 2] = [
+            ("aware_disk_total_bytes", "Size of the filesystem", |d| d.t_space),
+            ("aware_disk_available_bytes", "Space available on the filesystem", |d| d.a_space)
+        ];
+        for (name, help, value) in gauges{
+            out.family(name, "gauge", help);
+            for disk in &latest.disks{
+                out.sample(name, &[("mount", &disk.loc), ("device", &disk.name), ("fs", &disk.fs)], value(disk) as f64);
+            }
+        }
+        // Filesystems without a fixed number of inodes are left out.
+        let inodes: [Limit<DiskData>; 2] = [
+            ("aware_disk_inodes", "Inodes of the filesystem", |d| d.t_inodes.map(|i| i as f64)),
+            ("aware_disk_inodes_free", "Inodes free on the filesystem", |d| d.a_inodes.map(|i| i as f64))
+        ];
+        for (name, help, value) in inodes{
+            out.family(name, "gauge", help);
+            for disk in &latest.disks{
+                if let Some(v) = value(disk){
+                    out.sample(name, &[("mount", &disk.loc), ("device", &disk.name), ("fs", &disk.fs)], v);
+                }
+            }
+        }
+        out.family("aware_disk_mount", "info", "Mount options and backing block device of the filesystem");
+        for disk in &latest.disks{
+            out.sample("aware_disk_mount_info", &[
+                ("mount", &disk.loc), ("device", &disk.name), ("block_device", disk.device.as_deref().unwrap_or("")), ("options", &disk.options.join(","))
+            ], 1.0);
+        }
+        let counters: [Field<DiskData>; 2] = [
+            ("aware_disk_written_bytes", "Bytes written to the disk", |d| d.t_written),
+            ("aware_disk_read_bytes", "Bytes read from the disk", |d| d.t_read)
+        ];
+        for (name, help, value) in counters{
+            out.family(name, "counter", help);
+            for disk in &latest.disks{
+                out.sample(&format!("{}_total", name), &[("mount", &disk.loc), ("device", &disk.name)], value(disk) as f64);
+            }
+        }
+    }
+
+    if !latest.block_devices.is_empty(){
+        let counters: [Field<BlockDevice>; 4] = [
+            ("aware_block_reads", "Reads completed by the block device", |d| d.t_reads),
+            ("aware_block_writes", "Writes completed by the block device", |d| d.t_writes),
+            ("aware_block_read_bytes", "Bytes read from the block device", |d| d.t_read),
+            ("aware_block_written_bytes", "Bytes written to the block device", |d| d.t_written)
+        ];
+        for (name, help, value) in counters{
+            out.family(name, "counter", help);
+            for d in &latest.block_devices{
+                out.sample(&format!("{}_total", name), &[("device", &d.name)], value(d) as f64);
+            }
+        }
+        out.family("aware_block_io_time_seconds", "counter", "Time the block device had requests in flight");
+        for d in &latest.block_devices{
+            out.sample("aware_block_io_time_seconds_total", &[("device", &d.name)], d.t_busy_ms as f64 / 1000.0);
+        }
+        out.family("aware_block_in_flight_requests", "gauge", "Requests in flight on the block device");
+        for d in &latest.block_devices{
+            out.sample("aware_block_in_flight_requests", &[("device", &d.name)], d.in_flight as f64);
+        }
+    }
+
+    if !latest.networks.is_empty(){
+        let counters: [Field<Networks>; 6] = [
+            ("aware_network_received_bytes", "Bytes received", |n| n.t_down),
+            ("aware_network_transmitted_bytes", "Bytes transmitted", |n| n.t_up),
+            ("aware_network_received_packets", "Packets received", |n| n.t_packet_rx),
+            ("aware_network_transmitted_packets", "Packets transmitted", |n| n.t_packet_tx),
+            ("aware_network_receive_errors", "Errors while receiving", |n| n.t_err_rx),
+            ("aware_network_transmit_errors", "Errors while transmitting", |n| n.t_err_tx)
+        ];
+        for (name, help, value) in counters{
+            out.family(name, "counter", help);
+            for net in &latest.networks{
+                out.sample(&format!("{}_total", name), &[("interface", &net.name)], value(net) as f64);
+            }
+        }
+    }
+
+    if !latest.interfaces.is_empty(){
+        out.family("aware_network", "info", "Link of the interface");
+        for i in &latest.interfaces{
+            out.sample("aware_network_info", &[("interface", &i.name), ("mac", i.mac.as_deref().unwrap_or("")), ("state", &i.state)], 1.0);
+        }
+        out.family("aware_network_address", "info", "Address of the interface, with its prefix");
+        for i in &latest.interfaces{
+            for addr in &i.addresses{
+                out.sample("aware_network_address_info", &[("interface", &i.name), ("address", addr)], 1.0);
+            }
+        }
+        out.family("aware_network_up", "gauge", "Whether the interface is up");
+        for i in &latest.interfaces{
+            out.sample("aware_network_up", &[("interface", &i.name)], if i.up() { 1.0 } else { 0.0 });
+        }
+        out.family("aware_network_mtu_bytes", "gauge", "Largest packet the interface sends");
+        for i in &latest.interfaces{
+            out.sample("aware_network_mtu_bytes", &[("interface", &i.name)], i.mtu as f64);
+        }
+        if latest.interfaces.iter().any(|i| i.speed.is_some()){
+            out.family("aware_network_speed_bytes", "gauge", "Link speed in bytes per second, where the driver tells");
+            for i in &latest.interfaces{
+                if let Some(speed) = i.speed{
+                    out.sample("aware_network_speed_bytes", &[("interface", &i.name)], speed as f64 * 1e6 / 8.0);
+                }
+            }
+        }
+    }
+
+    if !latest.cgroups.is_empty(){
+        let counters: [Field<Cgroup>; 4] = [
+            ("aware_cgroup_cpu_seconds", "Cpu time used by the cgroup and those below it", |c| c.cpu_usage),
+            ("aware_cgroup_cpu_throttled_seconds", "Time the cgroup was held back by its cpu limit", |c| c.cpu_throttled),
+            ("aware_cgroup_read_bytes", "Bytes the cgroup read from block devices", |c| c.t_read),
+            ("aware_cgroup_written_bytes", "Bytes the cgroup wrote to block devices", |c| c.t_written)
+        ];
+        for (name, help, value) in counters{
+            out.family(name, "counter", help);
+            // cpu.stat counts microseconds.
+            let scale = if name.ends_with("_seconds") { 1e-6 } else { 1.0 };
+            for c in &latest.cgroups{
+                out.sample(&format!("{}_total", name), &[("cgroup", &c.path)], value(c) as f64 * scale);
+            }
+        }
+        // Unlimited cgroups are left out of the limit families.
+        let gauges: [Limit<Cgroup>; 6] = [
+            ("aware_cgroup_memory_bytes", "Memory charged to the cgroup", |c| c.mem.map(|m| m as f64)),
+            ("aware_cgroup_memory_limit_bytes", "Memory the cgroup may use at most", |c| c.mem_max.map(|m| m as f64)),
+            ("aware_cgroup_cpu_limit", "Cpus the cgroup may use at most", |c| c.cpu_max.map(|m| m as f64)),
+            ("aware_cgroup_pids", "Tasks in the cgroup and those below it", |c| c.pids.map(|p| p as f64)),
+            ("aware_cgroup_pids_limit", "Tasks the cgroup may have at most", |c| c.pids_max.map(|p| p as f64)),
+            ("aware_cgroup_processes", "Processes the agent attributed to the cgroup", |c| Some(c.procs.len() as f64))
+        ];
+        for (name, help, value) in gauges{
+            if latest.cgroups.iter().any(|c| value(c).is_some()){
+                out.family(name, "gauge", help);
+                for c in &latest.cgroups{
+                    if let Some(v) = value(c){
+                        out.sample(name, &[("cgroup", &c.path)], v);
+                    }
+                }
+            }
+        }
+    }
+
+    if !latest.units.is_empty(){
+        out.family("aware_unit", "info", "Systemd unit and its states");
+        for u in &latest.units{
+            out.sample("aware_unit_info", &[("unit", &u.name), ("load", &u.load), ("active", &u.active), ("sub", &u.sub)], 1.0);
+        }
+        out.family("aware_unit_restarts", "counter", "Automatic restarts of the service");
+        for u in &latest.units{
+            if let Some(restarts) = u.restarts{
+                out.sample("aware_unit_restarts_total", &[("unit", &u.name)], restarts as f64);
+            }
+        }
+        // Resource use of the main process, for units with one among the latest processes.
+        let linked: Vec<(&Unit, &Process)> = latest.units.iter().filter_map(|u| Some((u, u.process(&latest.processes)?))).collect();
+        if !linked.is_empty(){
+            out.family("aware_unit_cpu_usage_ratio", "gauge", "Cpu usage of the unit's main process, 1 is one full cpu");
+            for (u, p) in &linked{
+                out.sample("aware_unit_cpu_usage_ratio", &[("unit", &u.name), ("pid", &p.pid.to_string())], p.cpu as f64 / 100.0);
+            }
+            out.family("aware_unit_memory_bytes", "gauge", "Resident memory of the unit's main process");
+            for (u, p) in &linked{
+                out.sample("aware_unit_memory_bytes", &[("unit", &u.name), ("pid", &p.pid.to_string())], p.mem as f64);
+            }
+        }
+    }
+
+    if !latest.processes.is_empty(){
+        // A series per pid would grow without bound, so only the busiest processes are exported.
+        // Threads are listed like processes, their usage is already in their process's.
+        let mut procs: Vec<&Process> = latest.processes.iter().filter(|p| !p.thread).collect();
+        procs.sort_by(|a, b| b.cpu.total_cmp(&a.cpu).then(b.mem.cmp(&a.mem)));
+        let dropped = procs.len().saturating_sub(max_processes);
+        procs.truncate(max_processes);
+
+        out.family("aware_process_cpu_usage_ratio", "gauge", "Cpu usage per process, 1 is one full cpu");
+        for p in &procs{
+            out.sample("aware_process_cpu_usage_ratio", &[("pid", &p.pid.to_string()), ("name", &p.name)], p.cpu as f64 / 100.0);
+        }
+        out.family("aware_process_memory_bytes", "gauge", "Resident memory per process");
+        for p in &procs{
+            out.sample("aware_process_memory_bytes", &[("pid", &p.pid.to_string()), ("name", &p.name)], p.mem as f64);
+        }
+        out.family("aware_process_series_dropped", "gauge", "Processes left out by exporter.max_processes");
+        out.sample("aware_process_series_dropped", &[], dropped as f64);
+
+        out.family("aware_process_starts", "counter", "Processes seen starting");
+        out.sample("aware_process_starts_total", &[], latest.started as f64);
+        out.family("aware_process_exits", "counter", "Processes seen exiting");
+        out.sample("aware_process_exits_total", &[], latest.exited as f64);
+    }
+
+    if !latest.updated.is_empty(){
+        out.family("aware_last_update_timestamp_seconds", "gauge", "When each kind of data was last collected");
+        for (kind, t) in &latest.updated{
+            out.sample("aware_last_update_timestamp_seconds", &[("kind", kind)], *t);
+        }
+    }
+
+    out.text.push_str("# EOF\n");
+    out.text
+}
+
+#[derive(Default)]
+struct Metrics{
+    text: String
+}
+
+impl Metrics{
+    fn family(&mut self, name: &str, kind: &str, help: &str){
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        if let Some(unit) = ["bytes", "seconds", "hertz", "ratio", "celsius"].into_iter().find(|u| name.ends_with(&format!("_{}", u))){
+            let _ = writeln!(self.text, "# UNIT {} {}", name, unit);
+        }
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64){
+        self.text.push_str(name);
+        if !labels.is_empty(){
+            self.text.push('{');
+            for (idx, (key, value)) in labels.iter().enumerate(){
+                if idx > 0{
+                    self.text.push(',');
+                }
+                let _ = write!(self.text, "{}=\"{}\"", key, escape(value));
+            }
+            self.text.push('}');
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+}
+
+fn escape(value: &str) -> String{
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::models::{PressureResource, Stalls};
+
+    fn process(pid: u32, name: &str, cpu: f32, thread: bool) -> Process{
+        Process{
+            pid, name: name.to_string(), exe: String::new(), cpu, mem: pid as u64 * 1024, status: "Run".to_string(), cmd: String::new(),
+            parent: thread.then_some(1), user_id: None, thread, detail: None
+        }
+    }
+
+    fn network(name: &str, total: u64) -> Networks{
+        Networks{
+            name: name.to_string(), t_down: total, down: 0, t_up: total, up: 0, t_packet_rx: total, packet_rx: 0, t_packet_tx: total,
+            packet_tx: 0, t_err_rx: 0, err_rx: 0, t_err_tx: 0, err_tx: 0
+        }
+    }
+
+    #[test]
+    fn text(){
+        let latest = Latest{
+            memory: Some(Memory { t_ram: 8192, u_ram: 4096, a_ram: 2048, t_swap: 0, u_swap: 0, a_swap: 0 }),
+            ..Default::default()
+        };
+        let text = exposition(&latest, 10);
+        assert!(text.starts_with(concat!(
+            "# TYPE aware_memory_total_bytes gauge\n",
+            "# HELP aware_memory_total_bytes Total memory\n",
+            "# UNIT aware_memory_total_bytes bytes\n",
+            "aware_memory_total_bytes 8192\n",
+            "# TYPE aware_memory_used_bytes gauge\n"
+        )));
+        assert!(text.contains("\naware_memory_available_bytes 2048\n"));
+        assert!(text.ends_with("aware_swap_free_bytes 0\n# EOF\n"));
+
+        // Nothing collected yet is an empty exposition, not an error.
+        assert_eq!(exposition(&Latest::default(), 10), "# EOF\n");
+    }
+
+    #[test]
+    fn label_escaping(){
+        let mut out = Metrics::default();
+        out.sample("aware_x", &[("a", "say \"hi\""), ("b", "C:\\temp"), ("c", "two\nlines")], 1.5);
+        assert_eq!(out.text, "aware_x{a=\"say \\\"hi\\\"\",b=\"C:\\\\temp\",c=\"two\\nlines\"} 1.5\n");
+    }
+
+    #[test]
+    fn busiest_processes_only(){
+        let latest = Latest{
+            processes: vec![process(1, "init", 1.0, false), process(2, "busy", 90.0, false), process(3, "worker", 99.0, true), process(4, "mid", 50.0, false)],
+            ..Default::default()
+        };
+        let text = exposition(&latest, 2);
+        let pids: Vec<&str> = text.lines()
+            .filter(|l| l.starts_with("aware_process_cpu_usage_ratio{"))
+            .filter_map(|l| l.split('"').nth(1))
+            .collect();
+        assert_eq!(pids, ["2", "4"]); // The thread is no process of its own and takes no slot
+        assert!(text.contains("\naware_process_series_dropped 1\n"));
+
+        let text = exposition(&latest, 10);
+        assert!(text.contains("\naware_process_series_dropped 0\n"));
+        assert!(!text.contains("name=\"worker\""));
+    }
+
+    #[test]
+    fn counters_end_in_total(){
+        let latest = Latest{
+            networks: vec![network("eth0", 100), network("lo", 5)],
+            pressure: vec![Pressure { resource: PressureResource::Io, some: Stalls { total: 2_000_000, ..Default::default() }, full: None, triggered: false }],
+            processes: vec![process(1, "init", 0.0, false)],
+            started: 3,
+            exited: 2,
+            ..Default::default()
+        };
+        let text = exposition(&latest, 10);
+        let counters: Vec<&str> = text.lines()
+            .filter_map(|l| l.strip_prefix("# TYPE ")?.strip_suffix(" counter"))
+            .collect();
+        assert!(counters.len() >= 9);
+        for family in counters{
+            assert!(!family.ends_with("_total"), "{}", family);
+            let samples: Vec<&str> = text.lines().filter(|l| !l.starts_with('#') && l.starts_with(family)).collect();
+            assert!(!samples.is_empty());
+            assert!(samples.iter().all(|l| l[family.len()..].starts_with("_total")), "{:?}", samples);
+        }
+        assert!(text.contains("\naware_network_received_bytes_total{interface=\"eth0\"} 100\n"));
+        assert!(text.contains("\naware_pressure_stalled_seconds_total{resource=\"io\",kind=\"some\"} 2\n"));
+        assert!(text.contains("\naware_process_exits_total 2\n"));
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+use crate::{delta::{Desync, ProcessTable}, models::{Data, Telemetry, TelemetryKind}, MAX_SIZE};
+
+// How many half received messages we keep around before giving up on the oldest one.
+const MAX_PARTIALS: usize = 8;
+// How far `seq` or `msg_id` may go back before we take it for a publisher that started over rather than a late sample.
+const RESTART_GAP: u64 = 64;
+// Largest message we put together, a header claiming more is corrupt.
+const MAX_MESSAGE: u32 = 64 << 20;
+
+pub fn checksum(bytes: &[u8]) -> u32{
+    crc32fast::hash(bytes)
+}
+
+#[derive(Debug)]
+pub enum FrameError{
+    /// Samples with a sequence number in `expected..got` never arrived.
+    Lost{ expected: u64, got: u64 },
+    /// The header is inconsistent or the checksum doesn't match the payload.
+    Corrupt{ msg_id: u64, chunk: u32 },
+    /// A chunk of a message that was already completed or given up on.
+    Stale{ msg_id: u64, chunk: u32 },
+    /// A message was given up on before all of its chunks arrived.
+    Incomplete{ msg_id: u64, kind: TelemetryKind, received: u32, n_chunks: u32 },
+    /// All chunks arrived but the payload doesn't decode into `Data`.
+    Decode{ msg_id: u64, kind: TelemetryKind, err: bincode::Error },
+    /// A process delta can't be applied because an earlier one never arrived.
+    Desync(Desync),
+}
+
+impl fmt::Display for FrameError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            FrameError::Lost { expected, got } => write!(f, "lost {} sample(s) (seq {} to {})", got - expected, expected, got - 1),
+            FrameError::Corrupt { msg_id, chunk } => write!(f, "corrupt chunk {} of message {}", chunk, msg_id),
+            FrameError::Stale { msg_id, chunk } => write!(f, "stale chunk {} of message {}", chunk, msg_id),
+            FrameError::Incomplete { msg_id, kind, received, n_chunks } => write!(f, "message {} ({:?}) incomplete, got {} of {} chunks", msg_id, kind, received, n_chunks),
+            FrameError::Decode { msg_id, kind, err } => write!(f, "message {} ({:?}) failed to decode: {}", msg_id, kind, err),
+            FrameError::Desync(desync) => write!(f, "{}", desync),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameStats{
+    pub samples: u64,
+    pub messages: u64,
+    pub lost: u64,         // samples that never arrived
+    pub out_of_order: u64, // chunks that arrived before an earlier chunk of the same message
+    pub corrupt: u64,
+    pub stale: u64,
+    pub incomplete: u64,
+    pub undecodable: u64,
+    pub desynced: u64,     // process deltas dropped until the next keyframe
+}
+
+struct Partial{
+    kind: TelemetryKind,
+    n_chunks: u32,
+    total_len: u32,
+    received: u32,
+    next_chunk: u32,
+    chunks: BTreeMap<u32, Vec<u8>>, // Only those received, the header sizes nothing before the checksum is known good
+}
+
+/// Rebuilds `Data` messages from the `Telemetry` samples published by the transmitter.
+/// Process deltas are applied on the way, they come out as the whole `Data::Process` table.
+///
+/// Feed every received sample to `push`, then drain the outcomes with `pop`:
+/// ```ignore
+/// reassembler.push(&sample);
+/// while let Some(res) = reassembler.pop(){
+///     match res{
+///         Ok(data) => ...,
+///         Err(e) => eprintln!("{}", e)
+///     }
+/// }
+/// ```
+#[derive(Default)]
+pub struct Reassembler{
+    next_seq: Option<u64>,
+    last_done: Option<u64>,
+    partials: BTreeMap<u64, Partial>,
+    out: VecDeque<Result<Data, FrameError>>,
+    stats: FrameStats,
+    processes: ProcessTable,
+}
+
+impl Reassembler{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    pub fn stats(&self) -> FrameStats{
+        self.stats
+    }
+
+    pub fn pop(&mut self) -> Option<Result<Data, FrameError>>{
+        self.out.pop_front()
+    }
+
+    /// Forget everything seen so far, e.g. after the publisher restarted. Outcomes not popped yet are dropped too.
+    pub fn reset(&mut self){
+        self.next_seq = None;
+        self.last_done = None;
+        self.partials.clear();
+        self.out.clear();
+        self.processes.reset();
+    }
+
+    pub fn push(&mut self, sample: &Telemetry){
+        self.stats.samples += 1;
+        if self.restarted(sample){
+            self.reset(); // The publisher started over
+        }
+        self.track_seq(sample.seq);
+
+        let (msg_id, chunk) = (sample.msg_id, sample.chunk);
+        let (n_chunks, total_len) = (sample.n_chunks, sample.total_len);
+        // Each chunk holds at least one byte and at most `MAX_SIZE`, an empty message is one empty chunk.
+        let consistent = n_chunks != 0 && chunk < n_chunks && total_len <= MAX_MESSAGE
+            && n_chunks <= total_len.max(1) && n_chunks as usize >= (total_len as usize).div_ceil(MAX_SIZE);
+        let kind = sample.kind().filter(|_| consistent && sample.len as usize <= MAX_SIZE && checksum(sample.payload()) == sample.checksum);
+        let Some(kind) = kind else {
+            self.stats.corrupt += 1;
+            self.out.push_back(Err(FrameError::Corrupt { msg_id, chunk }));
+            return;
+        };
+        if !self.partials.contains_key(&msg_id) && self.last_done.is_some_and(|done| msg_id <= done){
+            self.stats.stale += 1;
+            self.out.push_back(Err(FrameError::Stale { msg_id, chunk }));
+            return;
+        }
+
+        let partial = self.partials.entry(msg_id).or_insert_with(|| Partial{
+            kind,
+            n_chunks,
+            total_len,
+            received: 0,
+            next_chunk: 0,
+            chunks: BTreeMap::new(),
+        });
+        if partial.n_chunks != n_chunks || partial.total_len != total_len || partial.kind != kind{
+            self.stats.corrupt += 1;
+            self.out.push_back(Err(FrameError::Corrupt { msg_id, chunk }));
+            return;
+        }
+        if partial.chunks.contains_key(&chunk){
+            self.stats.stale += 1;
+            self.out.push_back(Err(FrameError::Stale { msg_id, chunk }));
+            return;
+        }
+        partial.chunks.insert(chunk, sample.payload().to_vec());
+        partial.received += 1;
+        if chunk != partial.next_chunk{
+            self.stats.out_of_order += 1;
+        }
+        partial.next_chunk = partial.next_chunk.max(chunk + 1);
+
+        if partial.received == partial.n_chunks{
+            self.complete(msg_id);
+        }
+        else if self.partials.len() > MAX_PARTIALS
+            && let Some((&oldest, _)) = self.partials.first_key_value(){
+            self.abandon(oldest);
+        }
+    }
+
+    /// A sample with seq 0 after others, or one far behind what we have seen: the first of a new publisher.
+    /// Checking the distance too catches a restart whose first samples we missed, e.g. to a full buffer.
+    fn restarted(&self, sample: &Telemetry) -> bool{
+        self.next_seq.is_some_and(|expected| (sample.seq == 0 && expected > 1) || sample.seq.saturating_add(RESTART_GAP) < expected)
+            || self.last_done.is_some_and(|done| sample.msg_id.saturating_add(RESTART_GAP) < done)
+    }
+
+    fn track_seq(&mut self, seq: u64){
+        match self.next_seq{
+            Some(expected) if seq > expected => {
+                self.stats.lost += seq - expected;
+                self.out.push_back(Err(FrameError::Lost { expected, got: seq }));
+            }
+            Some(expected) if seq < expected => {
+                self.stats.out_of_order += 1;
+                self.stats.lost = self.stats.lost.saturating_sub(1); // Counted when it was skipped, it only came late
+            }
+            _ => {}
+        }
+        self.next_seq = Some(self.next_seq.map_or(seq + 1, |expected| expected.max(seq + 1)));
+    }
+
+    fn complete(&mut self, msg_id: u64){
+        let Some(partial) = self.partials.remove(&msg_id) else { return; };
+        self.last_done = Some(self.last_done.map_or(msg_id, |done| done.max(msg_id)));
+
+        // Anything older than a finished message is not going to be completed anymore.
+        let older: Vec<u64> = self.partials.range(..msg_id).map(|(id, _)| *id).collect();
+        for id in older{
+            self.abandon(id);
+        }
+
+        let mut binary = Vec::with_capacity(partial.total_len as usize);
+        for chunk in partial.chunks.into_values(){
+            binary.extend_from_slice(&chunk);
+        }
+        if binary.len() != partial.total_len as usize{
+            self.stats.corrupt += 1;
+            self.out.push_back(Err(FrameError::Corrupt { msg_id, chunk: partial.n_chunks - 1 }));
+            return;
+        }
+        match bincode::deserialize::<Data>(&binary){
+            Ok(Data::ProcessDelta(delta)) => {
+                self.stats.messages += 1;
+                match self.processes.apply(delta){
+                    Ok(Some(procs)) => self.out.push_back(Ok(Data::Process(procs))),
+                    Ok(None) => {} // Waiting for the first keyframe
+                    Err(desync) => {
+                        self.stats.desynced += 1;
+                        self.out.push_back(Err(FrameError::Desync(desync)));
+                    }
+                }
+            }
+            Ok(data) => {
+                self.stats.messages += 1;
+                self.out.push_back(Ok(data));
+            }
+            Err(err) => {
+                self.stats.undecodable += 1;
+                self.out.push_back(Err(FrameError::Decode { msg_id, kind: partial.kind, err }));
+            }
+        }
+    }
+
+    fn abandon(&mut self, msg_id: u64){
+        if let Some(partial) = self.partials.remove(&msg_id){
+            self.last_done = Some(self.last_done.map_or(msg_id, |done| done.max(msg_id)));
+            self.stats.incomplete += 1;
+            self.out.push_back(Err(FrameError::Incomplete {
+                msg_id,
+                kind: partial.kind,
+                received: partial.received,
+                n_chunks: partial.n_chunks
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::models::Component;
+
+    /// `data` as the samples the transmitter would send for it, `chunk_size` payload bytes each.
+    fn frame(data: &Data, msg_id: u64, seq: u64, chunk_size: usize) -> Vec<Telemetry>{
+        let binary = bincode::serialize(data).unwrap();
+        let n_chunks = binary.len().div_ceil(chunk_size) as u32;
+        binary.chunks(chunk_size).enumerate().map(|(idx, chunk)| {
+            let mut sample = Telemetry{
+                kind: data.kind() as u32,
+                msg_id,
+                seq: seq + idx as u64,
+                chunk: idx as u32,
+                n_chunks,
+                total_len: binary.len() as u32,
+                len: chunk.len() as u32,
+                checksum: checksum(chunk),
+                ..Default::default()
+            };
+            sample.data[..chunk.len()].copy_from_slice(chunk);
+            sample
+        }).collect()
+    }
+
+    fn components(label: &str) -> Data{
+        Data::Components(vec![Component{ label: label.to_string(), temperature: Some(40.0), max: None, critical: None }])
+    }
+
+    fn drain(reassembler: &mut Reassembler) -> Vec<Result<Data, FrameError>>{
+        std::iter::from_fn(|| reassembler.pop()).collect()
+    }
+
+    #[test]
+    fn dropped_chunk(){
+        let mut reassembler = Reassembler::new();
+        let first = frame(&components("first"), 0, 0, 8);
+        assert!(first.len() >= 3);
+        for (idx, sample) in first.iter().enumerate(){
+            if idx != 1{
+                reassembler.push(sample);
+            }
+        }
+        let out = drain(&mut reassembler);
+        assert!(matches!(out[..], [Err(FrameError::Lost { expected: 1, got: 2 })]));
+
+        // The next message to complete gives up on the broken one.
+        let next = frame(&components("next"), 1, first.len() as u64, MAX_SIZE);
+        reassembler.push(&next[0]);
+        let out = drain(&mut reassembler);
+        assert!(matches!(out[0], Err(FrameError::Incomplete { msg_id: 0, received, n_chunks, .. }) if received + 1 == n_chunks));
+        assert!(matches!(&out[1], Ok(Data::Components(c)) if c[0].label == "next"));
+
+        let stats = reassembler.stats();
+        assert_eq!((stats.lost, stats.incomplete, stats.messages), (1, 1, 1));
+    }
+
+    #[test]
+    fn swapped_chunks(){
+        let mut reassembler = Reassembler::new();
+        let mut samples = frame(&components("swapped"), 0, 0, 8);
+        samples.swap(1, 2); // Not the first, a sample with seq 0 arriving late looks like a publisher restart
+        for sample in &samples{
+            reassembler.push(sample);
+        }
+        let out = drain(&mut reassembler);
+        assert!(matches!(&out[..], [Err(FrameError::Lost { expected: 1, got: 2 }), Ok(Data::Components(c))] if c[0].label == "swapped"));
+
+        let stats = reassembler.stats();
+        assert_eq!((stats.messages, stats.lost, stats.corrupt), (1, 0, 0));
+        assert!(stats.out_of_order >= 2); // The sequence number going back and the chunk ahead of its turn
+    }
+
+    #[test]
+    fn bad_checksum(){
+        let mut reassembler = Reassembler::new();
+        let mut samples = frame(&components("corrupt"), 0, 0, MAX_SIZE);
+        samples[0].data[0] ^= 0xff;
+        reassembler.push(&samples[0]);
+        let out = drain(&mut reassembler);
+        assert!(matches!(out[..], [Err(FrameError::Corrupt { msg_id: 0, chunk: 0 })]));
+        assert_eq!((reassembler.stats().corrupt, reassembler.stats().messages), (1, 0));
+    }
+
+    #[test]
+    fn eviction_past_max_partials(){
+        let mut reassembler = Reassembler::new();
+        let mut seq = 0;
+        // Only the first chunk of each message arrives.
+        for msg_id in 0..=MAX_PARTIALS as u64{
+            let samples = frame(&components(&format!("partial {}", msg_id)), msg_id, seq, 16);
+            seq += samples.len() as u64;
+            reassembler.push(&samples[0]);
+        }
+        let incomplete: Vec<u64> = drain(&mut reassembler).into_iter()
+            .filter_map(|res| match res{
+                Err(FrameError::Incomplete { msg_id, .. }) => Some(msg_id),
+                _ => None
+            })
+            .collect();
+        assert_eq!(incomplete, vec![0]);
+        assert_eq!(reassembler.stats().incomplete, 1);
+
+        // A late chunk of the evicted message is stale.
+        let late = frame(&components("partial 0"), 0, 1, 16);
+        reassembler.push(&late[1]);
+        assert!(matches!(reassembler.pop(), Some(Err(FrameError::Stale { msg_id: 0, chunk: 1 }))));
+    }
+
+    #[test]
+    fn reset(){
+        let mut reassembler = Reassembler::new();
+        for sample in frame(&components("pending"), 0, 0, MAX_SIZE){
+            reassembler.push(&sample);
+        }
+        reassembler.reset();
+        assert!(reassembler.pop().is_none());
+
+        // A publisher that started over is not stale or lost.
+        for sample in frame(&components("old"), 0, 0, 8){
+            reassembler.push(&sample);
+        }
+        assert!(matches!(&drain(&mut reassembler)[..], [Ok(Data::Components(old))] if old[0].label == "old"));
+        for sample in frame(&components("new"), 0, 0, MAX_SIZE){
+            reassembler.push(&sample);
+        }
+        assert!(matches!(&drain(&mut reassembler)[..], [Ok(Data::Components(new))] if new[0].label == "new"));
+        assert_eq!((reassembler.stats().lost, reassembler.stats().stale), (0, 0));
+    }
+
+    #[test]
+    fn bad_header(){
+        let mut reassembler = Reassembler::new();
+        let good = frame(&components("header"), 0, 0, MAX_SIZE);
+
+        let mut unknown = frame(&components("header"), 0, 0, MAX_SIZE).remove(0);
+        unknown.kind = u32::MAX;
+        let mut too_many = frame(&components("header"), 0, 0, MAX_SIZE).remove(0);
+        too_many.n_chunks = too_many.total_len + 1;
+        let mut too_long = frame(&components("header"), 0, 0, MAX_SIZE).remove(0);
+        too_long.total_len = MAX_MESSAGE + 1;
+        too_long.n_chunks = u32::MAX;
+        for sample in [&unknown, &too_many, &too_long]{
+            reassembler.push(sample);
+        }
+        assert!(drain(&mut reassembler).iter().all(|res| matches!(res, Err(FrameError::Corrupt { msg_id: 0, chunk: 0 }))));
+        assert_eq!(reassembler.stats().corrupt, 3);
+
+        // None of them took the message's place.
+        reassembler.push(&good[0]);
+        assert!(matches!(&drain(&mut reassembler)[..], [Ok(Data::Components(c))] if c[0].label == "header"));
+    }
+
+    #[test]
+    fn restart_without_seq_zero(){
+        let mut reassembler = Reassembler::new();
+        let mut seq = 0;
+        for msg_id in 0..2 * RESTART_GAP{
+            for sample in frame(&components("old"), msg_id, seq, MAX_SIZE){
+                reassembler.push(&sample);
+                seq += 1;
+            }
+        }
+        drain(&mut reassembler);
+
+        // The new publisher's first samples were missed.
+        for sample in frame(&components("new"), 3, 3, MAX_SIZE){
+            reassembler.push(&sample);
+        }
+        assert!(matches!(&drain(&mut reassembler)[..], [Ok(Data::Components(new))] if new[0].label == "new"));
+        assert_eq!(reassembler.stats().stale, 0);
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Mutex, MutexGuard, OnceLock},
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use anyhow::{anyhow, Error};
+use iceoryx2::{node as ice_node, prelude::SignalHandlingMode, service::ipc};
+use tokio::time::Duration;
+
+use crate::{
+    config::{config, History},
+    models::{Data, HistoryMetric, HistoryPoint, HistoryRequest, HistoryResponse, HistoryStatus, HISTORY_POINTS},
+    rates::Rates, state::AppState, APPSTATE
+};
+
+// Every series of the last `history.window_s`, filled by the transmitter as data goes out.
+static STORE: OnceLock<Mutex<Store>> = OnceLock::new();
+
+const HISTORY_CYCLE: Duration = Duration::from_millis(50);
+
+pub type Key = (HistoryMetric, String);
+
+/// Turns the `Data` stream into one value per series, counters become rates.
+#[derive(Default)]
+pub struct Sampler{
+    rates: Rates<Key>
+}
+
+impl Sampler{
+    /// The values in `data`, taken at `t_ms`.
+    pub fn sample(&mut self, data: &Data, t_ms: u64) -> Vec<(Key, f64)>{
+        let mut out = Vec::new();
+        match data{
+            Data::Cpus(cpus) => {
+                for cpu in cpus{
+                    out.push(((HistoryMetric::CpuUsage, cpu.cpu_name.clone()), cpu.cpu_per as f64));
+                }
+            }
+            Data::Memory(mem) => {
+                out.push(((HistoryMetric::MemoryUsed, String::new()), mem.u_ram as f64));
+                out.push(((HistoryMetric::SwapUsed, String::new()), mem.u_swap as f64));
+            }
+            Data::Networks(nets) => {
+                for net in nets{
+                    self.rate(&mut out, (HistoryMetric::NetDown, net.name.clone()), t_ms, net.t_down);
+                    self.rate(&mut out, (HistoryMetric::NetUp, net.name.clone()), t_ms, net.t_up);
+                }
+            }
+            Data::Disk(disks) => {
+                for disk in disks{
+                    self.rate(&mut out, (HistoryMetric::DiskRead, disk.name.clone()), t_ms, disk.t_read);
+                    self.rate(&mut out, (HistoryMetric::DiskWritten, disk.name.clone()), t_ms, disk.t_written);
+                }
+            }
+            _ => {}
+        }
+        out
+    }
+
+    fn rate(&mut self, out: &mut Vec<(Key, f64)>, key: Key, t_ms: u64, total: u64){
+        if let Some(rate) = self.rates.rate(key.clone(), t_ms, total){
+            out.push((key, rate));
+        }
+    }
+
+    /// Forget counters not seen since `oldest_ms`, e.g. of interfaces that went away.
+    pub fn prune(&mut self, oldest_ms: u64){
+        self.rates.prune(oldest_ms);
+    }
+}
+
+#[derive(Default)]
+struct Store{
+    series: BTreeMap<Key, VecDeque<HistoryPoint>>,
+    sampler: Sampler
+}
+
+impl Store{
+    fn push(&mut self, key: Key, t_ms: u64, value: f64, cfg: &History){
+        let ring = self.series.entry(key).or_default();
+        while ring.len() >= cfg.max_points{
+            ring.pop_front();
+        }
+        ring.push_back(HistoryPoint { t_ms, value });
+    }
+
+    /// Drop the points that fell out of the window, and series left without any.
+    fn prune(&mut self, now_ms: u64, cfg: &History){
+        let oldest = now_ms.saturating_sub(cfg.window_s * 1000);
+        for ring in self.series.values_mut(){
+            while ring.front().is_some_and(|p| p.t_ms < oldest){
+                ring.pop_front();
+            }
+        }
+        self.series.retain(|_, ring| !ring.is_empty());
+        self.sampler.prune(oldest);
+    }
+
+    /// Answer `req` from the points kept.
+    fn query(&self, req: &HistoryRequest) -> HistoryResponse{
+        let Some(metric) = req.metric() else {
+            return HistoryResponse::new(HistoryStatus::InvalidRequest, 0);
+        };
+        let mut series = self.series.iter().filter(|((m, _), _)| *m == metric);
+        let n_series = series.clone().count() as u32;
+        let Some(((_, label), ring)) = series.nth(req.series as usize) else {
+            return HistoryResponse::new(HistoryStatus::NoSuchSeries, n_series);
+        };
+
+        let mut res = HistoryResponse::new(HistoryStatus::Ok, n_series);
+        res.set_label(label);
+        let to_ms = if req.to_ms == 0 { u64::MAX } else { req.to_ms };
+        let mut points = ring.iter().filter(|p| p.t_ms >= req.from_ms && p.t_ms <= to_ms);
+        for (slot, point) in res.points.iter_mut().zip(points.by_ref().take(HISTORY_POINTS)){
+            *slot = *point;
+            res.n_points += 1;
+        }
+        res.more = points.next().is_some() as u32;
+        res
+    }
+}
+
+fn store() -> MutexGuard<'static, Store>{
+    match STORE.get_or_init(Default::default).lock(){
+        Ok(store) => store,
+        Err(poisoned) => {
+            eprintln!("FATAL: STORE lock was poisoned!, Recovering");
+            poisoned.into_inner()
+        }
+    }
+}
+
+pub fn now_ms() -> u64{
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Add the series in `data` to the history.
+pub fn record(data: &Data){
+    let mut store = store();
+    let cfg = config().history;
+    if !cfg.enabled{
+        *store = Store::default();
+        return;
+    }
+    let t_ms = now_ms();
+    let values = store.sampler.sample(data, t_ms);
+    if values.is_empty(){
+        return;
+    }
+    for (key, value) in values{
+        store.push(key, t_ms, value, &cfg);
+    }
+    store.prune(t_ms, &cfg);
+}
+
+/// Serves the history service so clients can read what happened before they connected.
+/// The iceoryx2 ports are not Send, so this runs on its own blocking thread.
+pub fn main() -> Result<(), Error>{
+    let cfg = config();
+    let transport = &cfg.transport;
+    let node = ice_node::NodeBuilder::new()
+        .name(&format!("{}History", transport.node_name).as_str().try_into()?)
+        .signal_handling_mode(SignalHandlingMode::Disabled) // Signals are handled in main
+        .create::<ipc::Service>()?;
+
+    let service = node.service_builder(&transport.history_service.as_str().try_into()?)
+        .request_response::<HistoryRequest, HistoryResponse>()
+        .open_or_create()?;
+
+    let server = service.server_builder().create()?;
+
+    while node.wait(HISTORY_CYCLE).is_ok(){
+        while let Some(active) = server.receive()?{
+            let res = query(active.payload());
+            active.send_copy(res)?;
+        }
+        let appstate = APPSTATE.get().ok_or(anyhow!("APPSTATE is not initialized"))?;
+        if *appstate.blocking_read() == AppState::ShuttingDown{
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn query(req: &HistoryRequest) -> HistoryResponse{
+    if !config().history.enabled{
+        return HistoryResponse::new(HistoryStatus::Disabled, 0);
+    }
+    store().query(req)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const CFG: History = History { enabled: true, window_s: 60, max_points: 4 };
+
+    fn cpu(label: &str) -> Key{
+        (HistoryMetric::CpuUsage, label.to_string())
+    }
+
+    fn times(res: &HistoryResponse) -> Vec<u64>{
+        res.points().iter().map(|p| p.t_ms).collect()
+    }
+
+    #[test]
+    fn ring_keeps_the_newest(){
+        let mut store = Store::default();
+        for t_ms in 1..=6{
+            store.push(cpu("cpu0"), t_ms, t_ms as f64, &CFG);
+        }
+        let res = store.query(&HistoryRequest::new(HistoryMetric::CpuUsage, 0, 0, 0));
+        assert_eq!(times(&res), [3, 4, 5, 6]);
+        assert_eq!(res.points()[0].value, 3.0);
+    }
+
+    #[test]
+    fn window(){
+        let mut store = Store::default();
+        store.push(cpu("cpu0"), 1_000, 1.0, &CFG);
+        store.push(cpu("cpu1"), 30_000, 1.0, &CFG);
+        store.push(cpu("cpu1"), 50_000, 1.0, &CFG);
+
+        // 60s after 30s, cpu0's only point is gone and so is its series.
+        store.prune(90_000, &CFG);
+        let res = store.query(&HistoryRequest::new(HistoryMetric::CpuUsage, 0, 0, 0));
+        assert_eq!((res.n_series, res.label(), times(&res)), (1, "cpu1", vec![30_000, 50_000]));
+    }
+
+    #[test]
+    fn range(){
+        let mut store = Store::default();
+        for t_ms in [10, 20, 30, 40]{
+            store.push(cpu("cpu0"), t_ms, 0.0, &CFG);
+        }
+        let between = |from_ms, to_ms| times(&store.query(&HistoryRequest::new(HistoryMetric::CpuUsage, 0, from_ms, to_ms)));
+        assert_eq!(between(20, 30), [20, 30]);
+        assert_eq!(between(25, 0), [30, 40]); // Up to now
+        assert!(between(41, 0).is_empty());
+    }
+
+    #[test]
+    fn pages(){
+        let mut store = Store::default();
+        let cfg = History { max_points: HISTORY_POINTS + 1, ..CFG };
+        for t_ms in 0..=HISTORY_POINTS as u64{
+            store.push(cpu("cpu0"), t_ms, 0.0, &cfg);
+        }
+        let first = store.query(&HistoryRequest::new(HistoryMetric::CpuUsage, 0, 0, 0));
+        assert_eq!((first.n_points as usize, first.more()), (HISTORY_POINTS, true));
+
+        let from_ms = first.points().last().unwrap().t_ms + 1;
+        let rest = store.query(&HistoryRequest::new(HistoryMetric::CpuUsage, 0, from_ms, 0));
+        assert_eq!((times(&rest), rest.more()), (vec![HISTORY_POINTS as u64], false));
+    }
+
+    #[test]
+    fn unknown_series_or_metric(){
+        let mut store = Store::default();
+        store.push(cpu("cpu0"), 1, 0.0, &CFG);
+        store.push((HistoryMetric::NetUp, "eth0".to_string()), 1, 0.0, &CFG);
+
+        let res = store.query(&HistoryRequest::new(HistoryMetric::CpuUsage, 1, 0, 0));
+        assert_eq!((res.status(), res.n_series), (Some(HistoryStatus::NoSuchSeries), 1));
+        let res = store.query(&HistoryRequest::new(HistoryMetric::DiskRead, 0, 0, 0));
+        assert_eq!((res.status(), res.n_series), (Some(HistoryStatus::NoSuchSeries), 0));
+        let res = store.query(&HistoryRequest { metric: 99, ..HistoryRequest::new(HistoryMetric::CpuUsage, 0, 0, 0) });
+        assert_eq!(res.status(), Some(HistoryStatus::InvalidRequest));
+    }
+}
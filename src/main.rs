@@ -1,17 +1,30 @@
-use std::{sync::{Arc, OnceLock, RwLock}};
-use anyhow::Error;
+use std::{collections::HashMap, sync::{Arc, OnceLock, RwLock}};
+use anyhow::{anyhow, Error};
 use sysinfo::{
-    Disks, Networks, System
+    Components, Disks, Networks, ProcessRefreshKind, System, UpdateKind
 };
-use tokio::{sync::{mpsc}, time::{Duration}};
+use tokio::{signal::unix::{signal, SignalKind}, sync::{mpsc, watch}, task::JoinHandle, time::{Duration, Instant}};
 
-use crate::state::AppState;
+use crate::{config::{config, set_config, Config, Exporter, ProcessDetailLevel, Sources, Storage, Transport}, models::Data, state::AppState};
 
+mod alerts;
 mod collectors;
 mod cli;
 mod transmitter;
 mod state;
 mod models;
+mod framing;
+mod delta;
+mod tree;
+mod control;
+mod config;
+mod exporter;
+mod history;
+mod storage;
+mod notify;
+mod rates;
+#[cfg(test)]
+mod testutil;
 
 pub const MEM_DURATION: Duration = Duration::from_millis(2000);
 pub const LMEM_DURATION: Duration = Duration::from_millis(3000);
@@ -28,12 +41,40 @@ pub const LPROC_DURATION: Duration = Duration::from_millis(5260);
 pub const DISK_DURATION: Duration = Duration::from_millis(1512);
 pub const LDISK_DURATION: Duration = Duration::from_millis(2893);
 
+pub const PSI_DURATION: Duration = Duration::from_millis(2000);
+pub const LPSI_DURATION: Duration = Duration::from_millis(10000);
+
+pub const IFACE_DURATION: Duration = Duration::from_millis(10000);
+pub const LIFACE_DURATION: Duration = Duration::from_millis(60000);
+
+pub const BLOCK_DURATION: Duration = Duration::from_millis(1000);
+pub const LBLOCK_DURATION: Duration = Duration::from_millis(5000);
+
+pub const UNIT_DURATION: Duration = Duration::from_millis(5000);
+pub const LUNIT_DURATION: Duration = Duration::from_millis(30000);
+
+pub const CGROUP_DURATION: Duration = Duration::from_millis(3000);
+pub const LCGROUP_DURATION: Duration = Duration::from_millis(10000);
+
+pub const COMPONENT_DURATION: Duration = Duration::from_millis(2048);
+pub const LCOMPONENT_DURATION: Duration = Duration::from_millis(5120);
+
+// How long the tasks get to stop once a shutdown starts.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+// How often the config file is checked for changes.
+pub const RELOAD_POLL: Duration = Duration::from_secs(2);
+
 pub const MAX_SIZE: usize = 1024;
+pub const SUB_BUFFER_SIZE: usize = 512; // Samples a subscriber can hold, a process list alone is a few hundred
+pub const PROC_KEYFRAME_EVERY: u32 = 10; // Process tables between two sent whole
 
 pub static APPSTATE: OnceLock<Arc<tokio::sync::RwLock<AppState>>> = OnceLock::new();
 pub static SYSTEM: OnceLock<Arc<RwLock<System>>> = OnceLock::new();
 pub static NETWORKS: OnceLock<Arc<RwLock<Networks>>> = OnceLock::new();
 pub static DISKS: OnceLock<Arc<RwLock<Disks>>> = OnceLock::new();
+pub static COMPONENTS: OnceLock<Arc<RwLock<Components>>> = OnceLock::new();
+// Flipped to true once, when the agent starts shutting down.
+pub static SHUTDOWN: OnceLock<watch::Sender<bool>> = OnceLock::new();
 
 // To decide where we are sending the data to and who we are interacting with.
 // If this just a cli, we send data to the cli reciever. Else, we send to the tauri reciever.
@@ -41,7 +82,26 @@ pub static IS_CLI: OnceLock<Arc<RwLock<bool>>> = OnceLock::new();
 
 #[tokio::main]
 async fn main() -> Result<(), Error>{
-    let power_on: bool = true;
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let sources = Sources::from_args(&mut args)?;
+    set_config(startup_config(&sources, args.first().map(String::as_str))?);
+    let cfg = config();
+
+    // `agent cli [KIND..]`, `agent tui`, `agent ctl ..`, `agent proc ..`, `agent history ..` and `agent storage ..` run a terminal client instead of the agent.
+    match args.first().map(String::as_str){
+        Some("cli") => return cli::main::main(&args[1..]).await,
+        Some("tui") => return cli::tui::main(),
+        Some("ctl") => return cli::control::main(&args[1..]),
+        Some("proc") => return cli::process::main(&args[1..]),
+        Some("history") => return cli::history::main(&args[1..]),
+        Some("storage") => return cli::storage::main(&args[1..]),
+        Some("config") => {
+            print!("{}", toml::to_string(&*cfg)?); // The effective config
+            return Ok(());
+        }
+        Some(other) => return Err(anyhow!("Unknown command '{}', expected cli, tui, ctl, proc, history, storage or config", other)),
+        None => {}
+    }
 
     let is_cli = Arc::new(RwLock::new(true)); // For now this just cli
     IS_CLI.set(is_cli.clone()).unwrap();
@@ -49,6 +109,9 @@ async fn main() -> Result<(), Error>{
     let app_state: Arc<tokio::sync::RwLock<AppState>> = Arc::new(tokio::sync::RwLock::new(AppState::Meta));
     APPSTATE.set(app_state.clone()).unwrap();
 
+    let (shutdown_tx, _) = watch::channel(false);
+    SHUTDOWN.set(shutdown_tx.clone()).unwrap();
+
     // the channel to the refresher from the helpers
     let (refr_tx, refr_rx) = mpsc::channel(20);
 
@@ -56,12 +119,40 @@ async fn main() -> Result<(), Error>{
     let (tx, rx) = mpsc::channel(200);
 
     // Initialize the transmitter
-    let transmitter_handle = tokio::spawn(async move{
-        if let Err(e) = transmitter::main(rx).await{
+    let transmitter_handle = tokio::task::spawn_blocking(move ||{
+        if let Err(e) = transmitter::main(rx){
             eprint!("The transmitter panicked: {:?}", e);
         }
     });
 
+    // Initialize the control service
+    let control_handle = tokio::task::spawn_blocking(move ||{
+        if let Err(e) = control::main(){
+            eprint!("The control service panicked: {:?}", e);
+        }
+    });
+
+    // Initialize the history service
+    let history_handle = tokio::task::spawn_blocking(move ||{
+        if let Err(e) = history::main(){
+            eprint!("The history service panicked: {:?}", e);
+        }
+    });
+
+    // Initialize the on-disk store
+    let storage_handle = cfg.storage.enabled.then(|| tokio::task::spawn_blocking(move ||{
+        if let Err(e) = storage::main(){
+            eprint!("The storage writer panicked: {:?}", e);
+        }
+    }));
+
+    // Initialize the alert notifiers
+    let notify_handle = tokio::task::spawn_blocking(move ||{
+        if let Err(e) = notify::main(){
+            eprint!("The notifiers panicked: {:?}", e);
+        }
+    });
+
     // Initialize the refresher
     let refr_handle = tokio::spawn(async move{
         if let Err(e) = refresher(refr_rx).await{
@@ -70,68 +161,195 @@ async fn main() -> Result<(), Error>{
     });
     refr_tx.send(Kind::Init).await?;
 
-    // Initialize the Meta and cpu
-    let (tx_clone, rtx_clone) = (tx.clone(), refr_tx.clone());
-    let meta_handle = tokio::spawn(async move{
-        if let Err(e) = collectors::meta::main(tx_clone, rtx_clone).await{
-            eprint!("Meta data collector panicked: {:?}", e);
+    // Initialize the Prometheus exporter
+    let exporter_handle = cfg.exporter.enabled.then(|| tokio::spawn(async move{
+        if let Err(e) = exporter::main().await{
+            eprint!("The exporter panicked: {:?}", e);
         }
-    });
+    }));
 
-    // Initialize the Disk
-    let (tx_clone, rtx_clone) = (tx.clone(), refr_tx.clone());
-    let disk_handle = tokio::spawn(async move{
-        if let Err(e) = collectors::disks::main(tx_clone, rtx_clone).await{
-            eprint!("Disk data collector panicked: {:?}", e);
+    // Initialize the enabled collectors, keyed by their config name
+    let mut collectors = HashMap::new();
+    for (name, collector) in cfg.collectors.iter(){
+        if collector.enabled{
+            collectors.insert(name, collectors::spawn(name, tx.clone(), refr_tx.clone()));
         }
-    });
+    }
 
-    // Initialize Memory
-    let (tx_clone, rtx_clone) = (tx.clone(), refr_tx.clone());
-    let mem_handle = tokio::spawn(async move{
-        if let Err(e) = collectors::memory::main(tx_clone, rtx_clone).await{
-            eprint!("Memory data collector panicked: {:?}", e);
+    // Run until we get a signal or a client asks us to shut down, reloading the config on SIGHUP or when the file changes.
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+    let mut shutdown_rx = shutdown_tx.subscribe();
+    let mut poll = tokio::time::interval(RELOAD_POLL);
+    let mut modified = sources.modified();
+    loop{
+        tokio::select!{
+            _ = sigint.recv() => {
+                eprintln!("Received SIGINT, shutting down");
+                break;
+            }
+            _ = sigterm.recv() => {
+                eprintln!("Received SIGTERM, shutting down");
+                break;
+            }
+            _ = shutdown_rx.wait_for(|down| *down) => {
+                eprintln!("Shutdown requested over the control service");
+                break;
+            }
+            _ = sighup.recv() => {
+                eprintln!("Received SIGHUP, reloading the config");
+                modified = sources.modified();
+                reload(&sources, &mut collectors, &tx, &refr_tx).await;
+            }
+            _ = poll.tick() => {
+                let now = sources.modified();
+                if now != modified{
+                    eprintln!("The config file changed, reloading it");
+                    modified = now;
+                    reload(&sources, &mut collectors, &tx, &refr_tx).await;
+                }
+            }
         }
-    });
+    }
+    *app_state.write().await = AppState::ShuttingDown;
+    shutdown_tx.send_replace(true);
 
-    // Initialize the networks
-    let (tx_clone, rtx_clone) = (tx.clone(), refr_tx.clone());
-    let net_handle = tokio::spawn(async move{
-        if let Err(e) = collectors::networks::main(tx_clone, rtx_clone).await{
-            eprint!("Network data collector panicked: {:?}", e);
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    let mut stuck = Vec::new();
+
+    // The collectors notice the state change and return, everything they sent is still queued.
+    for (name, handle) in collectors{
+        if !join_until(name, handle, deadline).await{
+            stuck.push(name);
         }
-    });
+    }
 
-    // Initialize the Sockets
-    let tx_clone = tx.clone();
-    let socket_handle = tokio::spawn(async move{
-        if let Err(e) = collectors::sockets::main(tx_clone).await{
-            eprint!("Socket data collector panicked: {:?}", e);
+    // The transmitter publishes what is left in the queue, then the marker, then drops its node.
+    // A task that already stopped can't get its marker, the joins below still have to run.
+    if let Err(e) = tx.send(Data::ShuttingDown).await{
+        eprintln!("The transmitter stopped before the shutdown marker: {}", e);
+    }
+    drop(tx);
+    if let Err(e) = refr_tx.send(Kind::ShuttingDown).await{
+        eprintln!("The refresher stopped before the shutdown: {}", e);
+    }
+    drop(refr_tx);
+
+    let tasks = [
+        ("refresher", Some(refr_handle)), ("transmitter", Some(transmitter_handle)), ("control", Some(control_handle)),
+        ("history", Some(history_handle)), ("exporter", exporter_handle), ("storage", storage_handle),
+        ("notify", Some(notify_handle))
+    ];
+    for (name, handle) in tasks.into_iter().filter_map(|(name, handle)| Some((name, handle?))){
+        if !join_until(name, handle, deadline).await{
+            stuck.push(name);
         }
-    });
+    }
+
+    if !stuck.is_empty(){
+        eprintln!("Gave up on: {}", stuck.join(", "));
+        // The runtime would wait forever on blocking threads that never return.
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-    // Initialize the processes
-    let (tx_clone, rtx_clone) = (tx.clone(), refr_tx.clone());
-    let proc_handle = tokio::spawn(async move{
-        if let Err(e) = collectors::processes::main(tx_clone, rtx_clone).await{
-            eprint!("Process data collector panicked: {:?}", e);
+/// Load the config again and apply it. A config that fails to load or validate is ignored.
+/// Collectors are started or stopped to match, intervals apply from their next pause.
+/// The open services, the exporter's listener and the store's directory stay as they are,
+/// so of their settings only `transport.chunk_size`, `exporter.max_processes` and the
+/// storage limits change without a restart.
+async fn reload(sources: &Sources, collectors: &mut HashMap<&'static str, JoinHandle<()>>, tx: &mpsc::Sender<Data>, refr_tx: &mpsc::Sender<Kind>){
+    let new = match sources.load(){
+        Ok(new) => new,
+        Err(e) => {
+            eprintln!("Keeping the current config: {:#}", e);
+            return;
         }
-    });
+    };
+    let (new, ignored) = pin(&config(), new);
+    for msg in ignored{
+        eprintln!("{}", msg);
+    }
+    set_config(new);
+
+    // A collector that stopped on its own is started again below if it is still enabled.
+    collectors.retain(|_, handle| !handle.is_finished());
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    for (name, start) in respawns(&config(), |name| collectors.contains_key(name)){
+        if start{
+            collectors.insert(name, collectors::spawn(name, tx.clone(), refr_tx.clone()));
+            eprintln!("Started the {} collector", name);
+        }
+        else if let Some(handle) = collectors.remove(name){
+            join_until(name, handle, deadline).await;
+            eprintln!("Stopped the {} collector", name);
+        }
+    }
+}
 
-    while power_on{
-        continue;
+/// The collectors to start (true) or stop (false) so that those `running` match the enabled ones.
+/// The config to start `command` with. A config that can't be loaded stops the agent,
+/// but only warns the terminal clients, which go on with the defaults to reach an agent that did start.
+fn startup_config(sources: &Sources, command: Option<&str>) -> Result<Config, Error>{
+    match sources.load(){
+        Ok(config) => Ok(config),
+        Err(e) if matches!(command, Some("cli" | "tui" | "ctl" | "proc" | "history" | "storage")) => {
+            eprintln!("The config can't be loaded, going on with the defaults: {:#}", e);
+            Ok(Config::default())
+        }
+        Err(e) => Err(e)
     }
-    refr_handle.await?;
-    meta_handle.await?;
-    disk_handle.await?;
-    mem_handle.await?;
-    net_handle.await?;
-    socket_handle.await?;
-    proc_handle.await?;
-    transmitter_handle.await?;
+}
 
-    Ok(())
+fn respawns(cfg: &Config, running: impl Fn(&str) -> bool) -> Vec<(&'static str, bool)>{
+    cfg.collectors.iter().into_iter()
+        .filter(|(name, collector)| collector.enabled != running(name))
+        .map(|(name, collector)| (name, collector.enabled))
+        .collect()
+}
 
+/// `new` with the settings that only apply on a restart put back to `current`'s, and a message for each part that had changed.
+fn pin(current: &Config, mut new: Config) -> (Config, Vec<&'static str>){
+    let mut ignored = Vec::new();
+    let transport = Transport{
+        chunk_size: new.transport.chunk_size,
+        process_keyframe_every: new.transport.process_keyframe_every,
+        ..current.transport.clone()
+    };
+    if new.transport != transport{
+        ignored.push("Only transport.chunk_size and transport.process_keyframe_every can change without a restart, ignoring the other transport settings");
+    }
+    new.transport = transport;
+    let exporter = Exporter{ max_processes: new.exporter.max_processes, ..current.exporter.clone() };
+    if new.exporter != exporter{
+        ignored.push("Only exporter.max_processes can change without a restart, ignoring the other exporter settings");
+    }
+    new.exporter = exporter;
+    let storage = Storage{ enabled: current.storage.enabled, dir: current.storage.dir.clone(), ..new.storage.clone() };
+    if new.storage != storage{
+        ignored.push("storage.enabled and storage.dir only change with a restart, ignoring them");
+    }
+    new.storage = storage;
+    (new, ignored)
+}
+
+/// Wait for a task until `deadline`, aborting it if it takes longer.
+/// Returns whether it stopped cleanly, a task that panicked or was cancelled did not.
+async fn join_until(name: &str, mut handle: JoinHandle<()>, deadline: Instant) -> bool{
+    match tokio::time::timeout_at(deadline, &mut handle).await{
+        Ok(Ok(())) => true,
+        Ok(Err(e)) => {
+            eprintln!("The {} task failed: {:?}", name, e);
+            false
+        }
+        Err(_) => {
+            eprintln!("The {} task did not stop within {:?}, aborting it", name, SHUTDOWN_TIMEOUT);
+            handle.abort();
+            false
+        }
+    }
 }
 
 pub enum Kind{
@@ -142,6 +360,7 @@ pub enum Kind{
     Proc,
     Meta,
     Memory,
+    Components,
     ShuttingDown
 }
 
@@ -149,6 +368,7 @@ pub async fn refresher(mut rx: mpsc::Receiver<Kind>) -> Result<(), Error>{
     let system = Arc::new(RwLock::new(System::new_all()));
     let networks_ = Arc::new(RwLock::new(Networks::new_with_refreshed_list()));
     let disks_ = Arc::new(RwLock::new(Disks::new_with_refreshed_list()));
+    let components_ = Arc::new(RwLock::new(Components::new_with_refreshed_list()));
 
     loop {
         if let Some(kind) = rx.recv().await{
@@ -157,6 +377,7 @@ pub async fn refresher(mut rx: mpsc::Receiver<Kind>) -> Result<(), Error>{
                     let _ = SYSTEM.set(system.clone());
                     let _ = NETWORKS.set(networks_.clone());
                     let _ = DISKS.set(disks_.clone());
+                    let _ = COMPONENTS.set(components_.clone());
                 }
                 Kind::Cpu => {
                     if let Some(sys) = SYSTEM.get(){
@@ -182,6 +403,12 @@ pub async fn refresher(mut rx: mpsc::Receiver<Kind>) -> Result<(), Error>{
                         refd.refresh_memory();
                     }
                 }
+                Kind::Components => {
+                    if let Some(components_) = COMPONENTS.get(){
+                        let mut refd = components_.write().unwrap();
+                        refd.refresh(true);
+                    }
+                }
                 Kind::Net => {
                     if let Some(net) = NETWORKS.get(){
                         let mut refd = net.write().unwrap();
@@ -191,7 +418,21 @@ pub async fn refresher(mut rx: mpsc::Receiver<Kind>) -> Result<(), Error>{
                 Kind::Proc => {
                     if let Some(sys) = SYSTEM.get(){
                         let mut refd = sys.write().unwrap();
-                        refd.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
+                        // The default kind leaves the user and command of new processes unset.
+                        let kind = ProcessRefreshKind::nothing()
+                            .with_memory()
+                            .with_cpu()
+                            .with_disk_usage()
+                            .with_exe(UpdateKind::OnlyIfNotSet)
+                            .with_cmd(UpdateKind::OnlyIfNotSet)
+                            .with_user(UpdateKind::OnlyIfNotSet)
+                            .with_tasks();
+                        // A readlink per process, only when it is shown.
+                        let kind = match config().processes.detail{
+                            ProcessDetailLevel::Full => kind.with_cwd(UpdateKind::Always),
+                            ProcessDetailLevel::Basic => kind
+                        };
+                        refd.refresh_processes_specifics(sysinfo::ProcessesToUpdate::All, true, kind);
                     }
                 }
                 Kind::ShuttingDown => {
@@ -201,4 +442,68 @@ pub async fn refresher(mut rx: mpsc::Receiver<Kind>) -> Result<(), Error>{
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn clients_start_with_a_broken_config(){
+        let path = crate::testutil::temp_dir("startup").join("agent.toml");
+        std::fs::write(&path, "[collectors.meta]\nfocused_ms = 1\n").unwrap();
+        let sources = Sources::from_args(&mut vec!["--config".to_string(), path.display().to_string()]).unwrap();
+        assert_eq!(startup_config(&sources, Some("ctl")).unwrap().collectors.meta.focused_ms, Config::default().collectors.meta.focused_ms);
+        assert!(startup_config(&sources, None).is_err());
+        assert!(startup_config(&sources, Some("config")).is_err());
+    }
+
+    #[test]
+    fn pinned_on_reload(){
+        let current = Config::default();
+        let mut new = Config::default();
+        new.transport.chunk_size = 512;
+        new.transport.process_keyframe_every = 3;
+        new.exporter.max_processes = 10;
+        new.storage.max_bytes = 1 << 30;
+        new.storage.retention_1s_s = 60;
+        new.collectors.sockets.enabled = false;
+        new.collectors.memory.focused_ms = 100;
+
+        // Everything that applies live.
+        let (pinned, ignored) = pin(&current, new);
+        assert!(ignored.is_empty());
+        assert_eq!((pinned.transport.chunk_size, pinned.transport.process_keyframe_every, pinned.exporter.max_processes), (512, 3, 10));
+        assert_eq!((pinned.storage.max_bytes, pinned.storage.retention_1s_s), (1 << 30, 60));
+        assert!(!pinned.collectors.sockets.enabled);
+        assert_eq!(pinned.collectors.memory.focused_ms, 100);
+
+        // What only changes with a restart stays, next to what changed along with it.
+        let mut new = pinned.clone();
+        new.transport.service_name = "Other".to_string();
+        new.transport.subscriber_buffer = 8;
+        new.exporter.enabled = true;
+        new.exporter.listen = "0.0.0.0:1".to_string();
+        new.storage.enabled = true;
+        new.storage.dir = "elsewhere".into();
+        new.storage.segment_bytes = 1 << 10;
+        let (pinned, ignored) = pin(&current, new);
+        assert_eq!(ignored.len(), 3);
+        assert_eq!(pinned.transport, Transport { chunk_size: 512, process_keyframe_every: 3, ..current.transport.clone() });
+        assert_eq!(pinned.exporter, Exporter { max_processes: 10, ..current.exporter.clone() });
+        assert_eq!((pinned.storage.enabled, &pinned.storage.dir), (current.storage.enabled, &current.storage.dir));
+        assert_eq!(pinned.storage.segment_bytes, 1 << 10);
+    }
+
+    #[test]
+    fn collectors_follow_enabled(){
+        let mut cfg = Config::default();
+        cfg.collectors.sockets.enabled = false;
+        cfg.collectors.units.enabled = false;
+        // Units was already off, memory stopped on its own and is started again.
+        let running = |name: &str| !["units", "memory"].contains(&name);
+        assert_eq!(respawns(&cfg, running), [("memory", true), ("sockets", false)]);
+
+        assert!(respawns(&Config::default(), |_| true).is_empty());
+    }
+}
@@ -1,10 +1,11 @@
 use iceoryx2::prelude::ZeroCopySend;
 use serde::{Deserialize, Serialize};
 
-use crate::MAX_SIZE;
+use crate::{state::AppState, MAX_SIZE};
 
-#[derive(Debug, Serialize, Deserialize, ZeroCopySend, Default)]
-#[repr(C)]
+// Samples carry this as a raw u32, see `Telemetry::kind`.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
 pub enum TelemetryKind{
     Meta,
     Disk,
@@ -12,24 +13,70 @@ pub enum TelemetryKind{
     Sockets,
     Cpus,#[default]
     Process,
-    Memory
+    Memory,
+    Alert,
+    ProcessEvents,
+    Components,
+    Pressure,
+    Cgroups,
+    Units,
+    BlockDevices,
+    Interfaces,
+    ShuttingDown
 }
+
+impl TelemetryKind{
+    pub fn from_raw(raw: u32) -> Option<Self>{
+        use TelemetryKind::*;
+        [Meta, Disk, Networks, Sockets, Cpus, Process, Memory, Alert, ProcessEvents, Components, Pressure, Cgroups, Units,
+            BlockDevices, Interfaces, ShuttingDown]
+            .into_iter().find(|k| *k as u32 == raw)
+    }
+}
+
+/// One shared memory sample. A serialized `Data` message larger than `MAX_SIZE`
+/// is split over several samples that share a `msg_id`; see `framing::Reassembler`.
 #[derive(Debug, ZeroCopySend)]
 #[repr(C)]
 pub struct Telemetry{
-    pub kind: TelemetryKind,
+    pub kind: u32,      // A `TelemetryKind`
+    pub msg_id: u64,    // Id of the message this chunk belongs to, increases by one per message
+    pub seq: u64,       // Id of the sample itself, increases by one per chunk sent
+    pub chunk: u32,     // Index of this chunk in the message
+    pub n_chunks: u32,  // Number of chunks in the message
+    pub total_len: u32, // Length of the whole serialized message
+    pub len: u32,       // Number of bytes of `data` in use
+    pub checksum: u32,  // crc32 of `data[..len]`
     pub data: [u8; MAX_SIZE]
 }
 
 impl Default for Telemetry {
     fn default() -> Self {
         Self {
-            kind: TelemetryKind::default(),
+            kind: TelemetryKind::default() as u32,
+            msg_id: 0,
+            seq: 0,
+            chunk: 0,
+            n_chunks: 0,
+            total_len: 0,
+            len: 0,
+            checksum: 0,
             data: [0u8; MAX_SIZE] 
         }
     }
 }
 
+impl Telemetry{
+    /// `None` if the sample holds something that is not a `TelemetryKind`.
+    pub fn kind(&self) -> Option<TelemetryKind>{
+        TelemetryKind::from_raw(self.kind)
+    }
+
+    pub fn payload(&self) -> &[u8]{
+        &self.data[..(self.len as usize).min(MAX_SIZE)]
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Data{
     Meta(Meta),
@@ -39,9 +86,126 @@ pub enum Data{
     Cpus(Vec<Cpus>),
     Process(Vec<Process>),
     Memory(Memory),
+    Alert(AlertEvent),
+    ProcessEvents(Vec<ProcessEvent>),
+    ProcessDelta(ProcessDelta), // How `Process` goes over the wire, see `delta`
+    Components(Vec<Component>), // Empty on machines without sensors
+    Pressure(Vec<Pressure>),
+    Cgroups(Vec<Cgroup>),
+    Units(Vec<Unit>),
+    BlockDevices(Vec<BlockDevice>),
+    Interfaces(Vec<Interface>),
     ShuttingDown
 }
 
+impl Data{
+    pub fn kind(&self) -> TelemetryKind{
+        match self{
+            Data::Meta(_) => TelemetryKind::Meta,
+            Data::Disk(_) => TelemetryKind::Disk,
+            Data::Networks(_) => TelemetryKind::Networks,
+            Data::Sockets(_) => TelemetryKind::Sockets,
+            Data::Cpus(_) => TelemetryKind::Cpus,
+            Data::Process(_) | Data::ProcessDelta(_) => TelemetryKind::Process,
+            Data::Memory(_) => TelemetryKind::Memory,
+            Data::Alert(_) => TelemetryKind::Alert,
+            Data::ProcessEvents(_) => TelemetryKind::ProcessEvents,
+            Data::Components(_) => TelemetryKind::Components,
+            Data::Pressure(_) => TelemetryKind::Pressure,
+            Data::Cgroups(_) => TelemetryKind::Cgroups,
+            Data::Units(_) => TelemetryKind::Units,
+            Data::BlockDevices(_) => TelemetryKind::BlockDevices,
+            Data::Interfaces(_) => TelemetryKind::Interfaces,
+            Data::ShuttingDown => TelemetryKind::ShuttingDown
+        }
+    }
+}
+
+/// A temperature sensor, in degrees Celsius. A value the sensor does not report is `None`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Component{
+    pub label: String,
+    pub temperature: Option<f32>,
+    pub max: Option<f32>,     // Highest seen since the agent started
+    pub critical: Option<f32> // Where the hardware considers it too hot
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum PressureResource{
+    Cpu,
+    Memory,
+    Io
+}
+
+impl PressureResource{
+    /// As in /proc/pressure.
+    pub fn name(&self) -> &'static str{
+        match self{
+            PressureResource::Cpu => "cpu",
+            PressureResource::Memory => "memory",
+            PressureResource::Io => "io"
+        }
+    }
+}
+
+/// One line of a /proc/pressure file. Averages are the percent of time stalled.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct Stalls{
+    pub avg10: f32,
+    pub avg60: f32,
+    pub avg300: f32,
+    pub total: u64 // Microseconds stalled since boot
+}
+
+/// Pressure Stall Information of a resource, see `collectors::pressure`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Pressure{
+    pub resource: PressureResource,
+    pub some: Stalls,         // At least one task stalled
+    pub full: Option<Stalls>, // All non-idle tasks stalled at once, None where the kernel has no such line
+    pub triggered: bool       // Sent early because a `[psi]` trigger of the resource fired
+}
+
+/// Usage and limits of a cgroup v2, see `collectors::cgroups`. A file the cgroup lacks is `None`, as for the root.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Cgroup{
+    pub path: String,            // Below the cgroup root, "/" for the root itself
+    pub cpu_usage: u64,          // Microseconds of cpu used, all its tasks and cgroups below together
+    pub cpu_throttled: u64,      // Microseconds its tasks were held back by cpu.max
+    pub cpu_max: Option<f32>,    // Cpus it may use at most, None if unlimited
+    pub mem: Option<u64>,
+    pub mem_max: Option<u64>,    // None if unlimited
+    pub t_read: u64,             // Bytes over all devices
+    pub t_written: u64,
+    pub pids: Option<u64>,       // Tasks in it and below
+    pub pids_max: Option<u64>,
+    pub procs: Vec<u32>          // Processes in it, or in a cgroup below `cgroups.max_depth` under it
+}
+
+/// A systemd unit with the states `systemctl` shows, see `collectors::units`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Unit{
+    pub name: String,
+    pub load: String,           // loaded, not-found, masked, ...
+    pub active: String,         // active, failed, activating, ...
+    pub sub: String,            // running, exited, auto-restart, ...
+    pub main_pid: Option<u32>,  // None while nothing runs
+    pub restarts: Option<u32>   // Automatic restarts, services only
+}
+
+impl Unit{
+    /// The process entry of its main pid, if `procs` has one.
+    pub fn process<'a>(&self, procs: &'a [Process]) -> Option<&'a Process>{
+        let pid = self.main_pid?;
+        procs.iter().find(|p| p.pid == pid && !p.thread)
+    }
+
+    pub fn failed(&self) -> bool{
+        self.active == "failed"
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Memory{
     pub t_ram: u64,
@@ -61,8 +225,14 @@ pub struct Meta{
     pub os: String,
     pub host_name: String,
     pub n_cpu: usize,
+    pub n_core: Option<usize>, // Physical cores, None if unknown
     pub n_proc: usize,
-    pub glob_cpu: f32
+    pub glob_cpu: f32,
+    pub load: [f64; 3],        // 1, 5 and 15 minute load averages
+    pub uptime: u64,           // Seconds
+    pub boot_time: u64,        // Seconds since the epoch
+    pub arch: String,          // e.g. x86_64
+    pub distro: String         // The ID of os-release, e.g. debian
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -79,6 +249,53 @@ pub struct DiskData{
     pub written: u64,
     pub t_read: u64,
     pub read: u64,
+    pub device: Option<String>,  // Kernel name of the block device behind it, like sda1 or dm-0
+    pub options: Vec<String>,    // Mount options, like ro, noexec or relatime
+    pub t_inodes: Option<u64>,   // None where the filesystem has no fixed number of them, like btrfs
+    pub a_inodes: Option<u64>
+}
+
+/// IO of a block device from /proc/diskstats, rates are over the collector's last interval.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockDevice{
+    pub name: String,              // As in /proc/diskstats, like sda or nvme0n1p2
+    pub partition: bool,
+    pub mounts: Vec<String>,       // `DiskData::loc` of the disks on it
+    pub rotational: Option<bool>,  // From its queue in /sys/block, a partition's is its disk's
+    pub queue_depth: Option<u32>,  // Requests the queue takes at most
+    pub reads: f64,                // Completed per second
+    pub writes: f64,
+    pub read: u64,                 // Bytes per second
+    pub written: u64,
+    pub r_await: f64,              // Average milliseconds per read, queueing included
+    pub w_await: f64,
+    pub busy: f32,                 // Percent of the interval with requests in flight
+    pub queue: f64,                // Average requests queued or in flight
+    pub in_flight: u64,            // Requests in flight when sampled
+    pub t_reads: u64,              // Since boot
+    pub t_writes: u64,
+    pub t_read: u64,               // Bytes
+    pub t_written: u64,
+    pub t_busy_ms: u64
+}
+
+/// Addresses and link of a network interface, sent far less often than its `Networks` counters.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Interface{
+    pub name: String,
+    pub mac: Option<String>,     // None where it has none, like lo
+    pub addresses: Vec<String>,  // With their prefix, like 10.0.0.2/24 or fe80::1/64
+    pub mtu: u64,
+    pub state: String,           // Operational state: up, down, dormant, unknown, ...
+    pub carrier: Option<bool>,   // Whether a link is detected, None while the interface is down
+    pub speed: Option<u64>       // Mbit/s, None where the driver does not tell, as for most virtual ones
+}
+
+impl Interface{
+    /// Up, or in the unknown state virtual drivers and lo report while they work.
+    pub fn up(&self) -> bool{
+        self.state == "up" || (self.state == "unknown" && self.carrier == Some(true))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -124,7 +341,28 @@ pub struct Cpus{
     pub freq: u64
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertState{
+    Pending,  // Over the threshold, waiting out `for_s`
+    Firing,
+    Resolved, // Back past the clear level, or the series went away
+    Cancelled // Like resolved, for a pending alert that never fired
+}
+
+/// A change of state of an alert rule for one series, see `alerts`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AlertEvent{
+    pub rule: String,
+    pub metric: String,
+    pub label: String, // Mount, interface or cpu the value is for, empty if there is only one
+    pub state: AlertState,
+    pub value: f64,     // NaN when resolved because the series or the rule went away
+    pub threshold: f64,
+    pub since_ms: u64, // When the condition first held
+    pub t_ms: u64
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Process{
     pub pid: u32,
     pub name: String,
@@ -134,5 +372,449 @@ pub struct Process{
     pub status: String,
     pub cmd: String,
     pub parent: Option<u32>,
-    pub user_id: Option<String>
-}
\ No newline at end of file
+    pub user_id: Option<String>,
+    pub thread: bool, // A thread of `parent`, listed like a process
+    pub detail: Option<ProcessDetail> // Only with `processes.detail = "full"`
+}
+
+/// What the full detail level adds to a process.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProcessDetail{
+    pub read: u64,            // Bytes since the last refresh
+    pub written: u64,
+    pub t_read: u64,          // Bytes since the process started
+    pub t_written: u64,
+    pub virtual_mem: u64,
+    pub threads: Option<u32>, // None for a thread itself
+    pub start_time: u64,      // Seconds since the epoch
+    pub cwd: String,          // Empty when it can't be read
+    pub fds: Option<u32>      // Open file descriptors, None when /proc/<pid>/fd can't be read
+}
+
+impl ProcessDetail{
+    /// Seconds the process has been running at `now_ms`, worked out from `start_time` so it is not sent in every delta.
+    pub fn run_time(&self, now_ms: u64) -> u64{
+        (now_ms / 1000).saturating_sub(self.start_time)
+    }
+}
+
+/// The process table as the changes to the one sent before it.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ProcessDelta{
+    pub seq: u64,       // Increases by one per table sent
+    pub keyframe: bool, // `added` is the whole table, nothing sent before is needed
+    pub added: Vec<Process>,
+    pub removed: Vec<u32>,
+    pub changed: Vec<ProcessChange>
+}
+
+/// The fields of a process that changed, `None` for those that did not.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ProcessChange{
+    pub pid: u32,
+    pub name: Option<String>,
+    pub exe: Option<String>,
+    pub cpu: Option<f32>,
+    pub mem: Option<u64>,
+    pub status: Option<String>,
+    pub cmd: Option<String>,
+    pub parent: Option<Option<u32>>,
+    pub user_id: Option<Option<String>>,
+    pub thread: Option<bool>,
+    pub detail: Option<DetailChange>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum DetailChange{
+    Whole(Option<ProcessDetail>), // It appeared or went away, e.g. after `processes.detail` was reloaded
+    Fields(ProcessDetailChange)
+}
+
+/// The fields of a process's detail that changed, `None` for those that did not.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ProcessDetailChange{
+    pub read: Option<u64>,
+    pub written: Option<u64>,
+    pub t_read: Option<u64>,
+    pub t_written: Option<u64>,
+    pub virtual_mem: Option<u64>,
+    pub threads: Option<Option<u32>>,
+    pub start_time: Option<u64>,
+    pub cwd: Option<String>,
+    pub fds: Option<Option<u32>>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lifecycle{
+    Started,
+    Exited
+}
+
+/// A process that appeared or went away between two process snapshots.
+/// Processes that start and exit between two snapshots are never seen.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProcessEvent{
+    pub event: Lifecycle,
+    pub pid: u32,
+    pub name: String,
+    pub exe: String,
+    pub parent: Option<u32>,
+    pub user_id: Option<String>,
+    pub start_time: u64, // Seconds since the epoch
+    pub run_time: u64,   // Seconds, until the exit was noticed for an exit
+    pub cpu: f32,        // When last seen, the final values for an exit
+    pub mem: u64,
+    pub t_ms: u64        // When the start or exit was noticed
+}
+
+// Requests and responses carry enums as raw u32s, as shared memory may hold any value: here these
+// and `AppState`, and likewise those of the process and history services below.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ControlAction{
+    Status,   // Only report the current state
+    Focus,    // Make `ControlRequest::state` the focused state
+    Shutdown
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ControlStatus{
+    Ok,
+    Rejected // e.g. focusing `ShuttingDown` or an unknown state, anything once the agent is shutting down or an unknown action
+}
+
+impl ControlAction{
+    pub fn from_raw(raw: u32) -> Option<Self>{
+        [Self::Status, Self::Focus, Self::Shutdown].into_iter().find(|a| *a as u32 == raw)
+    }
+}
+
+impl ControlStatus{
+    pub fn from_raw(raw: u32) -> Option<Self>{
+        [Self::Ok, Self::Rejected].into_iter().find(|s| *s as u32 == raw)
+    }
+}
+
+/// A request on the agent's "Control" service.
+#[derive(Debug, Clone, Copy, ZeroCopySend)]
+#[repr(C)]
+pub struct ControlRequest{
+    pub action: u32, // A `ControlAction`
+    pub state: u32   // An `AppState`, for `Focus`, ignored otherwise
+}
+
+impl ControlRequest{
+    /// `state` is only looked at for `Focus`, without one `u32::MAX` is sent.
+    pub fn new(action: ControlAction, state: Option<AppState>) -> Self{
+        Self { action: action as u32, state: state.map_or(u32::MAX, |s| s as u32) }
+    }
+
+    /// `None` if the client sent something that is not a `ControlAction`.
+    pub fn action(&self) -> Option<ControlAction>{
+        ControlAction::from_raw(self.action)
+    }
+
+    pub fn state(&self) -> Option<AppState>{
+        AppState::from_raw(self.state)
+    }
+}
+
+/// The agent's answer to a `ControlRequest`, `state` is the state after handling it.
+#[derive(Debug, Clone, Copy, ZeroCopySend)]
+#[repr(C)]
+pub struct ControlResponse{
+    pub status: u32, // A `ControlStatus`
+    pub state: u32   // An `AppState`
+}
+
+impl ControlResponse{
+    pub fn new(status: ControlStatus, state: AppState) -> Self{
+        Self { status: status as u32, state: state as u32 }
+    }
+
+    /// `None` if the agent answered with something that is not a `ControlStatus`.
+    pub fn status(&self) -> Option<ControlStatus>{
+        ControlStatus::from_raw(self.status)
+    }
+
+    pub fn state(&self) -> Option<AppState>{
+        AppState::from_raw(self.state)
+    }
+}
+
+// Raw u32s on the wire, see `ControlAction`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ProcessAction{
+    Signal, // Send `ProcessRequest::signal`
+    Renice, // Set the nice value to `ProcessRequest::nice`
+    Lookup  // Only describe the process
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ProcessSignal{
+    Term,
+    Kill,
+    Stop,
+    Cont
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ProcessStatus{
+    Ok,
+    NoSuchProcess,    // Also when `ProcessRequest::start_time` is not the one of the process with the pid now
+    NotAllowed,       // Refused by the agent's config, control.process_actions is off or the process is another user's, or the agent runs as root, without control.any_user
+    PermissionDenied, // Refused by the system, the agent lacks the privileges
+    InvalidArgument,  // e.g. pid 0, init, the agent itself, an unknown action or signal or a nice value out of range
+    Failed            // Anything else, see `ProcessResponse::errno`
+}
+
+impl ProcessAction{
+    pub fn from_raw(raw: u32) -> Option<Self>{
+        [Self::Signal, Self::Renice, Self::Lookup].into_iter().find(|a| *a as u32 == raw)
+    }
+}
+
+impl ProcessSignal{
+    pub fn from_raw(raw: u32) -> Option<Self>{
+        [Self::Term, Self::Kill, Self::Stop, Self::Cont].into_iter().find(|s| *s as u32 == raw)
+    }
+}
+
+impl ProcessStatus{
+    pub fn from_raw(raw: u32) -> Option<Self>{
+        [Self::Ok, Self::NoSuchProcess, Self::NotAllowed, Self::PermissionDenied, Self::InvalidArgument, Self::Failed]
+            .into_iter().find(|s| *s as u32 == raw)
+    }
+}
+
+/// A request on the agent's "Process" service, about the process `pid` of `models::Process`.
+#[derive(Debug, Clone, Copy, ZeroCopySend)]
+#[repr(C)]
+pub struct ProcessRequest{
+    pub action: u32,     // A `ProcessAction`
+    pub pid: u32,
+    pub signal: u32,     // A `ProcessSignal`, for `Signal`
+    pub nice: i32,       // For `Renice`, -20 to 19
+    pub start_time: u64  // Of the process meant, in seconds since the epoch as a lookup tells it, 0 for whichever has the pid
+}
+
+impl ProcessRequest{
+    pub fn new(action: ProcessAction, pid: u32) -> Self{
+        Self { action: action as u32, pid, signal: ProcessSignal::Term as u32, nice: 0, start_time: 0 }
+    }
+
+    /// `None` if the client sent something that is not a `ProcessAction`.
+    pub fn action(&self) -> Option<ProcessAction>{
+        ProcessAction::from_raw(self.action)
+    }
+
+    pub fn signal(&self) -> Option<ProcessSignal>{
+        ProcessSignal::from_raw(self.signal)
+    }
+}
+
+pub const NAME_LEN: usize = 64;
+pub const PATH_LEN: usize = 256;
+
+/// What `Lookup` tells about a process, as the agent sees it now.
+#[derive(Debug, Clone, Copy, ZeroCopySend)]
+#[repr(C)]
+pub struct ProcessInfo{
+    pub pid: u32,
+    pub parent: u32,      // 0 if none
+    pub uid: u32,         // u32::MAX if unknown
+    pub nice: i32,
+    pub cpu: f32,
+    pub mem: u64,
+    pub virtual_mem: u64,
+    pub start_time: u64,  // Seconds since the epoch
+    pub threads: u32,
+    pub status: [u8; NAME_LEN],
+    pub status_len: u32,
+    pub name: [u8; NAME_LEN],
+    pub name_len: u32,
+    pub exe: [u8; PATH_LEN],
+    pub exe_len: u32,
+    pub cmd: [u8; PATH_LEN],
+    pub cmd_len: u32
+}
+
+/// The agent's answer to a `ProcessRequest`, `info` is only filled in for a successful `Lookup`.
+#[derive(Debug, Clone, Copy, ZeroCopySend)]
+#[repr(C)]
+pub struct ProcessResponse{
+    pub status: u32, // A `ProcessStatus`
+    pub errno: i32, // From the system call that failed, 0 otherwise
+    pub info: ProcessInfo
+}
+
+impl ProcessResponse{
+    pub fn new(status: ProcessStatus, errno: i32) -> Self{
+        Self{
+            status: status as u32,
+            errno,
+            info: ProcessInfo{
+                pid: 0, parent: 0, uid: u32::MAX, nice: 0, cpu: 0.0, mem: 0, virtual_mem: 0, start_time: 0, threads: 0,
+                status: [0u8; NAME_LEN], status_len: 0,
+                name: [0u8; NAME_LEN], name_len: 0,
+                exe: [0u8; PATH_LEN], exe_len: 0,
+                cmd: [0u8; PATH_LEN], cmd_len: 0
+            }
+        }
+    }
+
+    /// `None` if the agent answered with something that is not a `ProcessStatus`.
+    pub fn status(&self) -> Option<ProcessStatus>{
+        ProcessStatus::from_raw(self.status)
+    }
+}
+
+impl ProcessInfo{
+    pub fn set_strs(&mut self, status: &str, name: &str, exe: &str, cmd: &str){
+        self.status_len = put_str(&mut self.status, status);
+        self.name_len = put_str(&mut self.name, name);
+        self.exe_len = put_str(&mut self.exe, exe);
+        self.cmd_len = put_str(&mut self.cmd, cmd);
+    }
+
+    pub fn status(&self) -> &str { get_str(&self.status, self.status_len) }
+    pub fn name(&self) -> &str { get_str(&self.name, self.name_len) }
+    pub fn exe(&self) -> &str { get_str(&self.exe, self.exe_len) }
+    pub fn cmd(&self) -> &str { get_str(&self.cmd, self.cmd_len) }
+}
+
+/// Copy `s` into `buf`, cut at its length on a char boundary, and return the bytes copied.
+fn put_str(buf: &mut [u8], s: &str) -> u32{
+    let mut len = s.len().min(buf.len());
+    while !s.is_char_boundary(len){
+        len -= 1;
+    }
+    buf[..len].copy_from_slice(&s.as_bytes()[..len]);
+    len as u32
+}
+
+fn get_str(buf: &[u8], len: u32) -> &str{
+    std::str::from_utf8(&buf[..(len as usize).min(buf.len())]).unwrap_or("")
+}
+
+// Points per `HistoryResponse`, longer ranges are fetched page by page.
+pub const HISTORY_POINTS: usize = 256;
+pub const LABEL_LEN: usize = 64;
+
+/// A series kept by the agent's history, see `history` and `storage`.
+/// Raw u32s on the wire like `HistoryStatus` and `more`, see `ControlAction`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[repr(u32)]
+pub enum HistoryMetric{
+    CpuUsage,    // Percent per cpu
+    MemoryUsed,  // Bytes
+    SwapUsed,    // Bytes
+    NetDown,     // Bytes per second per interface
+    NetUp,       // Bytes per second per interface
+    DiskRead,    // Bytes per second per disk
+    DiskWritten  // Bytes per second per disk
+}
+
+impl HistoryMetric{
+    pub fn from_raw(raw: u32) -> Option<Self>{
+        use HistoryMetric::*;
+        [CpuUsage, MemoryUsed, SwapUsed, NetDown, NetUp, DiskRead, DiskWritten].into_iter().find(|m| *m as u32 == raw)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, ZeroCopySend)]
+#[repr(C)]
+pub struct HistoryPoint{
+    pub t_ms: u64, // Unix time in milliseconds
+    pub value: f64
+}
+
+/// A request on the agent's "History" service: points of the `series`-th series of `metric`,
+/// ordered by label, taken between `from_ms` and `to_ms` (0 for now).
+#[derive(Debug, Clone, Copy, ZeroCopySend)]
+#[repr(C)]
+pub struct HistoryRequest{
+    pub metric: u32, // A `HistoryMetric`
+    pub series: u32,
+    pub from_ms: u64,
+    pub to_ms: u64
+}
+
+impl HistoryRequest{
+    pub fn new(metric: HistoryMetric, series: u32, from_ms: u64, to_ms: u64) -> Self{
+        Self { metric: metric as u32, series, from_ms, to_ms }
+    }
+
+    /// `None` if the client sent something that is not a `HistoryMetric`.
+    pub fn metric(&self) -> Option<HistoryMetric>{
+        HistoryMetric::from_raw(self.metric)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum HistoryStatus{
+    Ok,
+    NoSuchSeries,  // `series` is not below `n_series`
+    Disabled,      // history.enabled is false
+    InvalidRequest // An unknown metric
+}
+
+impl HistoryStatus{
+    pub fn from_raw(raw: u32) -> Option<Self>{
+        [Self::Ok, Self::NoSuchSeries, Self::Disabled, Self::InvalidRequest].into_iter().find(|s| *s as u32 == raw)
+    }
+}
+
+/// The agent's answer to a `HistoryRequest`. When `more` is set, ask again from the last point on.
+#[derive(Debug, Clone, Copy, ZeroCopySend)]
+#[repr(C)]
+pub struct HistoryResponse{
+    pub status: u32,   // A `HistoryStatus`
+    pub n_series: u32, // Series `metric` has right now
+    pub label: [u8; LABEL_LEN],
+    pub label_len: u32,
+    pub n_points: u32,
+    pub more: u32,     // 1 if points were left out, 0 otherwise
+    pub points: [HistoryPoint; HISTORY_POINTS]
+}
+
+impl HistoryResponse{
+    pub fn new(status: HistoryStatus, n_series: u32) -> Self{
+        Self{
+            status: status as u32,
+            n_series,
+            label: [0u8; LABEL_LEN],
+            label_len: 0,
+            n_points: 0,
+            more: 0,
+            points: [HistoryPoint::default(); HISTORY_POINTS]
+        }
+    }
+
+    /// `None` if the agent answered with something that is not a `HistoryStatus`.
+    pub fn status(&self) -> Option<HistoryStatus>{
+        HistoryStatus::from_raw(self.status)
+    }
+
+    pub fn more(&self) -> bool{
+        self.more != 0
+    }
+
+    /// Cut at `LABEL_LEN` bytes, on a char boundary.
+    pub fn set_label(&mut self, label: &str){
+        self.label_len = put_str(&mut self.label, label);
+    }
+
+    pub fn label(&self) -> &str{
+        get_str(&self.label, self.label_len)
+    }
+
+    pub fn points(&self) -> &[HistoryPoint]{
+        &self.points[..(self.n_points as usize).min(HISTORY_POINTS)]
+    }
+}
@@ -0,0 +1,473 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::OpenOptions,
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    process::{Command, Stdio},
+    sync::{mpsc::{self, RecvTimeoutError, SyncSender, TrySendError}, OnceLock},
+    thread,
+    time::{Duration, Instant}
+};
+
+use anyhow::{anyhow, bail, Context, Error};
+use serde::Serialize;
+use sysinfo::System;
+
+use crate::{
+    config::{config, Notifier, Sink},
+    models::{AlertEvent, AlertState, Data}
+};
+
+// Where `record` hands alert events to the delivery thread, set once it runs.
+static SINK: OnceLock<SyncSender<Entry>> = OnceLock::new();
+
+// Events waiting for the delivery thread before new ones are dropped.
+const QUEUE_LEN: usize = 256;
+// How long the thread sleeps at most when no retry is due.
+const NOTIFY_CYCLE: Duration = Duration::from_millis(500);
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+// Bytes of a webhook response read to find its status.
+const MAX_RESPONSE: u64 = 4096;
+
+enum Entry{
+    Event(AlertEvent),
+    Close // Stop, the agent is shutting down
+}
+
+/// What sinks get, as JSON or as AWARE_ALERT_* variables.
+#[derive(Serialize)]
+struct Payload<'a>{
+    host: &'a str,
+    #[serde(flatten)]
+    event: &'a AlertEvent,
+    suppressed: u32 // Events of this notifier dropped by deduplication or the rate limit since the last one sent
+}
+
+struct Delivery{
+    notifier: String,
+    event: AlertEvent,
+    suppressed: u32,
+    attempt: u32,
+    due: Instant
+}
+
+/// What a notifier sent lately, to deduplicate and rate limit.
+#[derive(Default)]
+struct Limits{
+    sent: VecDeque<Instant>,               // Within the last `RATE_WINDOW`
+    last: HashMap<(String, String), Last>, // By rule and label
+    suppressed: u32
+}
+
+/// The state of an alert a notifier was last sent, and the newer one the rate limit holds back.
+struct Last{
+    state: Option<AlertState>, // None until one was sent
+    at: Instant,
+    held: Option<AlertEvent>   // Sent once the rate limit allows, unless the alert gets back to `state` first
+}
+
+/// Queue `data` for the notifiers if it is an alert event. Never blocks, a full queue drops it.
+pub fn record(data: &Data){
+    let Some(sink) = SINK.get() else { return; };
+    match data{
+        Data::Alert(event) => {
+            if let Err(TrySendError::Full(_)) = sink.try_send(Entry::Event(event.clone())){
+                eprintln!("The notifiers are behind, dropping an alert event for {}", event.rule);
+            }
+        }
+        Data::ShuttingDown => {
+            let _ = sink.send(Entry::Close);
+        }
+        _ => {}
+    }
+}
+
+/// Sends what `record` queues to the `[[notify]]` sinks, retrying with backoff, until the agent shuts down.
+/// Sinks block on the network, processes and files, so this runs on its own blocking thread.
+pub fn main() -> Result<(), Error>{
+    let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+    SINK.set(tx).map_err(|_| anyhow!("The notifiers are already running"))?;
+
+    let host = System::host_name().unwrap_or_default();
+    let mut outbox = Outbox::default();
+
+    loop{
+        let wait = outbox.next_due().map_or(NOTIFY_CYCLE, |due| due.saturating_duration_since(Instant::now()));
+        match rx.recv_timeout(wait.min(NOTIFY_CYCLE)){
+            Ok(Entry::Event(event)) => outbox.push(&config().notify, &event),
+            Ok(Entry::Close) | Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        outbox.deliver(&config().notify, &host);
+    }
+    if !outbox.queue.is_empty(){
+        eprintln!("Dropping {} undelivered alert notifications", outbox.queue.len());
+    }
+    Ok(())
+}
+
+/// Whether `notifier` is meant to get `event` at all. Pending alerts, and those cancelled
+/// before they fired, only go to notifiers that asked for pending ones.
+fn wants(notifier: &Notifier, event: &AlertEvent) -> bool{
+    (notifier.pending || !matches!(event.state, AlertState::Pending | AlertState::Cancelled))
+        && (notifier.rules.is_empty() || notifier.rules.contains(&event.rule))
+}
+
+/// The deliveries still to make, and what each notifier sent lately.
+#[derive(Default)]
+struct Outbox{
+    limits: HashMap<String, Limits>, // By notifier name
+    queue: Vec<Delivery>
+}
+
+impl Outbox{
+    /// Queue `event` for every notifier that wants it and doesn't suppress it.
+    fn push(&mut self, notifiers: &[Notifier], event: &AlertEvent){
+        for notifier in notifiers.iter().filter(|n| wants(n, event)){
+            let limits = self.limits.entry(notifier.name.clone()).or_default();
+            if let Some(suppressed) = limits.admit(notifier, event, Instant::now()){
+                self.queue.push(Delivery { notifier: notifier.name.clone(), event: event.clone(), suppressed, attempt: 0, due: Instant::now() });
+            }
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant>{
+        self.queue.iter().map(|d| d.due).min()
+    }
+
+    /// Make the deliveries that are due, with backoff for those that fail.
+    fn deliver(&mut self, notifiers: &[Notifier], host: &str){
+        self.limits.retain(|name, _| notifiers.iter().any(|n| &n.name == name));
+        let now = Instant::now();
+        for notifier in notifiers{
+            let Some(limits) = self.limits.get_mut(&notifier.name) else { continue; };
+            for (event, suppressed) in limits.release(notifier, now){
+                self.queue.push(Delivery { notifier: notifier.name.clone(), event, suppressed, attempt: 0, due: now });
+            }
+        }
+        // A notifier's events go out in order, none passes one that waits for a retry.
+        let mut waiting: HashMap<String, Instant> = HashMap::new();
+        self.queue.retain_mut(|delivery|{
+            // Gone with a reload
+            let Some(notifier) = notifiers.iter().find(|n| n.name == delivery.notifier) else { return false; };
+            if let Some(due) = waiting.get(&notifier.name){
+                delivery.due = delivery.due.max(*due);
+                return true;
+            }
+            if delivery.due > now{
+                waiting.insert(notifier.name.clone(), delivery.due);
+                return true;
+            }
+            let payload = Payload { host, event: &delivery.event, suppressed: delivery.suppressed };
+            match send(notifier, &payload){
+                Ok(()) => false,
+                Err(e) if delivery.attempt < notifier.retries => {
+                    let backoff = Duration::from_millis(notifier.backoff_ms.saturating_mul(1 << delivery.attempt.min(16)));
+                    eprintln!("Notifier {} failed, retrying in {:?}: {:#}", notifier.name, backoff, e);
+                    delivery.attempt += 1;
+                    delivery.due = Instant::now() + backoff;
+                    waiting.insert(notifier.name.clone(), delivery.due);
+                    true
+                }
+                Err(e) => {
+                    eprintln!("Notifier {} gave up on alert {} after {} attempts: {:#}", notifier.name, delivery.event.rule, delivery.attempt + 1, e);
+                    false
+                }
+            }
+        });
+    }
+}
+
+impl Limits{
+    /// Whether `event` goes out, with how many were suppressed before it, or `None` if it is suppressed or held back.
+    /// Only a repeat of the state last sent is a duplicate, so a sink never misses that an alert flapped back.
+    fn admit(&mut self, notifier: &Notifier, event: &AlertEvent, now: Instant) -> Option<u32>{
+        self.expire(notifier, now);
+        let last = self.last.entry((event.rule.clone(), event.label.clone()))
+            .or_insert(Last { state: None, at: now, held: None });
+
+        if last.state == Some(event.state) && now.duration_since(last.at) < Duration::from_secs(notifier.dedup_s){
+            // What the sink was told holds again, a transition held back in between is moot.
+            self.suppressed += 1 + last.held.take().is_some() as u32;
+            return None;
+        }
+        if self.sent.len() >= notifier.max_per_min as usize{
+            // Only the latest state matters once the limit allows again.
+            self.suppressed += last.held.replace(event.clone()).is_some() as u32;
+            return None;
+        }
+        last.state = Some(event.state);
+        last.at = now;
+        last.held = None;
+        self.sent.push_back(now);
+        Some(std::mem::take(&mut self.suppressed))
+    }
+
+    /// The held back events the rate limit allows now, with how many were suppressed before each.
+    fn release(&mut self, notifier: &Notifier, now: Instant) -> Vec<(AlertEvent, u32)>{
+        self.expire(notifier, now);
+        let mut out = Vec::new();
+        for last in self.last.values_mut(){
+            if self.sent.len() >= notifier.max_per_min as usize{
+                break;
+            }
+            if let Some(event) = last.held.take(){
+                last.state = Some(event.state);
+                last.at = now;
+                self.sent.push_back(now);
+                out.push((event, std::mem::take(&mut self.suppressed)));
+            }
+        }
+        out
+    }
+
+    /// Forget sends older than the rate window, and alerts with nothing held whose last send is past `dedup_s`.
+    fn expire(&mut self, notifier: &Notifier, now: Instant){
+        while self.sent.front().is_some_and(|at| now.duration_since(*at) >= RATE_WINDOW){
+            self.sent.pop_front();
+        }
+        let dedup = Duration::from_secs(notifier.dedup_s);
+        self.last.retain(|_, last| last.held.is_some() || now.duration_since(last.at) < dedup);
+    }
+}
+
+/// One attempt at handing `payload` to the sink of `notifier`.
+fn send(notifier: &Notifier, payload: &Payload) -> Result<(), Error>{
+    let timeout = Duration::from_millis(notifier.timeout_ms);
+    match &notifier.sink{
+        Sink::Webhook { url } => post(url, &serde_json::to_vec(payload)?, timeout),
+        Sink::Command { argv } => run(argv, payload, timeout),
+        Sink::File { path } => {
+            let mut line = serde_json::to_vec(payload)?;
+            line.push(b'\n');
+            OpenOptions::new().create(true).append(true).open(path)
+                .and_then(|mut file| file.write_all(&line))
+                .with_context(|| format!("Failed to append to {}", path.display()))
+        }
+    }
+}
+
+/// Split an `http://host[:port][/path]` url into the host and the path.
+pub fn parse_url(url: &str) -> Result<(&str, &str), Error>{
+    let Some(rest) = url.strip_prefix("http://") else {
+        bail!("'{}' is not an http:// url", url);
+    };
+    let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    if host.is_empty(){
+        bail!("'{}' has no host", url);
+    }
+    Ok((host, if path.is_empty() { "/" } else { path }))
+}
+
+fn post(url: &str, body: &[u8], timeout: Duration) -> Result<(), Error>{
+    let (host, path) = parse_url(url)?;
+    let has_port = host.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+    let addr = if has_port { host.to_string() } else { format!("{}:80", host) };
+    let addr = addr.to_socket_addrs().with_context(|| format!("Failed to resolve {}", host))?
+        .next().ok_or(anyhow!("{} resolves to no address", host))?;
+    let mut stream = TcpStream::connect_timeout(&addr, timeout).with_context(|| format!("Failed to connect to {}", host))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path, host, body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+
+    let mut response = Vec::new();
+    let mut reader = stream.take(MAX_RESPONSE);
+    // Only the status line matters, it is in the first bytes.
+    while !response.contains(&b'\n'){
+        let mut buf = [0; 512];
+        let n = reader.read(&mut buf).context("No response")?;
+        if n == 0{
+            break;
+        }
+        response.extend_from_slice(&buf[..n]);
+    }
+    let status_line = String::from_utf8_lossy(&response);
+    let status = status_line.split_whitespace().nth(1).and_then(|s| s.parse::<u16>().ok())
+        .ok_or(anyhow!("Not an HTTP response"))?;
+    if !(200..300).contains(&status){
+        bail!("{} answered with HTTP {}", host, status);
+    }
+    Ok(())
+}
+
+fn run(argv: &[String], payload: &Payload, timeout: Duration) -> Result<(), Error>{
+    let event = payload.event;
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .env("AWARE_ALERT_HOST", payload.host)
+        .env("AWARE_ALERT_RULE", &event.rule)
+        .env("AWARE_ALERT_METRIC", &event.metric)
+        .env("AWARE_ALERT_LABEL", &event.label)
+        .env("AWARE_ALERT_STATE", format!("{:?}", event.state))
+        .env("AWARE_ALERT_VALUE", event.value.to_string())
+        .env("AWARE_ALERT_THRESHOLD", event.threshold.to_string())
+        .env("AWARE_ALERT_SINCE_MS", event.since_ms.to_string())
+        .env("AWARE_ALERT_T_MS", event.t_ms.to_string())
+        .env("AWARE_ALERT_SUPPRESSED", payload.suppressed.to_string())
+        .env("AWARE_ALERT_JSON", serde_json::to_string(payload)?)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", argv[0]))?;
+
+    let deadline = Instant::now() + timeout;
+    loop{
+        if let Some(status) = child.try_wait()?{
+            if !status.success(){
+                bail!("{} exited with {}", argv[0], status);
+            }
+            return Ok(());
+        }
+        if Instant::now() >= deadline{
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("{} did not finish within {:?}", argv[0], timeout);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::{io::{BufRead, BufReader}, net::TcpListener, sync::mpsc::Receiver};
+
+    use super::*;
+
+    /// A webhook on 127.0.0.1 answering with `statuses` in turn, sending back each body it got with when.
+    fn stub(statuses: Vec<u16>) -> (String, Receiver<(Instant, serde_json::Value)>){
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move ||{
+            for status in statuses{
+                let Ok((stream, _)) = listener.accept() else { return; };
+                let mut reader = BufReader::new(stream);
+                let mut len = 0;
+                loop{
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n"{
+                        break;
+                    }
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:"){
+                        len = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+                let _ = tx.send((Instant::now(), serde_json::from_slice(&body).unwrap()));
+                write!(reader.get_mut(), "HTTP/1.1 {} Whatever\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    fn webhook(url: String) -> Notifier{
+        Notifier{
+            name: "hook".to_string(), sink: Sink::Webhook { url }, rules: Vec::new(), pending: false,
+            retries: 2, backoff_ms: 200, timeout_ms: 2000, max_per_min: 10, dedup_s: 60
+        }
+    }
+
+    fn event(state: AlertState) -> AlertEvent{
+        AlertEvent{
+            rule: "disk_full".to_string(), metric: "disk.used_ratio".to_string(), label: "/".to_string(),
+            state, value: 0.97, threshold: 0.95, since_ms: 1000, t_ms: 2000
+        }
+    }
+
+    /// Deliver until nothing is queued anymore.
+    fn drain(outbox: &mut Outbox, notifiers: &[Notifier]){
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !outbox.queue.is_empty() && Instant::now() < deadline{
+            outbox.deliver(notifiers, "testhost");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(outbox.queue.is_empty());
+    }
+
+    #[test]
+    fn webhook_retries_and_dedup(){
+        let (url, bodies) = stub(vec![500, 200, 200]);
+        let notifiers = [webhook(url)];
+        let mut outbox = Outbox::default();
+
+        outbox.push(&notifiers, &event(AlertState::Firing));
+        outbox.push(&notifiers, &event(AlertState::Firing)); // Within dedup_s
+        outbox.push(&notifiers, &event(AlertState::Pending)); // Not wanted without `pending`
+        outbox.push(&notifiers, &event(AlertState::Cancelled));
+        assert_eq!(outbox.queue.len(), 1);
+        drain(&mut outbox, &notifiers);
+
+        // The first attempt got a 500 and was retried after the backoff.
+        let (failed_at, failed) = bodies.recv_timeout(Duration::from_secs(1)).unwrap();
+        let (sent_at, sent) = bodies.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(sent_at.duration_since(failed_at) >= Duration::from_millis(200));
+        assert_eq!(failed, sent);
+        assert_eq!(sent["host"], "testhost");
+        assert_eq!(sent["rule"], "disk_full");
+        assert_eq!(sent["label"], "/");
+        assert_eq!(sent["state"], "Firing");
+        assert_eq!(sent["value"], 0.97);
+        assert_eq!(sent["suppressed"], 0);
+
+        // The next event that goes out counts the duplicate that didn't.
+        outbox.push(&notifiers, &event(AlertState::Resolved));
+        drain(&mut outbox, &notifiers);
+        let (_, resolved) = bodies.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(resolved["state"], "Resolved");
+        assert_eq!(resolved["suppressed"], 1);
+        assert!(bodies.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn pending_notifiers_hear_of_cancelled_alerts(){
+        let notifier = Notifier { pending: true, ..webhook("http://127.0.0.1:1/".to_string()) };
+        assert!(wants(&notifier, &event(AlertState::Pending)));
+        assert!(wants(&notifier, &event(AlertState::Cancelled)));
+        assert!(!wants(&webhook("http://127.0.0.1:1/".to_string()), &event(AlertState::Cancelled)));
+    }
+
+    #[test]
+    fn flapping_is_never_deduplicated(){
+        let notifier = webhook("http://127.0.0.1:1/".to_string());
+        let mut limits = Limits::default();
+        let now = Instant::now();
+        assert_eq!(limits.admit(&notifier, &event(AlertState::Firing), now), Some(0));
+        assert_eq!(limits.admit(&notifier, &event(AlertState::Resolved), now), Some(0));
+        assert_eq!(limits.admit(&notifier, &event(AlertState::Firing), now), Some(0));
+        // Only a repeat of what was sent last is.
+        assert_eq!(limits.admit(&notifier, &event(AlertState::Firing), now), None);
+        assert_eq!(limits.admit(&notifier, &event(AlertState::Resolved), now), Some(1));
+    }
+
+    #[test]
+    fn rate_limit_holds_the_latest_state(){
+        let notifier = Notifier { max_per_min: 1, ..webhook("http://127.0.0.1:1/".to_string()) };
+        let mut limits = Limits::default();
+        let now = Instant::now();
+        assert_eq!(limits.admit(&notifier, &event(AlertState::Firing), now), Some(0));
+        assert_eq!(limits.admit(&notifier, &event(AlertState::Resolved), now), None);
+        assert!(limits.release(&notifier, now + Duration::from_secs(30)).is_empty());
+
+        // Once the window is over, the sink learns the alert resolved.
+        let later = now + RATE_WINDOW;
+        let released = limits.release(&notifier, later);
+        assert_eq!(released.iter().map(|(e, n)| (e.state, *n)).collect::<Vec<_>>(), [(AlertState::Resolved, 0)]);
+        assert!(limits.release(&notifier, later + RATE_WINDOW).is_empty());
+
+        // A flap back to the state sent before drops what was held, there is nothing new to tell.
+        let mut limits = Limits::default();
+        limits.admit(&notifier, &event(AlertState::Firing), now);
+        limits.admit(&notifier, &event(AlertState::Resolved), now);
+        assert_eq!(limits.admit(&notifier, &event(AlertState::Firing), now), None);
+        assert!(limits.release(&notifier, later).is_empty());
+        assert_eq!(limits.admit(&notifier, &event(AlertState::Resolved), later), Some(2));
+    }
+}
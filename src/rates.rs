@@ -0,0 +1,52 @@
+use std::{collections::HashMap, hash::Hash};
+
+/// How much a counter grew from `then` to `now`. `None` if it went back, which means it was
+/// reset: the interface or device was replaced, the driver reloaded or the cgroup recreated.
+pub fn increase(now: u64, then: u64) -> Option<u64>{
+    now.checked_sub(then)
+}
+
+/// Turns counters into per second rates, one per series.
+pub struct Rates<K>{
+    last: HashMap<K, (u64, u64)> // Last (t_ms, counter) seen
+}
+
+impl<K> Default for Rates<K>{
+    fn default() -> Self{
+        Self { last: HashMap::new() }
+    }
+}
+
+impl<K: Eq + Hash> Rates<K>{
+    /// The rate of `key`'s counter since it was last seen, `None` the first time and after a reset.
+    pub fn rate(&mut self, key: K, t_ms: u64, total: u64) -> Option<f64>{
+        let (prev_ms, prev_total) = self.last.insert(key, (t_ms, total))?;
+        let increase = increase(total, prev_total)?;
+        (t_ms > prev_ms).then(|| increase as f64 * 1000.0 / (t_ms - prev_ms) as f64)
+    }
+
+    /// Forget counters not seen since `oldest_ms`, e.g. of interfaces that went away.
+    pub fn prune(&mut self, oldest_ms: u64){
+        self.last.retain(|_, (t_ms, _)| *t_ms >= oldest_ms);
+    }
+
+    /// Keep the counters of the series `keep` says are still there.
+    pub fn retain(&mut self, mut keep: impl FnMut(&K) -> bool){
+        self.last.retain(|key, _| keep(key));
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn rate(){
+        let mut rates = Rates::default();
+        assert_eq!(rates.rate("eth0", 1_000, 100), None);
+        assert_eq!(rates.rate("eth0", 3_000, 500), Some(200.0));
+        assert_eq!(rates.rate("eth0", 4_000, 50), None); // Reset
+        assert_eq!(rates.rate("eth0", 5_000, 150), Some(100.0));
+        assert_eq!(rates.rate("eth0", 5_000, 200), None); // No time passed
+    }
+}
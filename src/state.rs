@@ -1,4 +1,7 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use iceoryx2::prelude::ZeroCopySend;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ZeroCopySend)]
+#[repr(C)]
 pub enum AppState{
     Meta,
     Cpu,
@@ -8,4 +11,11 @@ pub enum AppState{
     Sockets,
     Memory,
     ShuttingDown
-}
\ No newline at end of file
+}
+
+impl AppState{
+    pub fn from_raw(raw: u32) -> Option<Self>{
+        [Self::Meta, Self::Cpu, Self::Disk, Self::Processes, Self::Network, Self::Sockets, Self::Memory, Self::ShuttingDown]
+            .into_iter().find(|s| *s as u32 == raw)
+    }
+}
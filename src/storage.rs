@@ -0,0 +1,553 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc::{self, RecvTimeoutError, SyncSender, TrySendError}, Mutex, OnceLock},
+    time::{Duration, Instant}
+};
+
+use anyhow::{anyhow, Context, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{config, Storage}, framing::checksum,
+    history::{now_ms, Key, Sampler},
+    models::{Data, HistoryMetric}, SHUTDOWN
+};
+
+// Where `record` hands values to the writer thread, set once the writer runs.
+static SINK: OnceLock<SyncSender<Entry>> = OnceLock::new();
+static SAMPLER: OnceLock<Mutex<Sampler>> = OnceLock::new();
+
+// Every segment file starts with this.
+const MAGIC: &[u8; 8] = b"AWARESG1";
+const SEGMENT_EXT: &str = "seg";
+// A length field above this means the rest of the segment is garbage.
+const MAX_RECORD: usize = 4096;
+// Batches waiting for the writer before new ones are dropped.
+const QUEUE_LEN: usize = 256;
+// How often finished buckets are written out and the files synced.
+const FLUSH_CYCLE: Duration = Duration::from_secs(1);
+const RETENTION_CYCLE_MS: u64 = 60_000;
+
+/// The tiers of the store. Every tier keeps one record per series and period,
+/// and each is rolled up from the one before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution{
+    Second,
+    Minute,
+    Hour
+}
+
+const TIERS: [Resolution; 3] = [Resolution::Second, Resolution::Minute, Resolution::Hour];
+
+impl Resolution{
+    pub fn period_ms(self) -> u64{
+        match self{
+            Resolution::Second => 1000,
+            Resolution::Minute => 60_000,
+            Resolution::Hour => 3_600_000
+        }
+    }
+
+    pub fn name(self) -> &'static str{
+        match self{
+            Resolution::Second => "1s",
+            Resolution::Minute => "1m",
+            Resolution::Hour => "1h"
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self>{
+        TIERS.into_iter().find(|res| res.name() == name)
+    }
+
+    /// The finest tier that still keeps data that old, by the configured retentions.
+    pub fn for_span(span_ms: u64) -> Self{
+        let s = &config().storage;
+        if span_ms <= s.retention_1s_s * 1000{
+            Resolution::Second
+        }
+        else if span_ms <= s.retention_1m_s * 1000{
+            Resolution::Minute
+        }
+        else{
+            Resolution::Hour
+        }
+    }
+
+    fn retention_ms(self, s: &Storage) -> u64{
+        1000 * match self{
+            Resolution::Second => s.retention_1s_s,
+            Resolution::Minute => s.retention_1m_s,
+            Resolution::Hour => s.retention_1h_s
+        }
+    }
+}
+
+/// One series over one period of a tier.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record{
+    pub t_ms: u64, // Start of the period
+    pub metric: HistoryMetric,
+    pub label: String,
+    pub avg: f64,
+    pub min: f64,
+    pub max: f64,
+    pub count: u32 // Values that went into it
+}
+
+impl Record{
+    fn merge(&mut self, other: &Record){
+        let count = self.count + other.count;
+        self.avg = (self.avg * self.count as f64 + other.avg * other.count as f64) / count.max(1) as f64;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.count = count;
+    }
+}
+
+enum Entry{
+    Values(u64, Vec<(Key, f64)>),
+    Close // Write out what is left, the agent is shutting down
+}
+
+/// Queue the values in `data` for the writer. Never blocks, a full queue drops them.
+/// What is stored are the `HistoryMetric` series the history derives from `data`, not the messages
+/// themselves: a day of process tables alone would be gigabytes, and the reader queries one metric at a time.
+pub fn record(data: &Data){
+    let Some(sink) = SINK.get() else { return; };
+    if matches!(data, Data::ShuttingDown){
+        let _ = sink.send(Entry::Close);
+        return;
+    }
+    let t_ms = now_ms();
+    let values = {
+        let mut sampler = match SAMPLER.get_or_init(Default::default).lock(){
+            Ok(sampler) => sampler,
+            Err(poisoned) => {
+                eprintln!("FATAL: SAMPLER lock was poisoned!, Recovering");
+                poisoned.into_inner()
+            }
+        };
+        sampler.prune(t_ms.saturating_sub(RETENTION_CYCLE_MS));
+        sampler.sample(data, t_ms)
+    };
+    if values.is_empty(){
+        return;
+    }
+    if let Err(TrySendError::Full(_)) = sink.try_send(Entry::Values(t_ms, values)){
+        eprintln!("The storage writer is behind, dropping a sample");
+    }
+}
+
+/// Writes what `record` queues under `storage.dir` until the agent shuts down.
+/// File IO blocks, so this runs on its own blocking thread.
+pub fn main() -> Result<(), Error>{
+    let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+    SINK.set(tx).map_err(|_| anyhow!("The storage writer is already running"))?;
+
+    let dir = config().storage.dir.clone();
+    let mut writer = Writer::open(&dir)?;
+    let mut last_retention = 0;
+    let mut next_flush = Instant::now() + FLUSH_CYCLE;
+    let mut idle = true; // Nothing came in since the last flush
+
+    loop{
+        match rx.recv_timeout(next_flush.saturating_duration_since(Instant::now())){
+            Ok(Entry::Values(t_ms, values)) => {
+                for ((metric, label), value) in values{
+                    writer.add(0, Record { t_ms, metric, label, avg: value, min: value, max: value, count: 1 })?;
+                }
+                idle = false;
+            }
+            Ok(Entry::Close) | Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        if Instant::now() < next_flush{
+            continue;
+        }
+        // Nothing came in for a whole cycle after the shutdown started, so nothing will.
+        if idle && SHUTDOWN.get().is_some_and(|s| *s.borrow()){
+            break;
+        }
+        let now = now_ms();
+        writer.due(now)?;
+        writer.sync()?;
+        if now >= last_retention + RETENTION_CYCLE_MS{
+            writer.enforce_retention(now, &config().storage)?;
+            last_retention = now;
+        }
+        next_flush = Instant::now() + FLUSH_CYCLE;
+        idle = true;
+    }
+    writer.close()
+}
+
+struct Bucket{
+    start: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    count: u32
+}
+
+impl Bucket{
+    fn add(&mut self, rec: &Record){
+        self.sum += rec.avg * rec.count as f64;
+        self.min = self.min.min(rec.min);
+        self.max = self.max.max(rec.max);
+        self.count += rec.count;
+    }
+
+    fn into_record(self, (metric, label): Key) -> Record{
+        Record { t_ms: self.start, metric, label, avg: self.sum / self.count.max(1) as f64, min: self.min, max: self.max, count: self.count }
+    }
+}
+
+struct Tier{
+    res: Resolution,
+    dir: PathBuf,
+    file: Option<BufWriter<File>>, // The segment being appended to, always the newest one
+    size: u64,
+    buckets: HashMap<Key, Bucket>
+}
+
+impl Tier{
+    /// Continue the newest segment, cutting off a record a crash left half written.
+    fn open(root: &Path, res: Resolution) -> Result<Self, Error>{
+        let dir = root.join(res.name());
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let mut tier = Self { res, dir, file: None, size: 0, buckets: HashMap::new() };
+
+        if let Some((_, path)) = segments(&tier.dir)?.pop(){
+            let (_, valid) = read_segment(&path)?;
+            let file = OpenOptions::new().append(true).open(&path)?;
+            if valid < file.metadata()?.len(){
+                eprintln!("Dropping {} unreadable bytes at the end of {}", file.metadata()?.len() - valid, path.display());
+                file.set_len(valid)?;
+            }
+            let mut file = BufWriter::new(file);
+            if valid == 0{
+                file.write_all(MAGIC)?;
+            }
+            tier.size = valid.max(MAGIC.len() as u64);
+            tier.file = Some(file);
+        }
+        Ok(tier)
+    }
+
+    fn write(&mut self, rec: &Record) -> Result<(), Error>{
+        if self.file.is_none() || self.size >= config().storage.segment_bytes{
+            self.rotate(rec.t_ms)?;
+        }
+        let payload = bincode::serialize(rec)?;
+        let Some(file) = self.file.as_mut() else { return Ok(()); };
+        file.write_all(&(payload.len() as u32).to_le_bytes())?;
+        file.write_all(&checksum(&payload).to_le_bytes())?;
+        file.write_all(&payload)?;
+        self.size += 8 + payload.len() as u64;
+        Ok(())
+    }
+
+    /// Start a new segment named after the first record going into it.
+    fn rotate(&mut self, t_ms: u64) -> Result<(), Error>{
+        self.sync()?;
+        let path = self.dir.join(format!("{:020}.{}", t_ms, SEGMENT_EXT));
+        let mut file = BufWriter::new(OpenOptions::new().create(true).append(true).open(&path)?);
+        let len = file.get_ref().metadata()?.len();
+        if len == 0{
+            file.write_all(MAGIC)?;
+        }
+        self.size = len.max(MAGIC.len() as u64);
+        self.file = Some(file);
+        // Make the new name itself survive a crash.
+        File::open(&self.dir)?.sync_all()?;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Error>{
+        if let Some(file) = self.file.as_mut(){
+            file.flush()?;
+            file.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+struct Writer{
+    tiers: Vec<Tier>
+}
+
+impl Writer{
+    fn open(root: &Path) -> Result<Self, Error>{
+        let tiers = TIERS.into_iter().map(|res| Tier::open(root, res)).collect::<Result<_, _>>()?;
+        Ok(Self { tiers })
+    }
+
+    /// Fold `rec` into its period of tier `idx`, writing out the period it replaces.
+    fn add(&mut self, idx: usize, rec: Record) -> Result<(), Error>{
+        let tier = &mut self.tiers[idx];
+        let start = rec.t_ms - rec.t_ms % tier.res.period_ms();
+        let key = (rec.metric, rec.label.clone());
+        match tier.buckets.get_mut(&key){
+            // A late value, e.g. after the clock stepped back, goes into the open period.
+            Some(bucket) if bucket.start >= start => bucket.add(&rec),
+            _ => {
+                let bucket = Bucket { start, sum: rec.avg * rec.count as f64, min: rec.min, max: rec.max, count: rec.count };
+                if let Some(done) = tier.buckets.insert(key.clone(), bucket){
+                    self.emit(idx, done.into_record(key))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, idx: usize, rec: Record) -> Result<(), Error>{
+        self.tiers[idx].write(&rec)?;
+        if idx + 1 < self.tiers.len(){
+            self.add(idx + 1, rec)?;
+        }
+        Ok(())
+    }
+
+    /// Write out the periods that ended before `now`, so series that stopped still get theirs.
+    fn due(&mut self, now: u64) -> Result<(), Error>{
+        for idx in 0..self.tiers.len(){
+            let period = self.tiers[idx].res.period_ms();
+            let done: Vec<Key> = self.tiers[idx].buckets.iter()
+                .filter(|(_, b)| b.start + period <= now)
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in done{
+                if let Some(bucket) = self.tiers[idx].buckets.remove(&key){
+                    self.emit(idx, bucket.into_record(key))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Error>{
+        self.tiers.iter_mut().try_for_each(Tier::sync)
+    }
+
+    /// Delete segments past their tier's retention, then the oldest ones of the finest
+    /// tiers until everything fits in `storage.max_bytes`. The coarse tiers summarize
+    /// what the fine ones lose, so they go last.
+    fn enforce_retention(&mut self, now: u64, limits: &Storage) -> Result<(), Error>{
+        let mut all = Vec::new();
+        for tier in &self.tiers{
+            let segs = segments(&tier.dir)?;
+            let mut kept = Vec::new();
+            // A segment ends where the next one starts; the newest one is still written to.
+            for (idx, (start, path)) in segs.iter().enumerate(){
+                let end = segs.get(idx + 1).map(|(next, _)| *next);
+                match end{
+                    Some(end) if end + tier.res.retention_ms(limits) < now => remove(path)?,
+                    Some(_) => kept.push((*start, path.clone(), fs::metadata(path)?.len())),
+                    None => {}
+                }
+            }
+            all.push(kept);
+        }
+
+        let max = limits.max_bytes;
+        let active: u64 = self.tiers.iter().map(|t| t.size).sum();
+        let mut total = active + all.iter().flatten().map(|(_, _, len)| len).sum::<u64>();
+        for kept in &mut all{
+            while total > max && !kept.is_empty(){
+                let (_, path, len) = kept.remove(0);
+                remove(&path)?;
+                total -= len;
+            }
+        }
+        Ok(())
+    }
+
+    fn close(mut self) -> Result<(), Error>{
+        for idx in 0..self.tiers.len(){
+            let open: Vec<Key> = self.tiers[idx].buckets.keys().cloned().collect();
+            for key in open{
+                if let Some(bucket) = self.tiers[idx].buckets.remove(&key){
+                    self.emit(idx, bucket.into_record(key))?;
+                }
+            }
+        }
+        self.sync()
+    }
+}
+
+fn remove(path: &Path) -> Result<(), Error>{
+    fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))
+}
+
+/// The segments in `dir`, oldest first, with the time of their first record.
+fn segments(dir: &Path) -> Result<Vec<(u64, PathBuf)>, Error>{
+    let mut segs = Vec::new();
+    for entry in fs::read_dir(dir)?{
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == SEGMENT_EXT)
+            && let Some(start) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse().ok()){
+            segs.push((start, path));
+        }
+    }
+    segs.sort();
+    Ok(segs)
+}
+
+/// The records of a segment, and how many bytes of it are intact.
+/// Reading stops at the first record that is cut short or fails its checksum.
+fn read_segment(path: &Path) -> Result<(Vec<Record>, u64), Error>{
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut records = Vec::new();
+    if !bytes.starts_with(MAGIC){
+        return Ok((records, 0));
+    }
+    let mut pos = MAGIC.len();
+    while let Some(head) = bytes.get(pos..pos + 8){
+        let len = u32::from_le_bytes([head[0], head[1], head[2], head[3]]) as usize;
+        let crc = u32::from_le_bytes([head[4], head[5], head[6], head[7]]);
+        let Some(payload) = bytes.get(pos + 8..pos + 8 + len) else { break; };
+        if len > MAX_RECORD || checksum(payload) != crc{
+            break;
+        }
+        let Ok(rec) = bincode::deserialize(payload) else { break; };
+        records.push(rec);
+        pos += 8 + len;
+    }
+    Ok((records, pos as u64))
+}
+
+/// Reads ranges back out of a store, also while an agent is writing to it.
+pub struct Reader{
+    dir: PathBuf
+}
+
+impl Reader{
+    pub fn new(dir: impl Into<PathBuf>) -> Self{
+        Self { dir: dir.into() }
+    }
+
+    /// The records of `metric` in tier `res` whose period starts between `from_ms` and `to_ms`,
+    /// by label and then time. `label` picks a single series.
+    pub fn query(&self, res: Resolution, metric: HistoryMetric, label: Option<&str>, from_ms: u64, to_ms: u64) -> Result<Vec<Record>, Error>{
+        let dir = self.dir.join(res.name());
+        if !dir.exists(){
+            return Err(anyhow!("No {} data in {}", res.name(), self.dir.display()));
+        }
+        let segs = segments(&dir)?;
+        let mut out: Vec<Record> = Vec::new();
+        for (idx, (start, path)) in segs.iter().enumerate(){
+            // Records can be written a period late, so look one period past a segment's end.
+            let end = segs.get(idx + 1).map_or(u64::MAX, |(next, _)| next.saturating_add(res.period_ms()));
+            if *start > to_ms || end < from_ms{
+                continue;
+            }
+            let (records, _) = read_segment(path)?;
+            out.extend(records.into_iter().filter(|r|
+                r.metric == metric && r.t_ms >= from_ms && r.t_ms <= to_ms && label.is_none_or(|l| l == r.label)
+            ));
+        }
+
+        // A period cut short by a restart shows up twice, once from each run.
+        out.sort_by(|a, b| a.label.cmp(&b.label).then(a.t_ms.cmp(&b.t_ms)));
+        let mut merged: Vec<Record> = Vec::with_capacity(out.len());
+        for rec in out{
+            match merged.last_mut(){
+                Some(last) if last.label == rec.label && last.t_ms == rec.t_ms => last.merge(&rec),
+                _ => merged.push(rec)
+            }
+        }
+        Ok(merged)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::{config::Config, testutil::temp_dir};
+
+    // An hour boundary, so every tier's periods line up with it.
+    const T0: u64 = 1_000 * 3_600_000;
+
+    fn value(t_ms: u64, value: f64) -> Record{
+        Record { t_ms, metric: HistoryMetric::CpuUsage, label: "cpu0".to_string(), avg: value, min: value, max: value, count: 1 }
+    }
+
+    fn query(dir: &Path, res: Resolution) -> Vec<Record>{
+        Reader::new(dir).query(res, HistoryMetric::CpuUsage, Some("cpu0"), 0, u64::MAX).unwrap()
+    }
+
+    #[test]
+    fn torn_tail_is_cut_off_on_open(){
+        let dir = temp_dir("storage-torn");
+        let mut writer = Writer::open(&dir).unwrap();
+        for sec in 0..3{
+            writer.add(0, value(T0 + sec * 1000, sec as f64)).unwrap();
+        }
+        writer.close().unwrap();
+
+        let (_, path) = segments(&dir.join("1s")).unwrap().pop().unwrap();
+        let intact = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[40, 0, 0, 0, 1, 2, 3, 4, 5]).unwrap(); // A header and the start of a payload
+        drop(file);
+
+        let mut writer = Writer::open(&dir).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+        writer.add(0, value(T0 + 3000, 3.0)).unwrap();
+        writer.close().unwrap();
+        let values: Vec<f64> = query(&dir, Resolution::Second).iter().map(|r| r.avg).collect();
+        assert_eq!(values, vec![0.0, 1.0, 2.0, 3.0]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rollups(){
+        let dir = temp_dir("storage-rollups");
+        let mut writer = Writer::open(&dir).unwrap();
+        for sec in 0..120{
+            writer.add(0, value(T0 + sec * 1000, sec as f64)).unwrap();
+        }
+        writer.due(T0 + Resolution::Hour.period_ms()).unwrap();
+        writer.sync().unwrap();
+
+        assert_eq!(query(&dir, Resolution::Second).len(), 120);
+        let minutes = query(&dir, Resolution::Minute);
+        assert_eq!(minutes.len(), 2);
+        assert_eq!((minutes[0].t_ms, minutes[0].avg, minutes[0].min, minutes[0].max, minutes[0].count), (T0, 29.5, 0.0, 59.0, 60));
+        assert_eq!((minutes[1].t_ms, minutes[1].avg, minutes[1].min, minutes[1].max, minutes[1].count), (T0 + 60_000, 89.5, 60.0, 119.0, 60));
+        let hours = query(&dir, Resolution::Hour);
+        assert_eq!(hours.len(), 1);
+        assert_eq!((hours[0].t_ms, hours[0].avg, hours[0].min, hours[0].max, hours[0].count), (T0, 59.5, 0.0, 119.0, 120));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retention(){
+        let dir = temp_dir("storage-retention");
+        let mut writer = Writer::open(&dir).unwrap();
+        let limits = Storage { retention_1s_s: 60, ..Config::default().storage };
+        // Three closed segments a minute apart, then the one being written to.
+        let starts = [T0, T0 + 60_000, T0 + 120_000, T0 + 180_000];
+        for start in starts{
+            let tier = &mut writer.tiers[0];
+            tier.rotate(start).unwrap();
+            tier.write(&value(start, 1.0)).unwrap();
+        }
+        writer.sync().unwrap();
+        let starts_left = |dir: &Path| segments(&dir.join("1s")).unwrap().into_iter().map(|(start, _)| start).collect::<Vec<u64>>();
+
+        // The first segment ended at T0 + 60s, more than a minute before now.
+        writer.enforce_retention(T0 + 121_000, &limits).unwrap();
+        assert_eq!(starts_left(&dir), starts[1..]);
+
+        // Over the size limit, the oldest closed segment goes, never the open one.
+        let limits = Storage { max_bytes: 1, ..limits };
+        writer.enforce_retention(T0 + 121_000, &limits).unwrap();
+        assert_eq!(starts_left(&dir), starts[3..]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
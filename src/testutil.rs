@@ -0,0 +1,11 @@
+//! Helpers shared by the unit tests.
+
+use std::{fs, path::PathBuf};
+
+/// An empty directory of its own for each test, `name` tells them apart.
+pub fn temp_dir(name: &str) -> PathBuf{
+    let dir = std::env::temp_dir().join(format!("aware-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
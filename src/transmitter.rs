@@ -1,128 +1,103 @@
 use iceoryx2::{node as ice_node, service::ipc};
 use iceoryx2::port::publisher;
+use iceoryx2::prelude::{PortFactory, SignalHandlingMode};
 use anyhow::{Error, Ok};
-use sysinfo::MINIMUM_CPU_UPDATE_INTERVAL;
 use tokio::sync::{mpsc};
 
-use crate::{models::{Data, TelemetryKind, Telemetry}, MAX_SIZE};
+use crate::{alerts, config::config, delta::Encoder, exporter, notify, history, storage, framing::checksum, models::{Data, TelemetryKind, Telemetry}, MAX_SIZE};
 
-pub async fn main(mut rx: mpsc::Receiver<Data>) -> Result<(), Error>{
+pub fn main(mut rx: mpsc::Receiver<Data>) -> Result<(), Error>{
 
+    let cfg = config();
+    let transport = &cfg.transport;
     let mut binary: Vec<u8> = Vec::with_capacity(MAX_SIZE * 2);
+    let mut ids = FrameIds::default();
 
     let node = ice_node::NodeBuilder::new()
-        .name(&"AwareAgent".try_into()?)
+        .name(&transport.node_name.as_str().try_into()?)
+        .signal_handling_mode(SignalHandlingMode::Disabled) // Signals are handled in main
         .create::<ipc::Service>()?;
 
-    let service = node.service_builder(&"Telemetry".try_into()?)
+    let service = node.service_builder(&transport.service_name.as_str().try_into()?)
         .publish_subscribe::<Telemetry>()
+        .subscriber_max_buffer_size(transport.subscriber_buffer)
         .open_or_create()?;
 
     let writer  = service.publisher_builder().create()?;
 
-    const CYCLES: u32 = 24;
+    let mut alerts = alerts::Engine::default();
+    let mut encoder = Encoder::default();
 
-    let mut status = true;
-
-    while status{
-        let mut processed = 0;
-        while processed < CYCLES{
-            match rx.try_recv(){
-                std::result::Result::Ok(data) => {
-                    if !handle_data(data, &writer, &mut binary)?{
-                        status = false;
-                        break;
-                    }
-                    processed += 1;
-                }
-                Err(_) => break
+    // The iceoryx2 ports are not Send, so this runs on its own blocking thread.
+    while let Some(data) = rx.blocking_recv(){
+        let events = alerts.observe(&data);
+        // Process tables go out as deltas, everything else as is.
+        let wire = match &data{
+            Data::Process(procs) => {
+                let subscribers = service.dynamic_config().number_of_subscribers();
+                Some(Data::ProcessDelta(encoder.encode(procs, config().transport.process_keyframe_every, subscribers)))
             }
+            _ => None
+        };
+        if !handle_data(data, wire, &writer, &mut binary, &mut ids)?{
+            break;
+        }
+        for event in events{
+            handle_data(Data::Alert(event), None, &writer, &mut binary, &mut ids)?;
         }
-        node.wait(MINIMUM_CPU_UPDATE_INTERVAL);
-
-
     }
     Ok(())
 }
 
-fn handle_data(data: Data, writer: &publisher::Publisher<ipc::Service, Telemetry, ()>, binary: &mut Vec<u8>) -> Result<bool, Error>{
-    let mut ret = true;
-    let mut buf = [0u8; MAX_SIZE];
+/// Publish `wire`, or `data` itself if it is None, then hand `data` to everything else that wants it.
+/// A message that fails to go out is dropped, subscribers see it as lost, and everything else still gets it.
+fn handle_data(data: Data, wire: Option<Data>, writer: &publisher::Publisher<ipc::Service, Telemetry, ()>, binary: &mut Vec<u8>, ids: &mut FrameIds) -> Result<bool, Error>{
+    binary.clear();
+    bincode::serialize_into(&mut *binary, wire.as_ref().unwrap_or(&data))?;
+    if let Err(e) = publish(data.kind(), binary, writer, ids){
+        ids.failed += 1;
+        eprintln!("Dropping a {:?} message, {} failed to publish so far: {:#}", data.kind(), ids.failed, e);
+    }
+    // The marker is the last thing subscribers get from us.
+    let more = !matches!(data, Data::ShuttingDown);
+    history::record(&data);
+    storage::record(&data);
+    notify::record(&data);
+    if config().exporter.enabled{
+        exporter::record(data);
+    }
+    Ok(more)
+}
 
-    match data{
-        Data::ShuttingDown =>  {
-            ret = false;
-        }
-        Data::Cpus(vec_cpus) => {
-            bincode::serialize_into(binary as &mut Vec<u8>, &vec_cpus)?;
-            for chunk in binary.chunks(MAX_SIZE) {
-                buf[..chunk.len()].copy_from_slice(chunk);
-                let mut sample = writer.loan()?;
-                sample.data = buf;
-                sample.kind = TelemetryKind::Cpus;
-                sample.send()?;
-            }
-            
-        }
-        Data::Disk(vec_disk) => {
-            bincode::serialize_into(binary as &mut Vec<u8>, &vec_disk)?;
-            for chunk in binary.chunks(MAX_SIZE) {
-                buf[..chunk.len()].copy_from_slice(chunk);
-                let mut sample = writer.loan()?;
-                sample.data = buf;
-                sample.kind = TelemetryKind::Disk;
-                sample.send()?;
-            }
-        }
-        Data::Memory(vec_mem) => {
-            bincode::serialize_into(binary as &mut Vec<u8>, &vec_mem)?;                    for chunk in binary.chunks(MAX_SIZE) {
-                buf[..chunk.len()].copy_from_slice(chunk);
-                let mut sample = writer.loan()?;
-                sample.data = buf;
-                sample.kind = TelemetryKind::Memory;
-                sample.send()?;
-            }
-        }
-        Data::Process(vec_proc) => {
-            bincode::serialize_into(binary as &mut Vec<u8>, &vec_proc)?;
-            for chunk in binary.chunks(MAX_SIZE) {
-                buf[..chunk.len()].copy_from_slice(chunk);
-                let mut sample = writer.loan()?;
-                sample.data = buf;
-                sample.kind = TelemetryKind::Process;
-                sample.send()?;
-            }
-        }
-        Data::Sockets(vec_sock) => {
-            bincode::serialize_into(binary as &mut Vec<u8>, &vec_sock)?;
-            for chunk in binary.chunks(MAX_SIZE) {
-                buf[..chunk.len()].copy_from_slice(chunk);
-                let mut sample = writer.loan()?;
-                sample.data = buf;
-                sample.kind = TelemetryKind::Sockets;
-                sample.send()?;
-            }
-        }
-        Data::Meta(meta) => {
-            bincode::serialize_into(binary as &mut Vec<u8>, &meta)?;
-            for chunk in binary.chunks(MAX_SIZE) {
-                buf.fill(0);
-                buf[..chunk.len()].copy_from_slice(chunk);
-                let mut sample = writer.loan()?;
-                sample.data = buf;
-                sample.kind = TelemetryKind::Meta;
-                sample.send()?;
-            }
-        }
-        Data::Networks(vec_net) => {
-            bincode::serialize_into(binary as &mut Vec<u8>, &vec_net)?;                    for chunk in binary.chunks(MAX_SIZE) {
-                buf[..chunk.len()].copy_from_slice(chunk);
-                let mut sample = writer.loan()?;
-                sample.data = buf;
-                sample.kind = TelemetryKind::Networks;
-                sample.send()?;
-            }
-        }
+/// Counters stamped into every sample so subscribers can put messages back together.
+#[derive(Default)]
+struct FrameIds{
+    msg_id: u64,
+    seq: u64,
+    failed: u64 // Messages dropped because a sample could not be loaned or sent, e.g. while a slow subscriber holds them all
+}
+
+fn publish(kind: TelemetryKind, binary: &[u8], writer: &publisher::Publisher<ipc::Service, Telemetry, ()>, ids: &mut FrameIds) -> Result<(), Error>{
+    let total_len = u32::try_from(binary.len())?;
+    let chunk_size = config().transport.chunk_size;
+    let n_chunks = u32::try_from(binary.len().div_ceil(chunk_size).max(1))?;
+    // Taken up front, so chunks of a message that fails half way are never mixed up with the next one.
+    let msg_id = ids.msg_id;
+    ids.msg_id += 1;
+
+    for (idx, chunk) in binary.chunks(chunk_size).enumerate(){
+        let mut sample = writer.loan()?;
+        sample.kind = kind as u32;
+        sample.msg_id = msg_id;
+        sample.seq = ids.seq;
+        sample.chunk = idx as u32;
+        sample.n_chunks = n_chunks;
+        sample.total_len = total_len;
+        sample.len = chunk.len() as u32;
+        sample.checksum = checksum(chunk);
+        sample.data[..chunk.len()].copy_from_slice(chunk);
+        sample.send()?;
+        ids.seq += 1;
     }
-    Ok(ret)
-}
\ No newline at end of file
+    Ok(())
+}
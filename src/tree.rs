@@ -0,0 +1,182 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use crate::models::Process;
+
+/// A process snapshot arranged by parent, with the totals of every subtree.
+/// Threads are left out, a process whose parent is not in the snapshot is a root.
+#[derive(Default)]
+pub struct ProcessTree{
+    nodes: Vec<Node>,
+    by_pid: HashMap<u32, usize>,
+    roots: Vec<usize>
+}
+
+#[derive(Debug, Clone)]
+pub struct Node{
+    pub idx: usize,            // Of the process in the snapshot
+    pub parent: Option<usize>, // Nodes, not pids
+    pub children: Vec<usize>,  // In snapshot order unless sorted
+    pub depth: usize,          // 0 for a root
+    pub cpu: f32,              // The process and all of its descendants
+    pub mem: u64,
+    pub count: usize
+}
+
+impl ProcessTree{
+    pub fn build(procs: &[Process]) -> Self{
+        let mut tree = Self::default();
+        for (idx, proc) in procs.iter().enumerate().filter(|(_, p)| !p.thread){
+            tree.by_pid.insert(proc.pid, tree.nodes.len());
+            tree.nodes.push(Node { idx, parent: None, children: Vec::new(), depth: 0, cpu: proc.cpu, mem: proc.mem, count: 1 });
+        }
+        for node in 0..tree.nodes.len(){
+            let parent = procs[tree.nodes[node].idx].parent.and_then(|pid| tree.by_pid.get(&pid).copied());
+            match parent{
+                Some(parent) if parent != node => {
+                    tree.nodes[node].parent = Some(parent);
+                    tree.nodes[parent].children.push(node);
+                }
+                _ => tree.roots.push(node)
+            }
+        }
+
+        // Only what hangs off a root is walked, so a parent loop is cut where it is first found.
+        let mut order = tree.walk_from(&tree.roots);
+        let mut seen = vec![false; tree.nodes.len()];
+        for &node in &order{
+            seen[node] = true;
+        }
+        for node in 0..tree.nodes.len(){
+            if seen[node]{
+                continue;
+            }
+            if let Some(parent) = tree.nodes[node].parent.take(){
+                tree.nodes[parent].children.retain(|c| *c != node);
+            }
+            tree.roots.push(node);
+            for below in tree.walk_from(&[node]){
+                seen[below] = true;
+                order.push(below);
+            }
+        }
+        for &node in &order{
+            if let Some(parent) = tree.nodes[node].parent{
+                tree.nodes[node].depth = tree.nodes[parent].depth + 1;
+            }
+        }
+        for &node in order.iter().rev(){
+            if let Some(parent) = tree.nodes[node].parent{
+                let (cpu, mem, count) = (tree.nodes[node].cpu, tree.nodes[node].mem, tree.nodes[node].count);
+                let parent = &mut tree.nodes[parent];
+                parent.cpu += cpu;
+                parent.mem += mem;
+                parent.count += count;
+            }
+        }
+        tree
+    }
+
+    pub fn nodes(&self) -> &[Node]{
+        &self.nodes
+    }
+
+    /// Reorder the children of every node, and the roots, by `cmp`.
+    pub fn sort_siblings(&mut self, mut cmp: impl FnMut(&Node, &Node) -> Ordering){
+        let mut roots = std::mem::take(&mut self.roots);
+        roots.sort_by(|a, b| cmp(&self.nodes[*a], &self.nodes[*b]));
+        self.roots = roots;
+        for node in 0..self.nodes.len(){
+            let mut children = std::mem::take(&mut self.nodes[node].children);
+            children.sort_by(|a, b| cmp(&self.nodes[*a], &self.nodes[*b]));
+            self.nodes[node].children = children;
+        }
+    }
+
+    /// Every node depth first, each after its parent.
+    pub fn walk(&self) -> impl Iterator<Item = &Node>{
+        self.walk_from(&self.roots).into_iter().map(|n| &self.nodes[n])
+    }
+
+    /// The parent of `node`, its parent and so on up to the root.
+    pub fn ancestors<'a>(&'a self, node: &Node) -> impl Iterator<Item = &'a Node>{
+        std::iter::successors(node.parent.map(|p| &self.nodes[p]), |n| n.parent.map(|p| &self.nodes[p]))
+    }
+
+    fn walk_from(&self, from: &[usize]) -> Vec<usize>{
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut stack: Vec<usize> = from.iter().rev().copied().collect();
+        while let Some(node) = stack.pop(){
+            order.push(node);
+            stack.extend(self.nodes[node].children.iter().rev());
+        }
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn process(pid: u32, parent: Option<u32>, cpu: f32, mem: u64) -> Process{
+        Process{
+            pid, name: format!("p{}", pid), exe: String::new(), cpu, mem, status: "Sleep".to_string(), cmd: String::new(),
+            parent, user_id: None, thread: false, detail: None
+        }
+    }
+
+    /// (pid, depth, cpu, mem, count) in walk order.
+    fn walked(tree: &ProcessTree, procs: &[Process]) -> Vec<(u32, usize, f32, u64, usize)>{
+        tree.walk().map(|n| (procs[n.idx].pid, n.depth, n.cpu, n.mem, n.count)).collect()
+    }
+
+    #[test]
+    fn totals_over_three_levels(){
+        let procs = vec![
+            process(1, None, 1.0, 10),
+            process(2, Some(1), 2.0, 20),
+            process(3, Some(2), 4.0, 40),
+            process(4, Some(2), 8.0, 80),
+            process(5, Some(1), 16.0, 160)
+        ];
+        let tree = ProcessTree::build(&procs);
+        assert_eq!(walked(&tree, &procs), [
+            (1, 0, 31.0, 310, 5),
+            (2, 1, 14.0, 140, 3),
+            (3, 2, 4.0, 40, 1),
+            (4, 2, 8.0, 80, 1),
+            (5, 1, 16.0, 160, 1)
+        ]);
+        let leaf = tree.nodes().iter().find(|n| procs[n.idx].pid == 3).unwrap();
+        assert_eq!(tree.ancestors(leaf).map(|n| procs[n.idx].pid).collect::<Vec<_>>(), [2, 1]);
+    }
+
+    #[test]
+    fn orphan_is_a_root(){
+        let procs = vec![process(1, None, 1.0, 1), process(7, Some(99), 2.0, 2), process(8, Some(7), 3.0, 3)];
+        let tree = ProcessTree::build(&procs);
+        assert_eq!(walked(&tree, &procs), [(1, 0, 1.0, 1, 1), (7, 0, 5.0, 5, 2), (8, 1, 3.0, 3, 1)]);
+    }
+
+    #[test]
+    fn cycle_is_cut(){
+        // 2 and 3 are each other's parent, as a snapshot taken while pids were reused can say.
+        let procs = vec![process(1, None, 1.0, 1), process(2, Some(3), 2.0, 2), process(3, Some(2), 4.0, 4), process(4, Some(4), 8.0, 8)];
+        let tree = ProcessTree::build(&procs);
+        let walked = walked(&tree, &procs);
+        assert_eq!(walked.len(), 4); // Every process once
+        assert_eq!(walked[..2], [(1, 0, 1.0, 1, 1), (4, 0, 8.0, 8, 1)]); // 4 is its own parent
+        // The loop becomes a root where it was first found, with the other below it.
+        assert_eq!(walked[2..], [(2, 0, 6.0, 6, 2), (3, 1, 4.0, 4, 1)]);
+        assert!(tree.nodes().iter().all(|n| tree.ancestors(n).count() <= 1));
+    }
+
+    #[test]
+    fn threads_left_out(){
+        let mut thread = process(11, Some(10), 50.0, 500);
+        thread.thread = true;
+        let procs = vec![process(10, None, 1.0, 100), thread, process(12, Some(10), 2.0, 200)];
+        let tree = ProcessTree::build(&procs);
+        assert_eq!(tree.nodes().len(), 2);
+        assert_eq!(walked(&tree, &procs), [(10, 0, 3.0, 300, 2), (12, 1, 2.0, 200, 1)]);
+    }
+}